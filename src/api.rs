@@ -0,0 +1,313 @@
+//! A small JSON-over-HTTP API for querying the chain and submitting
+//! transactions, built directly on `std::net`.
+//!
+//! Routes:
+//! - `GET /height` — `{"height": <u32>}`
+//! - `GET /block/{height}` — the block as JSON
+//! - `GET /block/hash/{hash}` — the block as JSON
+//! - `POST /tx` — a JSON transaction to add to the mempool; replies with
+//!   `{"hash": "<hex>"}`
+//!
+//! Errors are replied as `{"error": "<message>"}` with a 4xx or 5xx status.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::core::{block::Block, blockchain::Blockchain, hasher::{Hasher, TxHasher}, mempool::Mempool, storage::Storage, transaction::Transaction};
+use crate::types::hash::Hash;
+
+/// Largest request body accepted, in bytes.
+pub const MAX_BODY_BYTES: usize = 1 << 20;
+
+/// Longest request or header line accepted, in bytes.
+pub const MAX_LINE_BYTES: usize = 8 * 1024;
+
+/// Most header lines accepted in a request.
+pub const MAX_HEADERS: usize = 100;
+
+/// Longest a connection may wait on a read or write. Requests are served
+/// one at a time, so this bounds how long an idle or stalled client holds
+/// up the others.
+pub const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn json<T: serde::Serialize>(value: &T) -> Response {
+        match serde_json::to_string(value) {
+            Ok(body) => Response { status: 200, body },
+            Err(e) => Response::error(500, &e.to_string()),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response { status, body: serde_json::json!({ "error": message }).to_string() }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// Routes `request` to its handler.
+pub fn handle<S: Storage>(chain: &Blockchain<S>, mempool: &Mutex<Mempool>, request: &Request) -> Response {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["height"]) => Response::json(&serde_json::json!({ "height": chain.height() })),
+        ("GET", ["block", "hash", hash]) => match hash.parse::<Hash>() {
+            Ok(hash) => block_response(chain.get_block_by_hash(&hash)),
+            Err(e) => Response::error(400, &e.to_string()),
+        },
+        ("GET", ["block", height]) => match height.parse::<u32>() {
+            Ok(height) => block_response(chain.get_block(height)),
+            Err(e) => Response::error(400, &e.to_string()),
+        },
+        ("POST", ["tx"]) => submit_transaction(mempool, &request.body),
+        (_, ["height"] | ["block", ..] | ["tx"]) => Response::error(405, "method not allowed"),
+        _ => Response::error(404, "not found"),
+    }
+}
+
+fn block_response<E: ToString>(block: Result<Option<Block>, E>) -> Response {
+    match block {
+        Ok(Some(block)) => Response::json(&block),
+        Ok(None) => Response::error(404, "block not found"),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+fn submit_transaction(mempool: &Mutex<Mempool>, body: &[u8]) -> Response {
+    let tx: Transaction = match serde_json::from_slice(body) {
+        Ok(tx) => tx,
+        Err(e) => return Response::error(400, &e.to_string()),
+    };
+
+    let hash = match TxHasher::new().hash(&tx) {
+        Ok(hash) => hash,
+        Err(e) => return Response::error(500, &e),
+    };
+    match mempool.lock().unwrap().add(tx) {
+        Ok(()) => Response::json(&serde_json::json!({ "hash": hash })),
+        Err(e) => Response::error(400, &e.to_string()),
+    }
+}
+
+/// Reads an HTTP/1.1 request, using `Content-Length` for the body. Lines
+/// over `MAX_LINE_BYTES` and more than `MAX_HEADERS` headers are rejected,
+/// so a client cannot make it buffer without end.
+pub fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+
+    let mut line = String::new();
+    read_line(reader, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_owned(), path.to_owned()),
+        _ => return Err(invalid("malformed request line")),
+    };
+
+    let mut content_length = 0;
+    let mut headers = 0;
+    loop {
+        line.clear();
+        if read_line(reader, &mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "headers are truncated"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Err(invalid("too many headers"));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().map_err(|_| invalid("invalid content-length"))?;
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_BYTES {
+        return Err(invalid("request body is too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request { method, path, body })
+}
+
+/// `read_line` reading at most `MAX_LINE_BYTES`, failing on a longer line.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    let read = io::Read::take(&mut *reader, MAX_LINE_BYTES as u64 + 1).read_line(line)?;
+    if read > MAX_LINE_BYTES {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "request line is too long"));
+    }
+    Ok(read)
+}
+
+pub fn write_response<W: Write>(writer: &mut W, response: &Response) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.reason(),
+        response.body.len(),
+        response.body,
+    )?;
+    writer.flush()
+}
+
+/// Answers one request on `stream` and closes it, giving up on a client
+/// that stalls for `IO_TIMEOUT`.
+pub fn serve_connection<S: Storage>(stream: TcpStream, chain: &Blockchain<S>, mempool: &Mutex<Mempool>) -> io::Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader) {
+        Ok(request) => handle(chain, mempool, &request),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Response::error(400, &e.to_string()),
+        Err(e) => return Err(e),
+    };
+    write_response(&mut &stream, &response)
+}
+
+/// Serves requests from `listener` one at a time until accepting fails.
+pub fn serve<S: Storage>(listener: TcpListener, chain: &Blockchain<S>, mempool: &Mutex<Mempool>) -> io::Result<()> {
+    for stream in listener.incoming() {
+        if let Err(e) = serve_connection(stream?, chain, mempool) {
+            log::warn!("HTTP connection failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    use crate::core::{block::Block, blockchain::test::chain_of, hasher::{BlockHasher, Hasher}, mempool::Mempool, transaction::Transaction};
+    use crate::crypto::keypair::PrivateKey;
+
+    use super::{handle, read_request, serve_connection, write_response, Request, Response, IO_TIMEOUT, MAX_HEADERS, MAX_LINE_BYTES};
+
+    fn get(path: &str) -> Request {
+        Request { method: "GET".to_owned(), path: path.to_owned(), body: vec![] }
+    }
+
+    #[test]
+    fn test_get_height() {
+        let bc = chain_of(3);
+        let mempool = Mutex::new(Mempool::new());
+
+        let res = handle(&bc, &mempool, &get("/height"));
+        assert_eq!(res, Response { status: 200, body: r#"{"height":3}"#.to_owned() });
+    }
+
+    #[test]
+    fn test_get_block() {
+        let bc = chain_of(3);
+        let mempool = Mutex::new(Mempool::new());
+        let block = bc.get_block(2).unwrap().unwrap();
+
+        let res = handle(&bc, &mempool, &get("/block/2"));
+        assert_eq!(res.status, 200);
+        assert_eq!(serde_json::from_str::<Block>(&res.body).unwrap(), block);
+
+        let res = handle(&bc, &mempool, &get(&format!("/block/hash/{}", BlockHasher::new().hash(&block.header).unwrap())));
+        assert_eq!(res.status, 200);
+        assert_eq!(serde_json::from_str::<Block>(&res.body).unwrap(), block);
+
+        assert_eq!(handle(&bc, &mempool, &get("/block/4")).status, 404);
+        assert_eq!(handle(&bc, &mempool, &get("/block/two")).status, 400);
+        assert_eq!(handle(&bc, &mempool, &get("/nothing")).status, 404);
+    }
+
+    #[test]
+    fn test_post_tx() {
+        let bc = chain_of(0);
+        let mempool = Mutex::new(Mempool::new());
+
+        let mut tx = Transaction::new(b"foo".to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+        let post = Request { method: "POST".to_owned(), path: "/tx".to_owned(), body: serde_json::to_vec(&tx).unwrap() };
+
+        assert_eq!(handle(&bc, &mempool, &post).status, 200);
+        assert_eq!(mempool.lock().unwrap().pending(), vec![tx]);
+        assert_eq!(handle(&bc, &mempool, &post).status, 400);
+
+        let garbage = Request { body: b"{".to_vec(), ..post };
+        assert_eq!(handle(&bc, &mempool, &garbage).status, 400);
+    }
+
+    #[test]
+    fn test_http_round_trip() {
+        let bc = chain_of(1);
+        let mempool = Mutex::new(Mempool::new());
+
+        let raw = b"GET /height HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let request = read_request(&mut &raw[..]).unwrap();
+        assert_eq!(request, get("/height"));
+
+        let mut out = vec![];
+        assert!(write_response(&mut out, &handle(&bc, &mempool, &request)).is_ok());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.ends_with("\r\n\r\n{\"height\":1}"));
+
+        let raw = b"POST /tx HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc";
+        assert_eq!(read_request(&mut &raw[..]).unwrap().body, b"abc");
+    }
+
+    #[test]
+    fn test_read_request_limits() {
+        let long_path = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_BYTES));
+        assert_eq!(read_request(&mut long_path.as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let long_header = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(MAX_LINE_BYTES));
+        assert_eq!(read_request(&mut long_header.as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let headers = |n| format!("GET / HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(n));
+        assert!(read_request(&mut headers(MAX_HEADERS).as_bytes()).is_ok());
+        assert_eq!(read_request(&mut headers(MAX_HEADERS + 1).as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_serve_connection_times_out_stalled_client() {
+        let bc = chain_of(0);
+        let mempool = Mutex::new(Mempool::new());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        // A client that stops mid-header.
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"GET /height HTTP/1.1\r\nHost: local").unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        let start = Instant::now();
+        let err = serve_connection(stream, &bc, &mempool).unwrap_err();
+        assert!(matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut), "{:?}", err);
+        assert!(start.elapsed() >= IO_TIMEOUT && start.elapsed() < IO_TIMEOUT * 2);
+    }
+}
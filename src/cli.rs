@@ -0,0 +1,154 @@
+//! Command-line tools for exercising the crate without running a node.
+//!
+//! - `genkey <file>` writes a new private key to `file` as hex
+//! - `mkblock --key <file> --prev <hash> --height <n> --out <file>` creates
+//!   an empty block on `prev`, signs it and writes it encoded to `out`
+//! - `inspect <file>` decodes a block written by `mkblock` and prints it
+
+use std::{fs, io::Write, path::PathBuf};
+
+use chrono::Utc;
+
+use crate::core::{block::{Block, Header, HEADER_VERSION}, encoding::{Encode, Decode, BlockEncoder, BlockDecoder}, hasher::BlockHasher};
+use crate::crypto::keypair::PrivateKey;
+use crate::types::hash::Hash;
+
+pub const USAGE: &str = "usage:
+    blockchain genkey <file>
+    blockchain mkblock --key <file> --prev <hash> --height <n> --out <file>
+    blockchain inspect <file>";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    GenKey { out: PathBuf },
+    MkBlock { key: PathBuf, prev: Hash, height: u32, out: PathBuf },
+    Inspect { file: PathBuf },
+}
+
+/// Parses the arguments after the program name.
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    match args {
+        [cmd, out] if cmd == "genkey" => Ok(Command::GenKey { out: out.into() }),
+        [cmd, file] if cmd == "inspect" => Ok(Command::Inspect { file: file.into() }),
+        [cmd, flags @ ..] if cmd == "mkblock" => {
+            let (mut key, mut prev, mut height, mut out) = (None, None, None, None);
+            for pair in flags.chunks(2) {
+                let value = match pair {
+                    [_, value] => value,
+                    [flag] => return Err(format!("{} needs a value", flag)),
+                    _ => unreachable!(),
+                };
+                match pair[0].as_str() {
+                    "--key" => key = Some(value.into()),
+                    "--prev" => prev = Some(value.parse::<Hash>().map_err(|e| format!("invalid --prev: {}", e))?),
+                    "--height" => height = Some(value.parse::<u32>().map_err(|e| format!("invalid --height: {}", e))?),
+                    "--out" => out = Some(value.into()),
+                    flag => return Err(format!("unknown flag {}", flag)),
+                }
+            }
+            Ok(Command::MkBlock {
+                key: key.ok_or("missing --key")?,
+                prev: prev.ok_or("missing --prev")?,
+                height: height.ok_or("missing --height")?,
+                out: out.ok_or("missing --out")?,
+            })
+        }
+        _ => Err("unknown command".to_owned()),
+    }
+}
+
+/// Runs `cmd`, printing its results to `stdout`.
+pub fn run<W: Write>(cmd: Command, stdout: &mut W) -> Result<(), String> {
+    match cmd {
+        Command::GenKey { out } => {
+            let key = PrivateKey::generate_key();
+            fs::write(&out, hex::encode(key.to_bytes()) + "\n").map_err(|e| format!("could not write {}: {}", out.display(), e))?;
+
+            let public = key.generate_public();
+            writeln!(stdout, "public key: {}", hex::encode(public.to_bytes())).map_err(|e| e.to_string())?;
+            writeln!(stdout, "address:    {}", public.address()).map_err(|e| e.to_string())
+        }
+        Command::MkBlock { key, prev, height, out } => {
+            let key = read_key(&key)?;
+            let header = Header {
+                version: HEADER_VERSION,
+                data: Hash::zero(),
+                prev_block: prev,
+                timestamp: Utc::now().timestamp(),
+                height,
+                nonce: 0,
+                difficulty: 0,
+                state_root: Hash::zero(),
+            };
+            let mut block = Block::new(header, vec![]);
+            block.sign(key)?;
+            let hash = block.hash(Box::new(BlockHasher::new()));
+
+            let mut bytes = vec![];
+            block.encode_binary(&mut bytes, BlockEncoder::new()).map_err(|e| e.to_string())?;
+            fs::write(&out, bytes).map_err(|e| format!("could not write {}: {}", out.display(), e))?;
+            writeln!(stdout, "hash: {}", hash).map_err(|e| e.to_string())
+        }
+        Command::Inspect { file } => {
+            let bytes = fs::read(&file).map_err(|e| format!("could not read {}: {}", file.display(), e))?;
+            let mut block = *Block::decode_binary(&mut bytes.as_slice(), BlockDecoder::new())
+                .map_err(|e| format!("could not decode {}: {}", file.display(), e))?;
+            inspect(&mut block, stdout).map_err(|e| e.to_string())
+        }
+    }
+}
+
+fn read_key(path: &PathBuf) -> Result<PrivateKey, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let bytes = hex::decode(text.trim()).map_err(|e| format!("invalid key in {}: {}", path.display(), e))?;
+    PrivateKey::from_bytes(&bytes).map_err(|e| format!("invalid key in {}: {}", path.display(), e))
+}
+
+fn inspect<W: Write>(block: &mut Block, out: &mut W) -> std::io::Result<()> {
+    let header = block.header;
+    writeln!(out, "hash:         {}", block.hash(Box::new(BlockHasher::new())))?;
+    writeln!(out, "version:      {}", header.version)?;
+    writeln!(out, "height:       {}", header.height)?;
+    writeln!(out, "prev_block:   {}", header.prev_block)?;
+    writeln!(out, "data:         {}", header.data)?;
+    writeln!(out, "timestamp:    {}", header.timestamp)?;
+    writeln!(out, "nonce:        {}", header.nonce)?;
+    writeln!(out, "difficulty:   {}", header.difficulty)?;
+    writeln!(out, "state_root:   {}", header.state_root)?;
+    writeln!(out, "transactions: {}", block.transactions.len())?;
+    match block.validator {
+        Some(validator) => writeln!(out, "validator:    {}", validator.address())?,
+        None => writeln!(out, "validator:    none")?,
+    }
+    match block.verify_full() {
+        Ok(()) => writeln!(out, "signature:    valid"),
+        Err(e) => writeln!(out, "signature:    invalid ({})", e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::types::hash::Hash;
+
+    use super::{parse, Command};
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(&args("genkey k")), Ok(Command::GenKey { out: "k".into() }));
+        assert_eq!(parse(&args("inspect b")), Ok(Command::Inspect { file: "b".into() }));
+
+        let prev = Hash::random();
+        let mkblock = format!("mkblock --height 3 --prev {} --key k --out b", prev);
+        assert_eq!(parse(&args(&mkblock)), Ok(Command::MkBlock { key: "k".into(), prev, height: 3, out: "b".into() }));
+
+        assert!(parse(&args("mkblock --height 3 --key k --out b")).is_err());
+        assert!(parse(&args("mkblock --height three")).is_err());
+        assert!(parse(&args("mkblock --height")).is_err());
+        assert!(parse(&args("frobnicate")).is_err());
+        assert!(parse(&[]).is_err());
+    }
+}
@@ -0,0 +1,5 @@
+pub mod validator_set;
+pub mod difficulty;
+pub mod producer;
+
+pub use difficulty::{retarget, work};
\ No newline at end of file
@@ -0,0 +1,101 @@
+use crate::core::block::Header;
+
+/// Most the difficulty moves in one retarget, in bits: a factor of four in
+/// expected work either way, as in Bitcoin.
+pub const MAX_RETARGET_BITS: i64 = 2;
+
+/// The difficulty, in leading zero bits, for the block after `headers`.
+///
+/// Compares how long the last `window` headers took against
+/// `target_block_time_secs` per block and moves the last header's
+/// difficulty by the base-2 log of the ratio, rounded and clamped to
+/// `MAX_RETARGET_BITS`. With fewer than two headers to measure, the last
+/// difficulty is kept.
+pub fn retarget(headers: &[Header], target_block_time_secs: i64, window: usize) -> u32 {
+    let current = headers.last().map(|h| h.difficulty).unwrap_or(0);
+    let measured = &headers[headers.len() - window.min(headers.len())..];
+    if measured.len() < 2 {
+        return current;
+    }
+
+    let expected = target_block_time_secs.max(1) * (measured.len() as i64 - 1);
+    let actual = (measured[measured.len() - 1].timestamp - measured[0].timestamp).max(1);
+    let delta = (expected as f64 / actual as f64)
+        .log2()
+        .round()
+        .clamp(-MAX_RETARGET_BITS as f64, MAX_RETARGET_BITS as f64) as i64;
+    (current as i64 + delta).clamp(0, 256) as u32
+}
+
+/// Expected hashes to mine a block at `difficulty`, i.e. `2^difficulty`,
+/// saturating at `u128::MAX` from 128 bits up.
+pub fn work(difficulty: u32) -> u128 {
+    1u128.checked_shl(difficulty).unwrap_or(u128::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::core::block::{Block, Header};
+
+    use super::{retarget, work};
+
+    /// `len` headers at `difficulty`, `interval` seconds apart.
+    fn headers(len: u32, interval: i64, difficulty: u32) -> Vec<Header> {
+        (0..len)
+            .map(|h| {
+                let mut header = Block::random_block(h).header;
+                header.timestamp = 1_000_000 + h as i64 * interval;
+                header.difficulty = difficulty;
+                header
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_retarget_steady() {
+        assert_eq!(retarget(&headers(10, 60, 12), 60, 10), 12);
+        assert_eq!(retarget(&headers(10, 70, 12), 60, 10), 12);
+    }
+
+    #[test]
+    fn test_retarget_too_fast() {
+        assert_eq!(retarget(&headers(10, 30, 12), 60, 10), 13);
+        assert_eq!(retarget(&headers(10, 15, 12), 60, 10), 14);
+    }
+
+    #[test]
+    fn test_retarget_too_slow() {
+        assert_eq!(retarget(&headers(10, 120, 12), 60, 10), 11);
+        assert_eq!(retarget(&headers(10, 240, 12), 60, 10), 10);
+    }
+
+    #[test]
+    fn test_retarget_clamped() {
+        assert_eq!(retarget(&headers(10, 1, 12), 60, 10), 14);
+        assert_eq!(retarget(&headers(10, 0, 12), 60, 10), 14);
+        assert_eq!(retarget(&headers(10, 6000, 12), 60, 10), 10);
+        assert_eq!(retarget(&headers(10, 6000, 1), 60, 10), 0);
+        assert_eq!(retarget(&headers(10, 1, 255), 60, 10), 256);
+    }
+
+    #[test]
+    fn test_retarget_uses_last_window() {
+        let mut hs = headers(5, 6000, 12);
+        hs.extend(headers(5, 60, 12).into_iter().map(|mut h| {
+            h.timestamp += 100_000;
+            h
+        }));
+        assert_eq!(retarget(&hs, 60, 5), 12);
+        assert_eq!(retarget(&hs[..1], 60, 5), 12);
+        assert_eq!(retarget(&[], 60, 5), 0);
+    }
+
+    #[test]
+    fn test_work() {
+        assert_eq!(work(0), 1);
+        assert_eq!(work(12), 4096);
+        assert_eq!(work(127), 1 << 127);
+        assert_eq!(work(128), u128::MAX);
+        assert_eq!(work(256), u128::MAX);
+    }
+}
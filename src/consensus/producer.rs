@@ -0,0 +1,267 @@
+use std::{sync::mpsc::{Receiver, RecvTimeoutError}, time::Duration};
+
+use chrono::Utc;
+use log::{info, warn};
+
+use crate::{core::{block::{Block, Header, HEADER_VERSION}, blockchain::{ChainError, SharedChain}, hasher::{Hasher, TxHasher}, mempool::Mempool, storage::{MemoryStorage, Storage}}, crypto::keypair::PrivateKey, types::hash::Hash};
+
+/// Most transactions the producer puts in a block unless configured
+/// otherwise.
+pub const DEFAULT_MAX_BLOCK_TXS: usize = 1000;
+
+/// Where the producer gets block timestamps from, so tests can fix them.
+pub trait Clock: Send {
+    /// Unix seconds.
+    fn now(&self) -> i64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        Utc::now().timestamp()
+    }
+}
+
+/// Periodically turns pending transactions into a block, when this node's
+/// key is the leader for the next height or the chain has no validator set,
+/// and the chain's `expected_next_timestamp` has been reached.
+///
+/// Each block takes up to `max_txs` transactions from
+/// `Mempool::select_for_block` and is put together with `Block::assemble`,
+/// whose canonical order still applies every sender's nonces in turn. It is
+/// mined to the chain's expected difficulty, committed to the new account
+/// state if the chain tracks one, signed and added to the chain. Its
+/// transactions then leave the mempool and the block is handed to
+/// `on_block`, e.g. to broadcast it.
+pub struct Producer<S: Storage = MemoryStorage, C: Clock = SystemClock> {
+    chain: SharedChain<S>,
+    mempool: Mempool,
+    key: PrivateKey,
+    clock: C,
+    max_txs: usize,
+    on_block: Box<dyn FnMut(&Block) + Send>,
+}
+
+impl<S: Storage> Producer<S, SystemClock> {
+    pub fn new(chain: SharedChain<S>, mempool: Mempool, key: PrivateKey) -> Self {
+        Producer::with_clock(chain, mempool, key, SystemClock)
+    }
+}
+
+impl<S: Storage, C: Clock> Producer<S, C> {
+    pub fn with_clock(chain: SharedChain<S>, mempool: Mempool, key: PrivateKey, clock: C) -> Self {
+        Producer {
+            chain,
+            mempool,
+            key,
+            clock,
+            max_txs: DEFAULT_MAX_BLOCK_TXS,
+            on_block: Box::new(|_| {}),
+        }
+    }
+
+    pub fn set_max_txs(&mut self, max_txs: usize) {
+        self.max_txs = max_txs;
+    }
+
+    /// Called with every block produced, after it has been added to the
+    /// chain.
+    pub fn on_block(&mut self, f: impl FnMut(&Block) + Send + 'static) {
+        self.on_block = Box::new(f);
+    }
+
+    /// Ticks every `interval` until `shutdown` receives or its sender is
+    /// dropped. A failed tick is logged and the loop carries on.
+    ///
+    /// This blocks the calling thread; run it on one of its own.
+    pub fn run(&mut self, interval: Duration, shutdown: &Receiver<()>) {
+        while let Err(RecvTimeoutError::Timeout) = shutdown.recv_timeout(interval) {
+            if let Err(e) = self.tick() {
+                warn!("could not produce block: {}", e);
+            }
+        }
+        info!("Producer shutdown");
+    }
+
+    /// Produces one block if this node may and there are transactions to
+    /// include, returning it.
+    pub fn tick(&mut self) -> Result<Option<Block>, ChainError> {
+        let (height, tip, due, leader, difficulty, state) = self.chain.read(|bc| {
+            let height = bc.height() + 1;
+            let tip = bc.get_header(bc.height()).expect("the tip has a header");
+            let due = bc.expected_next_timestamp();
+            (height, tip, due, bc.leader_for_height(height), bc.expected_difficulty(height), bc.state())
+        });
+        if leader.is_some_and(|leader| leader != self.key.generate_public()) || self.clock.now() < due {
+            return Ok(None);
+        }
+
+        // Without a tracked state there is no telling which nonces apply, so
+        // each sender's lowest pending one is taken as its next.
+        let transactions = self.mempool.select_for_block(self.max_txs, state.as_ref());
+        if transactions.is_empty() {
+            return Ok(None);
+        }
+
+        let header = Header {
+            version: HEADER_VERSION,
+            data: Hash::zero(),
+            prev_block: tip.hash(),
+            timestamp: self.clock.now().max(tip.timestamp + 1),
+            height,
+            nonce: 0,
+            difficulty: 0,
+            state_root: Hash::zero(),
+        };
+        let mut block = Block::assemble(header, transactions);
+        if let Some(mut state) = state {
+            state.apply_block(&block).map_err(|e| ChainError::InvalidState(height, e.to_string()))?;
+            block.header.state_root = state.root();
+        }
+        block.mine(difficulty);
+        block.sign(self.key.clone()).map_err(|_| ChainError::InvalidSignature(height))?;
+
+        self.chain.add_block(block.clone())?;
+        let hasher = TxHasher::new();
+        let included: Vec<Hash> = block.transactions.iter().map(|tx| hasher.hash(tx).expect("could not hash")).collect();
+        self.mempool.remove(&included);
+        info!("Produced block - height: {}, transactions: {}", height, included.len());
+
+        (self.on_block)(&block);
+        Ok(Some(block))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{mpsc, Arc, Mutex}, thread, time::Duration};
+
+    use crate::{consensus::validator_set::ValidatorSet, core::{block::Block, blockchain::{Blockchain, SharedChain}, mempool::Mempool, storage::MemoryStorage, transaction::Transaction, transfer::Transfer}, crypto::keypair::PrivateKey};
+
+    use super::{Clock, Producer};
+
+    struct FixedClock(i64);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> i64 {
+            self.0
+        }
+    }
+
+    fn chain() -> SharedChain {
+        SharedChain::new(Blockchain::new(Block::genesis(), MemoryStorage::new()).unwrap())
+    }
+
+    fn signed_tx(data: &[u8]) -> Transaction {
+        let mut tx = Transaction::new(data.to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+        tx
+    }
+
+    #[test]
+    fn test_tick_produces_block() {
+        let chain = chain();
+        let mut mempool = Mempool::new();
+        let key = PrivateKey::generate_key();
+        let now = Block::genesis().header.timestamp + 60;
+        let mut producer = Producer::with_clock(chain.clone(), mempool.clone(), key.clone(), FixedClock(now));
+
+        let produced = Arc::new(Mutex::new(vec![]));
+        let sink = produced.clone();
+        producer.on_block(move |b| sink.lock().unwrap().push(b.clone()));
+
+        assert_eq!(producer.tick(), Ok(None));
+        assert!(mempool.add(signed_tx(b"foo")).is_ok());
+        assert!(mempool.add(signed_tx(b"bar")).is_ok());
+
+        let block = producer.tick().unwrap().unwrap();
+        assert_eq!(chain.read_height(), 1);
+        assert_eq!(block.header.height, 1);
+        assert_eq!(block.header.timestamp, now);
+        assert_eq!(block.transactions.len(), 2);
+        assert_eq!(block.validator, Some(key.generate_public()));
+        assert_eq!(mempool.len(), 0);
+        assert_eq!(*produced.lock().unwrap(), vec![block]);
+
+        assert_eq!(producer.tick(), Ok(None));
+        assert_eq!(chain.read_height(), 1);
+    }
+
+    #[test]
+    fn test_tick_takes_at_most_max_txs() {
+        let chain = chain();
+        let mut mempool = Mempool::new();
+        for data in [b"foo", b"bar", b"baz"] {
+            assert!(mempool.add(signed_tx(data)).is_ok());
+        }
+
+        let now = Block::genesis().header.timestamp + 60;
+        let mut producer = Producer::with_clock(chain, mempool.clone(), PrivateKey::generate_key(), FixedClock(now));
+        producer.set_max_txs(2);
+        assert_eq!(producer.tick().unwrap().unwrap().transactions.len(), 2);
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn test_tick_without_state_takes_any_nonce() {
+        let chain = chain();
+        let mut mempool = Mempool::new();
+        let key = PrivateKey::generate_key();
+        let to = PrivateKey::generate_key().generate_public().address();
+        let mut tx = Transaction::transfer(Transfer { from: key.generate_public().address(), to, amount: 1, nonce: 3, fee: 0 });
+        assert!(tx.sign(key).is_ok());
+        assert!(mempool.add(tx.clone()).is_ok());
+
+        let now = Block::genesis().header.timestamp + 60;
+        let mut producer = Producer::with_clock(chain, mempool, PrivateKey::generate_key(), FixedClock(now));
+        assert_eq!(producer.tick().unwrap().unwrap().transactions, vec![tx]);
+    }
+
+    #[test]
+    fn test_tick_waits_for_block_time() {
+        let chain = chain();
+        chain.write(|bc| bc.set_block_time_secs(30));
+        let mut mempool = Mempool::new();
+        assert!(mempool.add(signed_tx(b"foo")).is_ok());
+
+        let due = Block::genesis().header.timestamp + 30;
+        let mut early = Producer::with_clock(chain.clone(), mempool.clone(), PrivateKey::generate_key(), FixedClock(due - 1));
+        assert_eq!(early.tick(), Ok(None));
+        assert_eq!(mempool.len(), 1);
+
+        let mut producer = Producer::with_clock(chain.clone(), mempool.clone(), PrivateKey::generate_key(), FixedClock(due));
+        assert_eq!(producer.tick().unwrap().unwrap().header.timestamp, due);
+        assert_eq!(chain.read_height(), 1);
+    }
+
+    #[test]
+    fn test_tick_waits_for_leadership() {
+        let chain = chain();
+        let leader = PrivateKey::generate_key();
+        let mut set = ValidatorSet::new();
+        set.add(leader.generate_public());
+        chain.write(|bc| bc.set_validator_set(set));
+        let mut mempool = Mempool::new();
+        assert!(mempool.add(signed_tx(b"foo")).is_ok());
+
+        let mut follower = Producer::new(chain.clone(), mempool.clone(), PrivateKey::generate_key());
+        assert_eq!(follower.tick(), Ok(None));
+        assert_eq!(mempool.len(), 1);
+
+        let mut producer = Producer::new(chain.clone(), mempool.clone(), leader);
+        assert!(producer.tick().unwrap().is_some());
+        assert_eq!(chain.read_height(), 1);
+    }
+
+    #[test]
+    fn test_run_stops_on_shutdown() {
+        let mut producer = Producer::new(chain(), Mempool::new(), PrivateKey::generate_key());
+        let (shutdown, stop) = mpsc::channel();
+        let handle = thread::spawn(move || producer.run(Duration::from_millis(5), &stop));
+
+        thread::sleep(Duration::from_millis(20));
+        assert!(shutdown.send(()).is_ok());
+        assert!(handle.join().is_ok());
+    }
+}
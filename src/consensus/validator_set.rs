@@ -0,0 +1,72 @@
+use crate::crypto::keypair::PublicKey;
+
+/// Ordered registry of the keys allowed to propose blocks. Proposers take
+/// turns: the leader for a height is the validator at `height % len`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidatorSet {
+    validators: Vec<PublicKey>,
+}
+
+impl ValidatorSet {
+    pub fn new() -> Self {
+        ValidatorSet { validators: vec![] }
+    }
+
+    /// Appends `key` to the rotation; adding a key twice has no effect.
+    pub fn add(&mut self, key: PublicKey) {
+        if !self.contains(&key) {
+            self.validators.push(key);
+        }
+    }
+
+    pub fn remove(&mut self, key: &PublicKey) {
+        self.validators.retain(|v| v != key);
+    }
+
+    pub fn contains(&self, key: &PublicKey) -> bool {
+        self.validators.contains(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    pub fn leader_for_height(&self, height: u32) -> Option<&PublicKey> {
+        if self.is_empty() {
+            return None;
+        }
+        self.validators.get(height as usize % self.validators.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto::keypair::PrivateKey;
+
+    use super::ValidatorSet;
+
+    #[test]
+    fn test_leader_rotation() {
+        let keys: Vec<_> = (0..3).map(|_| PrivateKey::generate_key().generate_public()).collect();
+        let mut set = ValidatorSet::new();
+        assert_eq!(set.leader_for_height(0), None);
+
+        for key in &keys {
+            set.add(*key);
+        }
+        set.add(keys[0]);
+        assert_eq!(set.len(), 3);
+
+        for height in 0..9 {
+            assert_eq!(set.leader_for_height(height), Some(&keys[height as usize % 3]));
+        }
+
+        set.remove(&keys[1]);
+        assert_eq!(set.leader_for_height(1), Some(&keys[2]));
+        assert_eq!(set.leader_for_height(2), Some(&keys[0]));
+    }
+}
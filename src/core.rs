@@ -4,4 +4,9 @@ pub mod encoding;
 pub mod hasher;
 pub mod storage;
 pub mod validator;
-pub mod blockchain;
\ No newline at end of file
+pub mod blockchain;
+pub mod merkle;
+pub mod mempool;
+pub mod transfer;
+pub mod bloom;
+pub mod compact;
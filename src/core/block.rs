@@ -1,47 +1,195 @@
-use std::{io::{self, Write, Read, Cursor}};
+use std::{cmp::Reverse, collections::{BinaryHeap, HashMap, VecDeque}, fmt, io::{self, Write, Read}};
 
 use chrono::Utc;
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
 use encode_decode_derive::{Encode, Decode};
-use p256::ecdsa::Signature;
-use sha2::{Sha256, Digest};
-use crate::{types::hash::Hash, crypto::keypair::{PublicKey, PrivateKey}};
+use crate::{types::{hash::Hash, address::Address}, crypto::{self, keypair::{PrivateKey, VerifyError}, scheme::{ChainKey, ChainSignature, ChainSigner, Signer, Verifier}, hasher::{Hasher as _, Sha256Hasher}}};
 
-use super::{transaction::{Transaction}, encoding::{Encoder, Decoder, Encode, Decode, HeaderEncoder}, hasher::{BlockHasher, Hasher}};
+use super::{bloom::BlockFilter, transaction::{Transaction}, transfer::Transfer, encoding::{Encoder, Decoder, Encode, Decode, HeaderEncoder, varint_len}, hasher::{BlockHasher, Hasher, TxHasher}, merkle::merkle_root};
+
+/// Timestamp of the genesis block (2023-01-01T00:00:00Z), pinned so the
+/// genesis hash is the same on every node.
+pub const GENESIS_TIMESTAMP: i64 = 1_672_531_200;
+
+/// Header layout written for new blocks. Version 1 headers have no
+/// `nonce`; version 2 appends it, version 3 appends `difficulty` and
+/// version 4 `state_root`.
+pub const HEADER_VERSION: u32 = 4;
+
+/// Most transactions a block may carry.
+pub const MAX_TRANSACTIONS: usize = 10_000;
+
+/// Most bytes the encoded header and transactions of a block may take up.
+/// The trailing signature and validator are fixed-size and not counted.
+pub const MAX_BLOCK_BYTES: usize = 1 << 20;
+
+/// Prefix of the bytes a block signature covers, so a block signature can
+/// never pass for a signature in another context, such as a transaction's.
+pub const BLOCK_SIGNING_TAG: &[u8] = b"block-header-v1";
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BlockError {
+    TooManyTransactions(usize),
+    /// The header and transactions encode to this many bytes.
+    TooLarge(usize),
+    /// The header could not be encoded or hashed.
+    Encoding(String),
+    /// The block has no signature.
+    Unsigned,
+    /// The block has a signature but no validator to check it against.
+    MissingValidator,
+    /// `header.data` is not the Merkle root of the transactions.
+    MerkleMismatch,
+    /// The signature does not verify against the validator.
+    InvalidSignature(VerifyError),
+    /// The block carries more than one transaction with this hash.
+    DuplicateTransaction(Hash),
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockError::TooManyTransactions(n) => write!(f, "block has {} transactions, at most {} allowed", n, MAX_TRANSACTIONS),
+            BlockError::TooLarge(n) => write!(f, "block is {} bytes, at most {} allowed", n, MAX_BLOCK_BYTES),
+            BlockError::Encoding(e) => write!(f, "could not encode block: {}", e),
+            BlockError::Unsigned => write!(f, "block is not signed"),
+            BlockError::MissingValidator => write!(f, "signature has no validator"),
+            BlockError::MerkleMismatch => write!(f, "transactions do not match the header's Merkle root"),
+            BlockError::InvalidSignature(e) => write!(f, "invalid block signature: {}", e),
+            BlockError::DuplicateTransaction(hash) => write!(f, "transaction {} appears more than once", hash),
+        }
+    }
+}
+
+impl std::error::Error for BlockError {}
 
 #[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     pub version: u32,
     pub data: Hash,
     pub prev_block: Hash,
     pub timestamp: i64,
     pub height: u32,
+    pub nonce: u64,
+    /// Leading zero bits the block hash was mined to, as checked against
+    /// the chain's expected difficulty. Always zero before version 3.
+    pub difficulty: u32,
+    /// `AccountState::root` of the state after applying the block, checked
+    /// by chains that track state. Always zero before version 4.
+    pub state_root: Hash,
 }
 
 impl Header {
-    pub fn as_bytes(&self) -> Vec<u8> {
-        let encoder = HeaderEncoder::new();
-        let mut writer = Cursor::new(vec![]);
+    /// The header as encoded by `HeaderEncoder`, written straight into the
+    /// returned `Vec`.
+    pub fn as_bytes(&self) -> Result<Vec<u8>, io::Error> {
+        let mut bytes = vec![];
+        HeaderEncoder::new().encode(&mut bytes, self)?;
+        Ok(bytes)
+    }
 
-        assert!(encoder.encode(&mut writer, self).is_ok());
-        writer.set_position(0);
-        writer.into_inner()
+    /// SHA256 of `as_bytes`, the hash that identifies the block, as
+    /// computed by `BlockHasher::new()`.
+    ///
+    /// Panics if the header cannot be encoded, as for a version 1 header
+    /// with a nonce.
+    pub fn hash(&self) -> Hash {
+        BlockHasher::new().hash(self).expect("could not hash")
     }
 }
 
 
-#[derive(Debug, PartialEq, Decode, Encode, Clone)]
+#[derive(Decode, Encode, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Block {
     pub header: Header,
     pub transactions: Vec<Transaction>,
-    pub signature: Option<Signature>,
-    pub validator: Option<PublicKey>,
+    pub signature: Option<ChainSignature>,
+    pub validator: Option<ChainKey>,
     pub hash: Option<Hash>, // Cached version of the header hash
     pub prev_hash: Option<Hash>,
 }
 
+/// Blocks are equal when their header, transactions, signature and
+/// validator are. The cached hashes are left out, being derived from the
+/// header.
+impl PartialEq for Block {
+    fn eq(&self, other: &Self) -> bool {
+        self.header == other.header
+            && self.transactions == other.transactions
+            && self.signature == other.signature
+            && self.validator == other.validator
+    }
+}
+
+/// What a light client keeps of a block: the header and its hash, the
+/// number of transactions, and a filter to test for ones that concern it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockSummary {
+    pub header: Header,
+    pub hash: Hash,
+    pub tx_count: usize,
+    pub filter: BlockFilter,
+}
+
+/// `Block #<height> <short hash> txs=<count> validator=<address|none>`, where
+/// the short hash is the first eight hex digits of the header hash.
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hash = match BlockHasher::new().hash(&self.header) {
+            Ok(hash) => hash.to_string()[..8].to_owned(),
+            Err(_) => "unhashable".to_owned(),
+        };
+        write!(f, "Block #{} {} txs={} validator=", self.header.height, hash, self.transactions.len())?;
+        match self.validator {
+            Some(validator) => write!(f, "{}", validator.address()),
+            None => write!(f, "none"),
+        }
+    }
+}
+
+/// The one-line `Display` form; `{:#?}` and `detailed` show every field.
+impl fmt::Debug for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !f.alternate() {
+            return fmt::Display::fmt(self, f);
+        }
+        f.debug_struct("Block")
+            .field("header", &self.header)
+            .field("transactions", &self.transactions)
+            .field("signature", &self.signature)
+            .field("validator", &self.validator)
+            .field("hash", &self.hash)
+            .field("prev_hash", &self.prev_hash)
+            .finish()
+    }
+}
 
 impl Block {
-    pub fn new(header: Header, transactions: Vec<Transaction>) -> Block {
+    pub fn summary(&self) -> BlockSummary {
+        BlockSummary {
+            header: self.header,
+            hash: self.header.hash(),
+            tx_count: self.transactions.len(),
+            filter: BlockFilter::from_block(self),
+        }
+    }
+
+    /// Every field of the block, pretty-printed as by `{:#?}`.
+    pub fn detailed(&self) -> String {
+        format!("{:#?}", self)
+    }
+
+    /// Creates a block, committing to `transactions` by storing their Merkle
+    /// root in `header.data`.
+    ///
+    /// Panics if a transaction cannot be hashed. Everything is encoded into
+    /// memory so this does not happen in practice, but `try_new` reports it
+    /// as an error instead.
+    pub fn new(mut header: Header, transactions: Vec<Transaction>) -> Block {
+        header.data = merkle_root(&transactions);
         Block {
             header,
             transactions,
@@ -52,19 +200,121 @@ impl Block {
         }
     }
 
-    pub fn add_transaction(&mut self, t: &Transaction) -> Result<(), ()> {
-        self.transactions.push(t.clone());
-        Ok(())
+    /// Like `new`, but first puts `transactions` in `canonical_order`, so
+    /// any node assembling a block from the same transactions gets the same
+    /// Merkle root and hash.
+    pub fn assemble(header: Header, mut transactions: Vec<Transaction>) -> Block {
+        canonical_order(&mut transactions);
+        Block::new(header, transactions)
     }
 
+    /// Like `new`, but refuses blocks over `MAX_TRANSACTIONS` or
+    /// `MAX_BLOCK_BYTES`, which other nodes would fail to decode, and returns
+    /// encoding failures rather than panicking. The block hash is cached.
+    pub fn try_new(header: Header, transactions: Vec<Transaction>) -> Result<Block, BlockError> {
+        if transactions.len() > MAX_TRANSACTIONS {
+            return Err(BlockError::TooManyTransactions(transactions.len()));
+        }
+
+        let header_len = header.as_bytes().map_err(|e| BlockError::Encoding(e.to_string()))?.len();
+        let size = encoded_size(header_len, &transactions);
+        if size > MAX_BLOCK_BYTES {
+            return Err(BlockError::TooLarge(size));
+        }
+
+        let mut block = Block::new(header, transactions);
+        block.hash = Some(BlockHasher::new().hash(&block.header).map_err(BlockError::Encoding)?);
+        Ok(block)
+    }
+
+    /// The block without its transactions, as `Blockchain::prune` leaves it
+    /// in storage. The header still commits to the transactions, so only
+    /// its signature can be checked, as a `SignedHeader`.
+    pub fn without_body(&self) -> Block {
+        Block { transactions: vec![], ..self.clone() }
+    }
+
+    /// Whether the block has no transactions though its header commits to
+    /// some, as after `without_body`.
+    pub fn is_pruned(&self) -> bool {
+        self.transactions.is_empty() && self.header.data != merkle_root(&[])
+    }
+
+    /// Sum of the fees of the transactions, saturating at `u64::MAX`.
+    pub fn total_fees(&self) -> u64 {
+        self.transactions.iter().map(Transaction::fee).fold(0, u64::saturating_add)
+    }
+
+    /// Encoded bytes of the header and transactions, the size checked
+    /// against `MAX_BLOCK_BYTES`.
+    ///
+    /// Panics if the header cannot be encoded, as for a version 1 header
+    /// with a nonce.
+    pub fn weight(&self) -> usize {
+        let header_len = self.header.as_bytes().expect("could not encode header").len();
+        encoded_size(header_len, &self.transactions)
+    }
+
+    /// Appends `tx` and recommits `header.data` to the new Merkle root. The
+    /// header changes, so the cached hash and any signature are cleared and
+    /// the block must be signed again.
+    pub fn add_transaction(&mut self, tx: Transaction) {
+        self.transactions.push(tx);
+        self.header.data = merkle_root(&self.transactions);
+        self.hash = None;
+        self.signature = None;
+        self.validator = None;
+    }
+
+
+    /// The deterministic first block of every chain.
+    pub fn genesis() -> Self {
+        let header = Header {
+            version: HEADER_VERSION,
+            data: Hash::zero(),
+            prev_block: Hash::zero(),
+            timestamp: GENESIS_TIMESTAMP,
+            height: 0,
+            nonce: 0,
+            difficulty: 0,
+            state_root: Hash::zero(),
+        };
+
+        Block::new(header, vec![])
+    }
 
     pub fn random_block(h: u32) -> Self {
         let header = Header {
-            version: 1,
-            data: Hash::random(),
+            version: HEADER_VERSION,
+            data: Hash::zero(),
             prev_block: Hash::random(),
             timestamp: Utc::now().timestamp(),
             height: h,
+            nonce: 0,
+            difficulty: 0,
+            state_root: Hash::zero(),
+        };
+
+        Block::new(header, vec![])
+    }
+
+    /// Like `random_block`, but the same `seed` always gives the same block:
+    /// `prev_block` comes from an RNG seeded with it and the timestamp is
+    /// up to a year after `GENESIS_TIMESTAMP`, also picked by the seed.
+    pub fn random_block_seeded(h: u32, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut prev_block = [0u8; 32];
+        rng.fill_bytes(&mut prev_block);
+
+        let header = Header {
+            version: HEADER_VERSION,
+            data: Hash::zero(),
+            prev_block: Hash::from_bytes(&prev_block).expect("32 bytes"),
+            timestamp: GENESIS_TIMESTAMP + rng.gen_range(0..365 * 24 * 60 * 60),
+            height: h,
+            nonce: 0,
+            difficulty: 0,
+            state_root: Hash::zero(),
         };
 
         Block::new(header, vec![])
@@ -79,121 +329,832 @@ impl Block {
     }
     
 
+    /// Hashes the current header and refreshes the cached `hash` field.
+    /// Given `BlockHasher::new()` this is `header.hash()`; the hasher is an
+    /// argument so a different hash function can be plugged in.
+    ///
+    /// `header` is public and may be mutated at any time, so the hash is
+    /// always recomputed rather than served from the cache; the cached value
+    /// only reflects the header as of the last call.
     pub fn hash(&mut self, hasher: Box<dyn Hasher<Header>>) -> Hash {
-        if self.hash.is_none() {
-            self.hash = Some(hasher.hash(&self.header).expect("could not hash"));
+        let hash = hasher.hash(&self.header).expect("could not hash");
+        self.hash = Some(hash);
+        hash
+    }
+
+    /// Increments `header.nonce` until the block hash has at least
+    /// `difficulty` leading zero bits. From version 3 the difficulty is also
+    /// recorded in `header.difficulty`. Mining changes the header, so a block
+    /// should be signed afterwards.
+    pub fn mine(&mut self, difficulty: u32) {
+        assert!(difficulty <= 256, "difficulty is at most 256 bits");
+        assert!(self.header.version >= 2, "version 1 headers have no nonce");
+        if self.header.version >= 3 {
+            self.header.difficulty = difficulty;
+        }
+        while self.hash(Box::new(BlockHasher::new())).leading_zero_bits() < difficulty {
+            self.header.nonce = self.header.nonce.wrapping_add(1);
         }
-        self.hash.unwrap()
     }
 
-    // pub fn header_data(&self) -> Result<Vec<u8>, io::Error> {
-    //     let encoder = HeaderEncoder::new();
-    //     let mut vec = Cursor::new(vec![]);
-    //     self.header.encode_binary(&mut vec, encoder).expect("couldn't encode header");
-    //     Ok(vec.get_ref().to_owned())
-    // }
+    pub fn meets_difficulty(&self, difficulty: u32) -> bool {
+        match BlockHasher::new().hash(&self.header) {
+            Ok(hash) => hash.leading_zero_bits() >= difficulty,
+            Err(_) => false,
+        }
+    }
 
-    pub fn sign(&mut self, key: PrivateKey) -> Result<(), String> {
-        let header = self.header.as_bytes();
-        self.signature = Some(key.sign(&header).expect("could not sign"));
-        self.validator = Some(key.generate_public());
+    /// Signs the header as `key`'s validator. `header.data` is first reset to
+    /// the Merkle root of the transactions, so the signature always commits
+    /// to the transaction set; if that changes the header, the cached hash is
+    /// cleared.
+    pub fn sign(&mut self, key: ChainSigner) -> Result<(), String> {
+        let (validator, signature) = self.sign_with(&key)?;
+        self.signature = Some(signature);
+        self.validator = Some(validator);
         Ok(())
     }
 
-    pub fn verify(&self) -> Result<(), String> {
-        if self.signature.is_none() {
-            return Err("no signature".to_string());
+    /// What `sign` does, for any signature scheme: commits `header.data` to
+    /// the transactions and signs the header as `signer`'s validator,
+    /// returning the validator key and signature without storing them.
+    pub fn sign_with<S: Signer>(&mut self, signer: &S) -> Result<(S::PublicKey, S::Signature), String> {
+        let root = merkle_root(&self.transactions);
+        if self.header.data != root {
+            self.header.data = root;
+            self.hash = None;
         }
 
-        let validator = self.validator.as_ref().unwrap();
-        let signature = self.signature.unwrap();
-        let res = validator.verify(&self.header.as_bytes(), &signature);
-        if res.is_err() {
-            return Err("Could not verify".to_owned());
+        let validator = signer.public_key();
+        let signature = signer.sign(&signing_bytes(&self.header, &validator)?)?;
+        Ok((validator, signature))
+    }
+
+    /// The header with its signature and validator, as sent ahead of the
+    /// body in a headers-first sync.
+    pub fn signed_header(&self) -> Result<SignedHeader, BlockError> {
+        let (validator, signature) = self.validator_and_signature()?;
+        Ok(SignedHeader { header: self.header, signature: *signature, validator: *validator })
+    }
+
+    /// Checks the Merkle root, duplicates and the signature against
+    /// `validator`. An unsigned block is `Unsigned` whether or not it names
+    /// a validator.
+    pub fn verify(&self) -> Result<(), BlockError> {
+        let (validator, signature) = self.validator_and_signature()?;
+        self.verify_with(validator, signature)
+    }
+
+    fn validator_and_signature(&self) -> Result<(&ChainKey, &ChainSignature), BlockError> {
+        match (&self.validator, &self.signature) {
+            (Some(validator), Some(signature)) => Ok((validator, signature)),
+            (None, Some(_)) => Err(BlockError::MissingValidator),
+            (_, None) => Err(BlockError::Unsigned),
         }
+    }
+
+    /// What `verify` does once the validator and signature are known, for
+    /// any signature scheme.
+    pub fn verify_with<V: Verifier>(&self, validator: &V, signature: &V::Signature) -> Result<(), BlockError> {
+        self.verify_body()?;
+        let message = signing_bytes(&self.header, validator).map_err(BlockError::Encoding)?;
+        validator.verify(&message, signature).map_err(BlockError::InvalidSignature)
+    }
 
-        for t in &self.transactions {
-            assert!(t.verify().is_ok());
+    /// The checks on the transactions that every signature path makes.
+    fn verify_body(&self) -> Result<(), BlockError> {
+        if self.header.data != merkle_root(&self.transactions) {
+            return Err(BlockError::MerkleMismatch);
+        }
+        if let Some(hash) = self.duplicate_transaction() {
+            return Err(BlockError::DuplicateTransaction(hash));
         }
         Ok(())
     }
 
+    /// What an external signer that only signs JSON signs instead of the
+    /// binary signing bytes: `{"header":{...},"validator":"<key>"}` with
+    /// keys sorted at every level and no whitespace. Hashes are lowercase
+    /// hex, the validator is its compressed SEC1 key in hex, or `null` if
+    /// the block has none yet.
+    #[cfg(feature = "serde")]
+    pub fn signing_payload_json(&self) -> String {
+        let payload = serde_json::json!({ "header": self.header, "validator": self.validator });
+        // `serde_json::Map` keeps keys sorted, so this is canonical.
+        payload.to_string()
+    }
+
+    /// Like `verify`, but for a signature over `signing_payload_json`
+    /// rather than the binary signing bytes.
+    #[cfg(feature = "serde")]
+    pub fn verify_json(&self) -> Result<(), BlockError> {
+        let (validator, signature) = self.validator_and_signature()?;
+        self.verify_body()?;
+        validator
+            .verify(self.signing_payload_json().as_bytes(), signature)
+            .map_err(BlockError::InvalidSignature)
+    }
+
+    /// The hash of the first transaction that appears earlier in the block
+    /// too, if any. The Merkle root commits to duplicates just as to any
+    /// other transaction, so they have to be looked for separately.
+    pub fn duplicate_transaction(&self) -> Option<Hash> {
+        let hasher = TxHasher::new();
+        let mut seen = std::collections::HashSet::new();
+        self.transactions
+            .iter()
+            .map(|tx| hasher.hash(tx).expect("could not hash"))
+            .find(|hash| !seen.insert(*hash))
+    }
+
+    /// Puts a coinbase paying `reward` to `to` in front of the transactions.
+    /// Like `add_transaction`, this clears the hash and signature.
+    pub fn add_coinbase(&mut self, to: Address, reward: u64) {
+        let coinbase = Transaction::coinbase(Transfer::coinbase(to, reward, self.header.height));
+        self.transactions.insert(0, coinbase);
+        self.header.data = merkle_root(&self.transactions);
+        self.hash = None;
+        self.signature = None;
+        self.validator = None;
+    }
+
+    /// The coinbase, if the block starts with one.
+    pub fn coinbase(&self) -> Option<&Transaction> {
+        self.transactions.first().filter(|tx| tx.is_coinbase())
+    }
+
+    /// Verifies every contained transaction, returning the index of the
+    /// first one that is unsigned, fails verification or has expired by the
+    /// block's height. The first
+    /// transaction may instead be an unsigned coinbase; a coinbase anywhere
+    /// else is invalid.
+    ///
+    /// Signatures are checked together with `crypto::verify_batch`.
+    pub fn verify_transactions(&self) -> Result<(), usize> {
+        let height = self.header.height;
+        let mut invalid = None;
+        let mut signed = vec![];
+        for (i, t) in self.transactions.iter().enumerate() {
+            if !t.is_valid_at(height) {
+                invalid = Some(i);
+                break;
+            }
+            if i == 0 && t.is_coinbase() {
+                continue;
+            }
+            match t.signed_message() {
+                Ok(message) => signed.push((i, message)),
+                Err(_) => {
+                    invalid = Some(i);
+                    break;
+                }
+            }
+        }
+
+        // Every signed transaction comes before `invalid`, so a bad
+        // signature among them is the first problem.
+        let items: Vec<_> = signed.iter().map(|(_, (key, message, signature))| (*key, message.as_slice(), *signature)).collect();
+        crypto::verify_batch(&items).map_err(|j| signed[j].0)?;
+        match invalid {
+            Some(i) => Err(i),
+            None => Ok(()),
+        }
+    }
+
+    /// SHA256 of the signatures of the signed transactions, in block order.
+    /// Unsigned transactions contribute nothing. Two blocks with the same
+    /// signed transactions in the same order have the same digest.
+    pub fn tx_signatures_digest(&self) -> Hash {
+        let bytes: Vec<u8> = self.transactions
+            .iter()
+            .filter_map(|tx| tx.signature)
+            .flat_map(|signature| signature.to_bytes())
+            .collect();
+        Sha256Hasher.hash(&bytes)
+    }
+
+    /// Verifies the header signature (`verify`) and every transaction.
+    pub fn verify_full(&self) -> Result<(), String> {
+        self.verify().map_err(|e| e.to_string())?;
+        self.verify_transactions()
+            .map_err(|i| format!("invalid transaction at index {}", i))
+    }
+
+
+}
+
+
+/// A block header with the signature and validator of its block, which
+/// vouch for the header without the transactions.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SignedHeader {
+    pub header: Header,
+    pub signature: ChainSignature,
+    pub validator: ChainKey,
+}
+
+impl SignedHeader {
+    /// Checks the signature against `validator`, as `Block::verify` does
+    /// short of the transactions.
+    pub fn verify(&self) -> Result<(), BlockError> {
+        let message = signing_bytes(&self.header, &self.validator).map_err(BlockError::Encoding)?;
+        self.validator.verify(&message, &self.signature).map_err(BlockError::InvalidSignature)
+    }
+
+    /// The signed block with `transactions` as its body. The header is kept
+    /// as is, so the signature only verifies if `transactions` match its
+    /// Merkle root.
+    pub fn into_block(self, transactions: Vec<Transaction>) -> Block {
+        Block {
+            header: self.header,
+            transactions,
+            signature: Some(self.signature),
+            validator: Some(self.validator),
+            hash: None,
+            prev_hash: None,
+        }
+    }
+}
+
+/// The bytes a validator signs: `BLOCK_SIGNING_TAG`, the encoded header and
+/// the validator's compressed public key. Committing to the key means a
+/// signature cannot be paired with any key other than the signer's, even one
+/// derived from the signature itself.
+fn signing_bytes<V: Verifier>(header: &Header, validator: &V) -> Result<Vec<u8>, String> {
+    let mut bytes = BLOCK_SIGNING_TAG.to_vec();
+    bytes.extend(header.as_bytes().map_err(|e| e.to_string())?);
+    bytes.extend(validator.key_bytes());
+    Ok(bytes)
+}
 
+/// Bytes taken by a header of `header_len` bytes followed by the
+/// transaction count and `transactions`.
+fn encoded_size(header_len: usize, transactions: &[Transaction]) -> usize {
+    transactions
+        .iter()
+        .fold(header_len + varint_len(transactions.len() as u64), |size, tx| size + tx.as_bytes().len())
 }
 
+/// Sorts `transactions` into the order blocks are assembled in: a coinbase
+/// first, then by fee, highest first, with ties broken by ascending
+/// transaction hash. As in `Mempool::select_for_block`, a sender's transfers
+/// keep ascending nonce order, so each still applies on top of the one
+/// before: a transfer only competes on fee once all of the sender's lower
+/// nonces have been placed.
+///
+/// This is consensus-critical. The order fixes the Merkle root and so the
+/// block hash, and nodes only agree on the block built from a set of
+/// transactions if they all sort it the same way.
+pub fn canonical_order(transactions: &mut [Transaction]) {
+    let hasher = TxHasher::new();
+    let hashes: Vec<Hash> = transactions.iter().map(|tx| hasher.hash(tx).expect("could not hash")).collect();
+
+    // Each sender's transfers in nonce order; anything else is on its own.
+    let mut queues: Vec<VecDeque<(u64, usize)>> = vec![];
+    let mut senders: HashMap<Address, usize> = HashMap::new();
+    for (i, tx) in transactions.iter().enumerate() {
+        match tx.decode_transfer() {
+            Ok(transfer) if !tx.is_coinbase() => {
+                let queue = *senders.entry(transfer.from).or_insert_with(|| {
+                    queues.push(VecDeque::new());
+                    queues.len() - 1
+                });
+                queues[queue].push_back((transfer.nonce, i));
+            }
+            _ => queues.push(VecDeque::from([(0, i)])),
+        }
+    }
+    for queue in &mut queues {
+        queue.make_contiguous().sort_by_key(|&(nonce, i)| (nonce, hashes[i]));
+    }
+
+    let key = |i: usize| (transactions[i].is_coinbase(), transactions[i].fee(), Reverse(hashes[i]));
+    let mut heads: BinaryHeap<_> = queues
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(q, queue)| queue.pop_front().map(|(_, i)| (key(i), q, i)))
+        .collect();
+    let mut order = Vec::with_capacity(transactions.len());
+    while let Some((_, q, i)) = heads.pop() {
+        order.push(i);
+        if let Some((_, next)) = queues[q].pop_front() {
+            heads.push((key(next), q, next));
+        }
+    }
+
+    let sorted: Vec<Transaction> = order.into_iter().map(|i| transactions[i].clone()).collect();
+    transactions.clone_from_slice(&sorted);
+}
 
 #[cfg(test)]
 mod test {
-    // use crate::types::hash::Hash;
-
-    // use super::*;
-    // use std::io::Cursor;
-
-    // #[test]
-    // fn test_header_encode_decode() {
-    //     let h = Header {
-    //         version: 1,
-    //         prev_block: Hash::random(),
-    //         timestamp: chrono::Utc::now().timestamp_nanos(),
-    //         height: 10,
-    //         nonce: 989394,
-    //     };
-
-    //     let mut buf = Cursor::new(vec![]);
-    //     assert!(h.encode_binary(&mut buf).is_ok());
-
-    //     buf.set_position(0);
-    //     let h_decode = Header::decode_binary(&mut buf).unwrap();
-    //     assert_eq!(h, h_decode);
-    // }
-
-    // #[test]
-    // fn test_block_encode_decode() {
-    //     let header = Header {
-    //         version: 1,
-    //         prev_block: Hash::random(),
-    //         timestamp: chrono::Utc::now().timestamp_nanos(),
-    //         height: 10,
-    //         nonce: 989394,
-    //     };
-    //     let b = Block::new(header, vec![]);
-
-    //     let mut buf = Cursor::new(vec![]);
-    //     assert!(b.encode_binary(&mut buf).is_ok());
-
-    //     buf.set_position(0);
-    //     let b_decode = Block::decode_binary(&mut buf).unwrap();
-    //     println!("third part");
-    //     assert_eq!(b, b_decode);
-    // }
-
-    // #[test]
-    // fn test_block_hash() {
-    //     let mut b = Block {
-    //         header: Header {
-    //             version: 1,
-    //             prev_block: Hash::random(),
-    //             timestamp: chrono::Utc::now().timestamp_nanos(),
-    //             height: 10,
-    //             nonce: 0,
-    //         },
-    //         transactions: vec![],
-    //         hash: Hash::default(),
-    //     };
-
-    //     let h = b.hash();
-    //     assert!(!h.is_zero());
-    // }
+    use std::io::{self, Cursor, ErrorKind, Read};
 
-    
+    use crate::{types::{hash::Hash, address::Address}, crypto::{keypair::{PrivateKey, PublicKey, VerifyError}, signature::Signature, hasher::{self, Hasher as _, Sha256Hasher}, scheme::{test::MockKey, Signer}}, core::{hasher::{BlockHasher, Hasher, TxHasher}, transaction::{Transaction, TX_SIGNING_TAG}, transfer::Transfer, encoding::{Encode, Decode, HeaderEncoder, HeaderDecoder, BlockEncoder, BlockDecoder, TxEncoder, write_varint}}};
 
-    use crate::{crypto::{keypair::PrivateKey}};
+    use super::{canonical_order, merkle_root, Block, Header, BlockError, MAX_TRANSACTIONS, MAX_BLOCK_BYTES, HEADER_VERSION, GENESIS_TIMESTAMP, BLOCK_SIGNING_TAG};
+
+    fn encode_decode(b: &Block) -> Block {
+        let mut buf = Cursor::new(vec![]);
+        assert!(b.encode_binary(&mut buf, BlockEncoder::new()).is_ok());
+
+        buf.set_position(0);
+        *Block::decode_binary(&mut buf, BlockDecoder::new()).unwrap()
+    }
+
+    #[test]
+    fn test_header_encode_decode() {
+        let h = Header {
+            version: HEADER_VERSION,
+            data: Hash::random(),
+            prev_block: Hash::random(),
+            timestamp: chrono::Utc::now().timestamp(),
+            height: 10,
+            nonce: 989394,
+            difficulty: 17,
+            state_root: Hash::random(),
+        };
+
+        let mut buf = Cursor::new(vec![]);
+        assert!(h.encode_binary(&mut buf, HeaderEncoder::new()).is_ok());
+
+        buf.set_position(0);
+        let h_decode = Header::decode_binary(&mut buf, HeaderDecoder::new()).unwrap();
+        assert_eq!(h, *h_decode);
+    }
 
-    use super::{Block};
+    #[test]
+    fn test_header_as_bytes_matches_cursor_encoding() {
+        for version in 1..=HEADER_VERSION {
+            let mut h = Block::random_block(9).header;
+            h.version = version;
+            if version >= 2 {
+                h.nonce = 77;
+            }
+            if version >= 3 {
+                h.difficulty = 5;
+            }
+            if version >= 4 {
+                h.state_root = Hash::random();
+            }
+
+            let mut cursor = Cursor::new(vec![]);
+            assert!(h.encode_binary(&mut cursor, HeaderEncoder::new()).is_ok());
+            assert_eq!(h.as_bytes().unwrap(), cursor.get_ref().to_owned());
+        }
+
+        let tx = Transaction::new(b"foo".to_vec());
+        let mut cursor = Cursor::new(vec![]);
+        assert!(tx.encode_binary(&mut cursor, TxEncoder::new()).is_ok());
+        assert_eq!(tx.as_bytes(), cursor.into_inner());
+    }
+
+    #[test]
+    fn test_v1_header_decode() {
+        let data = Hash::random();
+        let prev_block = Hash::random();
+
+        let mut v1 = vec![];
+        v1.extend_from_slice(&1u32.to_le_bytes());
+        v1.extend_from_slice(&data.to_vec());
+        v1.extend_from_slice(&prev_block.to_vec());
+        v1.extend_from_slice(&GENESIS_TIMESTAMP.to_be_bytes());
+        v1.extend_from_slice(&7u32.to_le_bytes());
+
+        let h = *Header::decode_binary(&mut v1.as_slice(), HeaderDecoder::new()).unwrap();
+        assert_eq!(h, Header { version: 1, data, prev_block, timestamp: GENESIS_TIMESTAMP, height: 7, nonce: 0, difficulty: 0, state_root: Hash::zero() });
+        assert_eq!(h.as_bytes().unwrap(), v1);
+
+        let mut v2 = h;
+        v2.version = 2;
+        assert_eq!(v2.as_bytes().unwrap().len(), v1.len() + 8);
+
+        let mut v3 = h;
+        v3.version = 3;
+        assert_eq!(v3.as_bytes().unwrap().len(), v1.len() + 12);
+
+        let mut v4 = h;
+        v4.version = 4;
+        assert_eq!(v4.as_bytes().unwrap().len(), v1.len() + 44);
+    }
+
+    #[test]
+    fn test_header_timestamp_is_big_endian() {
+        let bytes = Block::genesis().header.as_bytes().unwrap();
+
+        // 2023-01-01T00:00:00Z, after the version and two hashes.
+        assert_eq!(GENESIS_TIMESTAMP, 0x63b0_cd00);
+        assert_eq!(bytes[68..76], [0x00, 0x00, 0x00, 0x00, 0x63, 0xb0, 0xcd, 0x00]);
+
+        let mut h = Block::genesis().header;
+        h.timestamp = -2;
+        let bytes = h.as_bytes().unwrap();
+        assert_eq!(bytes[68..76], [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe]);
+        assert_eq!(*Header::decode_binary(&mut bytes.as_slice(), HeaderDecoder::new()).unwrap(), h);
+    }
+
+    #[test]
+    fn test_header_version_checks() {
+        let mut h = Block::random_block(1).header;
+        h.version = 1;
+        h.nonce = 1;
+        assert_eq!(h.as_bytes().unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        h.version = 2;
+        h.difficulty = 1;
+        assert_eq!(h.as_bytes().unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        h.version = 3;
+        h.state_root = Hash::random();
+        assert_eq!(h.as_bytes().unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        h.version = 5;
+        assert_eq!(h.as_bytes().unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        let mut bytes = Block::random_block(1).header.as_bytes().unwrap();
+        bytes[0] = 99;
+        let err = Header::decode_binary(&mut bytes.as_slice(), HeaderDecoder::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_block_encode_decode() {
+        let header = Header {
+            version: HEADER_VERSION,
+            data: Hash::zero(),
+            prev_block: Hash::random(),
+            timestamp: chrono::Utc::now().timestamp(),
+            height: 10,
+            nonce: 989394,
+            difficulty: 0,
+            state_root: Hash::zero(),
+        };
+        let b = Block::new(header, vec![]);
+
+        let b_decode = encode_decode(&b);
+        assert!(b_decode.signature.is_none());
+        assert!(b_decode.validator.is_none());
+        assert_eq!(b, b_decode);
+    }
+
+    #[test]
+    fn test_signed_block_encode_decode() {
+        let mut signed = Transaction::new(b"foo".to_vec());
+        assert!(signed.sign(PrivateKey::generate_key()).is_ok());
+        let unsigned = Transaction::new(b"bar".to_vec());
+
+        let mut b = Block::new(Block::random_block(3).header, vec![signed, unsigned]);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        b.hash(Box::new(BlockHasher::new()));
+
+        let b_decode = encode_decode(&b);
+        assert_eq!(b_decode.signature, b.signature);
+        assert_eq!(b_decode.validator, b.validator);
+        assert_eq!(b, b_decode);
+        assert!(b_decode.verify().is_ok());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_verify_json() {
+        let mut b = Block::random_block_seeded(3, 7);
+        b.header.prev_block = Hash::from_bytes(&[0x22; 32]).unwrap();
+        b.header.timestamp = GENESIS_TIMESTAMP;
+        let expected = concat!(
+            r#"{"header":{"data":"0000000000000000000000000000000000000000000000000000000000000000","difficulty":0,"#,
+            r#""height":3,"nonce":0,"prev_block":"2222222222222222222222222222222222222222222222222222222222222222","#,
+            r#""state_root":"0000000000000000000000000000000000000000000000000000000000000000","#,
+            r#""timestamp":1672531200,"version":4},"validator":null}"#,
+        );
+        assert_eq!(b.signing_payload_json(), expected);
+
+        // An external signer gets the payload and returns a signature.
+        let key = PrivateKey::generate_key();
+        b.validator = Some(key.generate_public());
+        let signature = key.sign(b.signing_payload_json().as_bytes()).unwrap();
+        b.signature = Some(signature);
+        assert!(b.verify_json().is_ok());
+        assert!(b.verify().is_err());
+
+        let mut tampered = b.clone();
+        tampered.header.nonce += 1;
+        assert_eq!(tampered.verify_json(), Err(BlockError::InvalidSignature(VerifyError::Mismatch)));
+
+        b.validator = Some(PrivateKey::generate_key().generate_public());
+        assert!(b.verify_json().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_block_json_round_trip() {
+        let mut signed = Transaction::new(b"foo".to_vec());
+        assert!(signed.sign(PrivateKey::generate_key()).is_ok());
+        let unsigned = Transaction::new(b"bar".to_vec());
+
+        let mut b = Block::new(Block::random_block(3).header, vec![signed, unsigned]);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        b.hash(Box::new(BlockHasher::new()));
+
+        let json = serde_json::to_string(&b).unwrap();
+        assert!(json.contains(&b.header.prev_block.to_string()));
+
+        let b_decode: Block = serde_json::from_str(&json).unwrap();
+        assert_eq!(b, b_decode);
+        assert!(b_decode.verify().is_ok());
+    }
+
+    #[test]
+    fn test_try_new_enforces_limits() {
+        let header = Block::random_block(1).header;
+
+        let txs = vec![Transaction::new(vec![]); MAX_TRANSACTIONS + 1];
+        assert_eq!(Block::try_new(header, txs).unwrap_err(), BlockError::TooManyTransactions(MAX_TRANSACTIONS + 1));
+
+        let txs = vec![Transaction::new(vec![0u8; MAX_BLOCK_BYTES])];
+        assert!(matches!(Block::try_new(header, txs), Err(BlockError::TooLarge(_))));
+
+        let txs = vec![Transaction::new(b"foo".to_vec())];
+        assert!(Block::try_new(header, txs).is_ok());
+    }
+
+    #[test]
+    fn test_try_new_caches_hash() {
+        let mut tx = Transaction::new(b"foo".to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+
+        let mut b = Block::try_new(Block::random_block(1).header, vec![tx.clone()]).unwrap();
+        assert_eq!(b.transactions, vec![tx]);
+
+        let cached = b.hash;
+        assert!(cached.is_some());
+        assert_eq!(cached, Some(b.hash(Box::new(BlockHasher::new()))));
+    }
+
+    #[test]
+    fn test_decode_rejects_huge_transaction_count() {
+        let mut buf = Block::random_block(1).header.as_bytes().unwrap();
+        write_varint(&mut buf, u64::MAX).unwrap();
+
+        let err = Block::decode_binary(&mut buf.as_slice(), BlockDecoder::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_decode_rejects_oversized_block() {
+        // A single transaction claiming 4GiB of data, backed by an endless
+        // stream of zeroes.
+        let mut prefix = Block::random_block(1).header.as_bytes().unwrap();
+        write_varint(&mut prefix, 1).unwrap();
+        write_varint(&mut prefix, u32::MAX as u64).unwrap();
+        let mut reader = prefix.as_slice().chain(io::repeat(0));
+
+        let err = Block::decode_binary(&mut reader, BlockDecoder::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_decode_truncated_block() {
+        let mut b = Block::random_block(1);
+        for data in [b"foo", b"bar"] {
+            let mut tx = Transaction::new(data.to_vec());
+            assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+            b.add_transaction(tx);
+        }
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        let mut bytes = vec![];
+        assert!(b.encode_binary(&mut bytes, BlockEncoder::new()).is_ok());
+
+        let header_len = b.header.as_bytes().unwrap().len();
+        let at = |len: usize| Block::decode_binary(&mut &bytes[..len], BlockDecoder::new()).unwrap_err();
+        for len in 0..bytes.len() {
+            let err = at(len);
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "truncated at {}", len);
+            assert!(err.to_string().starts_with("block is truncated in "), "truncated at {}: {}", len, err);
+        }
+        assert_eq!(at(0).to_string(), "block is truncated in header");
+        assert_eq!(at(header_len).to_string(), "block is truncated in transaction count");
+        assert_eq!(at(header_len + 2).to_string(), "block is truncated in transaction 0");
+        assert_eq!(at(bytes.len() - 1).to_string(), "block is truncated in validator");
+
+        assert_eq!(*Block::decode_binary(&mut bytes.as_slice(), BlockDecoder::new()).unwrap(), b);
+    }
+
+    #[test]
+    fn test_assemble_orders_transactions() {
+        let key = PrivateKey::generate_key();
+        let to = PrivateKey::generate_key().generate_public().address();
+        let transfer = |nonce, fee| {
+            let t = Transfer { from: key.generate_public().address(), to, amount: 1, nonce, fee };
+            let mut tx = Transaction::transfer(t);
+            assert!(tx.sign(key.clone()).is_ok());
+            tx
+        };
+        let mut plain: Vec<Transaction> = (0..4u8).map(|i| {
+            let mut tx = Transaction::new(vec![i]);
+            assert!(tx.sign(key.clone()).is_ok());
+            tx
+        }).collect();
+        plain.sort_by_key(|tx| TxHasher::new().hash(tx).unwrap());
+
+        let coinbase = Transaction::coinbase(Transfer::coinbase(to, 50, 1));
+        let (rich, poor) = (transfer(0, 9), transfer(1, 3));
+        let txs = vec![plain[2].clone(), poor.clone(), plain[0].clone(), coinbase.clone(), plain[3].clone(), rich.clone(), plain[1].clone()];
+
+        let header = Block::random_block(1).header;
+        let mut a = Block::assemble(header, txs.clone());
+        let mut b = Block::assemble(header, txs.into_iter().rev().collect());
+        assert_eq!(a.header.data, b.header.data);
+        assert_eq!(a.hash(Box::new(BlockHasher::new())), b.hash(Box::new(BlockHasher::new())));
+
+        let mut expected = vec![coinbase, rich, poor];
+        expected.extend(plain);
+        assert_eq!(a.transactions, expected);
+
+        let mut sorted = a.transactions.clone();
+        canonical_order(&mut sorted);
+        assert_eq!(sorted, a.transactions);
+    }
+
+    #[test]
+    fn test_canonical_order_keeps_nonce_order() {
+        let key = PrivateKey::generate_key();
+        let other = PrivateKey::generate_key();
+        let to = PrivateKey::generate_key().generate_public().address();
+        let transfer = |key: &PrivateKey, nonce, fee| {
+            let t = Transfer { from: key.generate_public().address(), to, amount: 1, nonce, fee };
+            let mut tx = Transaction::transfer(t);
+            assert!(tx.sign(key.clone()).is_ok());
+            tx
+        };
+
+        // The dearest transfer cannot go before the sender's lower nonce.
+        let (first, second, middle) = (transfer(&key, 0, 1), transfer(&key, 1, 9), transfer(&other, 0, 5));
+        let mut txs = vec![second.clone(), middle.clone(), first.clone()];
+        canonical_order(&mut txs);
+        assert_eq!(txs, vec![middle, first, second]);
+    }
+
+    #[test]
+    fn test_display() {
+        let mut b = Block::random_block(7);
+        b.add_transaction(Transaction::new(b"foo".to_vec()));
+        let short_hash = BlockHasher::new().hash(&b.header).unwrap().to_string()[..8].to_owned();
+        assert_eq!(b.to_string(), format!("Block #7 {} txs=1 validator=none", short_hash));
+
+        let key = PrivateKey::generate_key();
+        assert!(b.sign(key.clone()).is_ok());
+        let shown = b.to_string();
+        assert!(shown.starts_with(&format!("Block #7 {} ", short_hash)));
+        assert!(shown.ends_with(&format!("validator={}", key.generate_public().address())));
+        assert_eq!(format!("{:?}", b), shown);
+
+        let detailed = b.detailed();
+        assert_eq!(detailed, format!("{:#?}", b));
+        assert!(detailed.contains("transactions: [") && detailed.contains("prev_block:"));
+    }
+
+    #[test]
+    fn test_block_hash() {
+        let mut b = Block::random_block(10);
+
+        let h = b.hash(Box::new(BlockHasher::new()));
+        assert!(!h.is_zero());
+    }
 
     
 
 
+    #[test]
+    fn test_block_commits_to_transactions() {
+        let header = Block::random_block(0).header;
+        let mut a = Block::new(header, vec![Transaction::new(b"foo".to_vec())]);
+        let mut b = Block::new(header, vec![Transaction::new(b"bar".to_vec())]);
+
+        assert_ne!(a.header.data, b.header.data);
+        assert_ne!(a.hash(Box::new(BlockHasher::new())), b.hash(Box::new(BlockHasher::new())));
+    }
+
+    #[test]
+    fn test_signature_commits_to_transactions() {
+        let mut tx = Transaction::new(b"foo".to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+        let mut b = Block::new(Block::random_block(1).header, vec![tx]);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        assert!(b.verify().is_ok());
+
+        b.transactions[0].data = b"bar".to_vec();
+        assert!(b.verify().is_err());
+
+        let mut stale = Block::random_block(1);
+        stale.transactions.push(Transaction::new(b"foo".to_vec()));
+        stale.hash(Box::new(BlockHasher::new()));
+        assert!(stale.sign(PrivateKey::generate_key()).is_ok());
+        assert_eq!(stale.header.data, merkle_root(&stale.transactions));
+        assert_eq!(stale.hash, None);
+        assert!(stale.verify().is_ok());
+    }
+
+    #[test]
+    fn test_add_transaction() {
+        let mut b = Block::random_block(1);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        let hash = b.hash(Box::new(BlockHasher::new()));
+
+        let mut tx = Transaction::new(b"foo".to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+        b.add_transaction(tx.clone());
+
+        assert_eq!(b.hash, None);
+        assert!(b.signature.is_none() && b.validator.is_none());
+        assert_eq!(b.header.data, merkle_root(&[tx.clone()]));
+        assert_ne!(b.hash(Box::new(BlockHasher::new())), hash);
+
+        let rebuilt = Block::new(b.header, vec![tx]);
+        assert_eq!(rebuilt.header, b.header);
+    }
+
+    #[test]
+    fn test_total_fees_and_weight() {
+        let mut b = Block::random_block(1);
+        assert_eq!(b.total_fees(), 0);
+        let empty = b.weight();
+        assert_eq!(empty, b.header.as_bytes().unwrap().len() + 1);
+
+        let key = PrivateKey::generate_key();
+        let transfer = Transfer { from: key.generate_public().address(), to: Address::zero(), amount: 10, nonce: 0, fee: 7 };
+        let mut tx = Transaction::transfer(transfer);
+        assert!(tx.sign(key).is_ok());
+        b.add_transaction(tx.clone());
+        assert_eq!(b.total_fees(), 7);
+        assert_eq!(b.weight(), empty + tx.as_bytes().len());
+
+        let mut unpaid = Transaction::new(b"foo".to_vec());
+        assert!(unpaid.sign(PrivateKey::generate_key()).is_ok());
+        b.add_transaction(unpaid);
+        assert_eq!(b.total_fees(), 7);
+        assert!(b.weight() > empty + tx.as_bytes().len());
+    }
+
+    #[test]
+    fn test_hash_tracks_header_changes() {
+        let mut b = Block::random_block(0);
+        let before = b.hash(Box::new(BlockHasher::new()));
+
+        b.header.height = 100;
+        let after = b.hash(Box::new(BlockHasher::new()));
+
+        assert_ne!(before, after);
+        assert_eq!(b.hash, Some(after));
+    }
+
+    #[test]
+    fn test_genesis_is_deterministic() {
+        let mut a = Block::genesis();
+        let mut b = Block::genesis();
+
+        assert_eq!(a.header.height, 0);
+        assert!(a.header.prev_block.is_zero());
+        assert!(a.transactions.is_empty());
+        assert_eq!(a.hash(Box::new(BlockHasher::new())), b.hash(Box::new(BlockHasher::new())));
+    }
+
+    #[test]
+    fn test_random_block_seeded() {
+        let mut a = Block::random_block_seeded(5, 42);
+        let mut b = Block::random_block_seeded(5, 42);
+        assert_eq!(a.header, b.header);
+        assert_eq!(a.hash(Box::new(BlockHasher::new())), b.hash(Box::new(BlockHasher::new())));
+        assert_eq!(a.header.height, 5);
+        assert!(a.header.timestamp >= GENESIS_TIMESTAMP);
+
+        let mut c = Block::random_block_seeded(5, 43);
+        assert_ne!(c.hash(Box::new(BlockHasher::new())), a.hash(Box::new(BlockHasher::new())));
+    }
+
+    #[test]
+    fn test_header_hash() {
+        let mut header = Block::random_block(3).header;
+        header.data = merkle_root(&[]);
+
+        let mut b = Block::new(header, vec![]);
+        assert_eq!(header.hash(), b.hash(Box::new(BlockHasher::new())));
+        assert_eq!(header.hash(), Sha256Hasher.hash(&header.as_bytes().unwrap()));
+
+        header.nonce += 1;
+        assert_ne!(header.hash(), b.header.hash());
+    }
+
+    #[test]
+    fn test_mine_block() {
+        let mut b = Block::random_block(0);
+        b.mine(8);
+
+        assert!(b.meets_difficulty(8));
+        assert_eq!(b.header.difficulty, 8);
+        assert_eq!(b.hash, Some(BlockHasher::new().hash(&b.header).unwrap()));
+        assert!(b.hash.unwrap().leading_zero_bits() >= 8);
+    }
+
     #[test]
     fn test_sign_block() {
         let key = PrivateKey::generate_key();
@@ -202,6 +1163,36 @@ mod test {
         assert!(b.signature.is_some());
     }
 
+    #[test]
+    fn test_sign_stores_compressed_validator() {
+        let key = PrivateKey::generate_key();
+        let mut b = Block::random_block(1);
+        assert!(b.sign(key.clone()).is_ok());
+        assert_eq!(b.validator, Some(key.generate_public()));
+
+        let mut buf = vec![];
+        assert!(b.encode_binary(&mut buf, BlockEncoder::new()).is_ok());
+        let validator = key.generate_public().to_bytes();
+        assert_eq!(validator.len(), 33);
+        assert!(buf.windows(34).any(|w| w[0] == 1 && w[1..] == validator[..]));
+        assert_eq!(encode_decode(&b).validator, b.validator);
+    }
+
+    #[test]
+    fn test_signatures_are_domain_separated() {
+        let key = PrivateKey::generate_key();
+        let public = key.generate_public();
+        let mut b = Block::random_block(1);
+        assert!(b.sign(key).is_ok());
+        let signature = b.signature.unwrap();
+
+        let mut underlying = b.header.as_bytes().unwrap();
+        underlying.extend(public.to_bytes());
+        assert!(public.verify(&[BLOCK_SIGNING_TAG, &underlying].concat(), &signature).is_ok());
+        assert!(public.verify(&[TX_SIGNING_TAG, &underlying].concat(), &signature).is_err());
+        assert!(public.verify(&underlying, &signature).is_err());
+    }
+
     #[test]
     fn test_verify_block() {
         let key = PrivateKey::generate_key();
@@ -221,4 +1212,190 @@ mod test {
 
     }
 
+    #[test]
+    fn test_tx_signatures_digest() {
+        let mut txs = vec![Transaction::new(b"unsigned".to_vec())];
+        for data in ["foo", "bar"] {
+            let mut tx = Transaction::new(data.as_bytes().to_vec());
+            assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+            txs.push(tx);
+        }
+
+        let header = Block::random_block(1).header;
+        let b = Block::new(header, txs.clone());
+        assert_eq!(b.tx_signatures_digest(), Block::new(header, txs.clone()).tx_signatures_digest());
+
+        txs.swap(1, 2);
+        assert_ne!(b.tx_signatures_digest(), Block::new(header, txs.clone()).tx_signatures_digest());
+
+        txs.swap(1, 2);
+        txs.remove(0);
+        assert_eq!(b.tx_signatures_digest(), Block::new(header, txs).tx_signatures_digest());
+    }
+
+    #[test]
+    fn test_add_coinbase() {
+        let to = PrivateKey::generate_key().generate_public().address();
+        let mut tx = Transaction::new(b"foo".to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+
+        let mut b = Block::new(Block::random_block(3).header, vec![tx]);
+        b.add_coinbase(to, 50);
+        assert!(b.coinbase().is_some());
+        assert_eq!(b.header.data, merkle_root(&b.transactions));
+        assert_eq!(b.transactions[0].decode_transfer().unwrap(), Transfer::coinbase(to, 50, 3));
+        assert_eq!(b.verify_transactions(), Ok(()));
+
+        b.transactions.swap(0, 1);
+        assert!(b.coinbase().is_none());
+        assert_eq!(b.verify_transactions(), Err(1));
+    }
+
+    #[test]
+    fn test_reject_second_coinbase() {
+        let to = PrivateKey::generate_key().generate_public().address();
+        let mut b = Block::random_block(1);
+        b.add_coinbase(to, 50);
+        b.add_coinbase(to, 50);
+
+        assert_eq!(b.verify_transactions(), Err(1));
+    }
+
+    struct DoubleSha256Hasher;
+
+    impl hasher::Hasher for DoubleSha256Hasher {
+        fn hash(&self, data: &[u8]) -> Hash {
+            Sha256Hasher.hash(&Sha256Hasher.hash(data).to_vec())
+        }
+    }
+
+    #[test]
+    fn test_block_hash_with_other_hasher() {
+        let mut b = Block::genesis();
+        let sha256 = b.hash(Box::new(BlockHasher::new()));
+        let double = b.hash(Box::new(BlockHasher::with_hasher(DoubleSha256Hasher)));
+
+        assert_ne!(double, sha256);
+        assert_eq!(b.hash, Some(double));
+        assert_eq!(double, Sha256Hasher.hash(&sha256.to_vec()));
+        assert_eq!(b.hash(Box::new(BlockHasher::with_hasher(DoubleSha256Hasher))), double);
+    }
+
+    #[test]
+    fn test_verify_reports_why() {
+        let mut b = Block::new(Block::random_block(1).header, vec![Transaction::new(b"foo".to_vec())]);
+        assert_eq!(b.verify(), Err(BlockError::Unsigned));
+        let unsigned_with_validator = Block { validator: Some(PrivateKey::generate_key().generate_public()), ..b.clone() };
+        assert_eq!(unsigned_with_validator.verify(), Err(BlockError::Unsigned));
+        assert_eq!(b.verify_full(), Err(BlockError::Unsigned.to_string()));
+
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        let mut malleated = b.clone();
+        malleated.signature = malleated.signature.map(|signature| signature.malleate());
+        assert_eq!(malleated.verify(), Err(BlockError::InvalidSignature(VerifyError::BadSignatureEncoding)));
+
+        let mut other_validator = b.clone();
+        other_validator.validator = Some(PrivateKey::generate_key().generate_public());
+        assert_eq!(other_validator.verify(), Err(BlockError::InvalidSignature(VerifyError::Mismatch)));
+
+        b.transactions[0].data = b"bar".to_vec();
+        assert_eq!(b.verify(), Err(BlockError::MerkleMismatch));
+    }
+
+    #[test]
+    fn test_sign_with_other_scheme() {
+        let mut tx = Transaction::new(b"foo".to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+        let mut b = Block::random_block(1);
+        b.transactions.push(tx);
+
+        let (validator, signature) = b.sign_with(&MockKey(*b"key1")).unwrap();
+        assert_eq!(validator, MockKey(*b"key1"));
+        assert_eq!(b.header.data, merkle_root(&b.transactions));
+        assert!(b.verify_with(&validator, &signature).is_ok());
+
+        assert_eq!(b.verify_with(&MockKey(*b"key2"), &signature), Err(BlockError::InvalidSignature(VerifyError::Mismatch)));
+        b.transactions[0].data = b"bar".to_vec();
+        assert_eq!(b.verify_with(&validator, &signature), Err(BlockError::MerkleMismatch));
+    }
+
+    #[test]
+    fn test_sign_with_invalid_key_errors() {
+        // A `PrivateKey` always holds a valid scalar, so a key that cannot
+        // sign is one whose signer fails.
+        struct InvalidKey(PrivateKey);
+
+        impl Signer for InvalidKey {
+            type PublicKey = PublicKey;
+            type Signature = Signature;
+
+            fn public_key(&self) -> PublicKey {
+                self.0.generate_public()
+            }
+
+            fn sign(&self, _: &[u8]) -> Result<Signature, String> {
+                Err("not a key".to_owned())
+            }
+        }
+
+        let mut b = Block::random_block(0);
+        assert_eq!(b.sign_with(&InvalidKey(PrivateKey::generate_key())).err(), Some("not a key".to_owned()));
+        assert!(b.signature.is_none());
+        assert!(b.verify().is_err());
+    }
+
+    #[test]
+    fn test_verify_rejects_duplicate_transactions() {
+        let mut tx = Transaction::new(b"foo".to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+        let hash = TxHasher::new().hash(&tx).unwrap();
+
+        let mut b = Block::new(Block::random_block(1).header, vec![tx.clone()]);
+        assert_eq!(b.duplicate_transaction(), None);
+
+        b.add_transaction(tx);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        assert_eq!(b.duplicate_transaction(), Some(hash));
+        assert_eq!(b.verify(), Err(BlockError::DuplicateTransaction(hash)));
+    }
+
+    #[test]
+    fn test_verify_block_commits_to_validator() {
+        let mut b = Block::random_block(1);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+
+        let mut no_validator = b.clone();
+        no_validator.validator = None;
+        assert_eq!(no_validator.verify(), Err(BlockError::MissingValidator));
+
+        // Another party's signature over the bare header verifies against
+        // nothing once the validator is part of the signed bytes.
+        let other = PrivateKey::generate_key();
+        let header = b.header.as_bytes().unwrap();
+        b.signature = Some(other.sign(&header).unwrap());
+        b.validator = Some(other.generate_public());
+        assert!(b.verify().is_err());
+
+        assert!(b.sign(other).is_ok());
+        assert!(b.verify().is_ok());
+    }
+
+    #[test]
+    fn test_verify_full_block() {
+        let mut txs = vec![];
+        for data in ["foo", "bar", "baz"] {
+            let mut tx = Transaction::new(data.as_bytes().to_vec());
+            assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+            txs.push(tx);
+        }
+        txs[1].data = b"tampered".to_vec();
+
+        let mut b = Block::new(Block::random_block(0).header, txs);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+
+        assert!(b.verify().is_ok());
+        assert_eq!(b.verify_transactions(), Err(1));
+        assert!(b.verify_full().is_err());
+    }
+
 }
\ No newline at end of file
@@ -1,61 +1,665 @@
 
 
-use std::sync::{RwLock, Arc};
+use std::{collections::HashMap, fmt, io, sync::{RwLock, Arc, mpsc::{self, Receiver, Sender}}};
 
-use crate::core::hasher::BlockHasher;
+use chrono::Utc;
 
-use super::{storage::{Storage, MemoryStore}, block::{Header, Block}, validator::{Validator, BlockValidator}};
+use crate::{core::hasher::{BlockHasher, Hasher, TxHasher}, types::hash::Hash, consensus::{retarget, work, validator_set::ValidatorSet}, crypto::keypair::PublicKey, state::account::{AccountState, SupplyPolicy}};
 
-pub struct Blockchain {
-    data: Arc<RwLock<BlockchainData>>
+use super::{storage::{Storage, MemoryStorage}, block::{Header, Block, SignedHeader}, compact::{CompactBlock, MissingTxs}, mempool::Mempool, merkle::merkle_root, transaction::Transaction, validator::{Validator, BlockValidator}};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ChainError {
+    /// The block's height does not extend the current tip by one.
+    InvalidHeight(u32),
+    /// The block is ahead of the tip, so its parent is not known yet. It
+    /// has been kept in the orphan pool and is added once its parent is.
+    UnknownParent(u32),
+    /// The block's `prev_block` is not the hash of the current tip.
+    InvalidPrevHash(u32),
+    /// The block is unsigned or its signature does not verify.
+    InvalidSignature(u32),
+    /// The transaction at the given index is unsigned or does not verify.
+    InvalidTransaction(u32, usize),
+    /// The block was not proposed by the leader for its height.
+    UnexpectedValidator(u32),
+    /// The block's timestamp is not after its parent's, or is too far in
+    /// the future.
+    InvalidTimestamp(u32),
+    /// The block claims a difficulty other than the chain expects at its
+    /// height, or its hash does not meet the claimed difficulty.
+    InvalidDifficulty(u32),
+    /// The block's hash differs from the checkpoint at its height.
+    CheckpointMismatch(u32),
+    /// The block at the given height does not apply to the account state.
+    InvalidState(u32, String),
+    /// The block carries the transaction with this hash more than once.
+    DuplicateTransaction(u32, Hash),
+    /// The block's `state_root` is not the root of the account state after
+    /// applying it.
+    StateRootMismatch(u32),
+    /// The transactions attached to the header at the given height are not
+    /// the ones its `data` commits to.
+    MerkleMismatch(u32),
+    /// The store has no block at the given height.
+    MissingBlock(u32),
+    /// The body of the block at the given height was deleted by `prune`.
+    Pruned(u32),
+    /// The block's cached `hash` is not the hash of its header.
+    HashMismatch(u32),
+    /// The block is ahead of the tip but the orphan pool already holds
+    /// `MAX_ORPHAN_BLOCKS`, so it was dropped.
+    OrphanPoolFull(u32),
+    /// The genesis block is not at height zero or has a nonzero
+    /// `prev_block`.
+    InvalidGenesis,
+    Storage(String),
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainError::InvalidHeight(h) => write!(f, "block {} has an invalid height", h),
+            ChainError::UnknownParent(h) => write!(f, "block {} is waiting for its parent", h),
+            ChainError::InvalidPrevHash(h) => write!(f, "block {} does not link to the previous block", h),
+            ChainError::InvalidSignature(h) => write!(f, "block {} has an invalid signature", h),
+            ChainError::InvalidTransaction(h, i) => write!(f, "block {} has an invalid transaction at index {}", h, i),
+            ChainError::UnexpectedValidator(h) => write!(f, "block {} was not proposed by the expected leader", h),
+            ChainError::InvalidTimestamp(h) => write!(f, "block {} has an invalid timestamp", h),
+            ChainError::InvalidDifficulty(h) => write!(f, "block {} does not meet the expected difficulty", h),
+            ChainError::CheckpointMismatch(h) => write!(f, "block {} conflicts with a checkpoint", h),
+            ChainError::InvalidState(h, e) => write!(f, "block {} does not apply to the account state: {}", h, e),
+            ChainError::DuplicateTransaction(h, hash) => write!(f, "block {} includes transaction {} more than once", h, hash),
+            ChainError::StateRootMismatch(h) => write!(f, "block {} does not match its state root", h),
+            ChainError::MerkleMismatch(h) => write!(f, "transactions do not match the Merkle root of header {}", h),
+            ChainError::MissingBlock(h) => write!(f, "block {} is missing from storage", h),
+            ChainError::Pruned(h) => write!(f, "block {} has been pruned, only its header is kept", h),
+            ChainError::HashMismatch(h) => write!(f, "block {} has a stale cached hash", h),
+            ChainError::OrphanPoolFull(h) => write!(f, "block {} was dropped, the orphan pool is full", h),
+            ChainError::InvalidGenesis => write!(f, "genesis block must be at height 0 with a zero prev_block"),
+            ChainError::Storage(e) => write!(f, "storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ChainError {}
+
+/// A change to the chain, delivered to `Blockchain::subscribe` receivers.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ChainEvent {
+    /// A block with this hash was appended at this height.
+    BlockAdded(Hash, u32),
+    /// The blocks from height `from` up were replaced by a fork whose tip is
+    /// at height `to`.
+    Reorg { from: u32, to: u32 },
 }
 
-pub struct BlockchainData {
-    store: Box<dyn Storage>,
+/// An `AccountState::snapshot` as of the block at `height`, which hashes to
+/// `hash`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StateSnapshot {
+    pub height: u32,
+    pub hash: Hash,
+    pub state: Vec<u8>,
+}
+
+/// How far ahead of the local clock, in seconds, a block timestamp may be
+/// unless configured otherwise.
+pub const DEFAULT_MAX_FUTURE_DRIFT: i64 = 2 * 60 * 60;
+
+/// Seconds between blocks the chain aims for unless configured otherwise.
+pub const DEFAULT_BLOCK_TIME_SECS: i64 = 5;
+
+/// Most blocks held in the orphan pool while waiting for their parents.
+pub const MAX_ORPHAN_BLOCKS: usize = 100;
+
+pub struct Blockchain<S: Storage = MemoryStorage> {
+    data: Arc<RwLock<BlockchainData<S>>>
+}
+
+pub struct BlockchainData<S: Storage> {
+    store: S,
     headers: Vec<Header>,
-    validator:Box<dyn Validator>,
+    validator:Box<dyn Validator<S>>,
+    validators: Option<ValidatorSet>,
+    max_future_drift: i64,
+    block_time_secs: i64,
+    difficulty: u32,
+    /// Target block time in seconds and window, in blocks, to retarget
+    /// over.
+    retarget: Option<(i64, usize)>,
+    orphans: Vec<Block>,
+    subscribers: Vec<Sender<ChainEvent>>,
+    /// Trusted block hashes by height.
+    checkpoints: HashMap<u32, Hash>,
+    /// The account state as of the tip, once `track_state` is called.
+    state: Option<AccountState>,
+    /// What each block may mint, checked by `BlockValidator` and by the
+    /// tracked state.
+    supply_policy: Option<SupplyPolicy>,
+    /// Headers accepted by `add_headers` whose blocks have not been
+    /// attached yet, in height order from the one after the tip.
+    pending_headers: Vec<SignedHeader>,
+    /// Blocks after genesis and below this height have had their bodies
+    /// pruned.
+    pruned_below: u32,
+    /// Transactions of blocks replaced by the last reorg that the fork did
+    /// not include, until `take_rolled_back`.
+    rolled_back: Vec<Transaction>,
+    #[cfg(feature = "metrics")]
+    metrics: crate::metrics::Metrics,
 }
 
-impl Blockchain {
-    pub fn new(genesis: &mut Block) -> Result<Blockchain, ()> {
-            let mut blockchain = Blockchain{
-                data: Arc::new(RwLock::new(BlockchainData { 
-                store: Box::new(MemoryStore::new()), 
-                headers: vec![], 
-                validator: Box::new(BlockValidator::new_validator()),
-                }))
+impl<S: Storage> Blockchain<S> {
+    /// Starts a chain at `genesis`, persisting it to `storage`.
+    pub fn new(genesis: Block, storage: S) -> Result<Blockchain<S>, ChainError> {
+        if genesis.header.height != 0 || !genesis.header.prev_block.is_zero() {
+            return Err(ChainError::InvalidGenesis);
+        }
+
+        let mut blockchain = Blockchain::empty(storage);
+        blockchain.add_block_without_validation(genesis)?;
+        Ok(blockchain)
+    }
+
+    /// Resumes the chain persisted in `storage`, e.g. by a `FileStorage` on
+    /// an earlier run. Blocks are read by height from genesis and the tip is
+    /// the last block of the longest run that checks out as in
+    /// `verify_blocks`, so a stored block that does not extend that run, and
+    /// anything after it, is ignored. Validator, difficulty and checkpoint
+    /// settings start at their defaults, as with `new`.
+    ///
+    /// Blocks right after genesis may have been stripped to their headers by
+    /// `prune`. Only their signatures and links are checked, and they stay
+    /// pruned on the loaded chain.
+    pub fn load(storage: S) -> Result<Blockchain<S>, ChainError> {
+        let mut headers: Vec<Header> = vec![];
+        let mut prev_hash = None;
+        let mut pruned_below = 0;
+        // Pruning only ever strips the blocks before the first it keeps.
+        let mut prunable = true;
+        loop {
+            let height = headers.len() as u32;
+            let block = match storage.get_by_height(height).map_err(|e| ChainError::Storage(e.to_string()))? {
+                Some(block) => block,
+                None => break,
+            };
+            let pruned = height > 0 && prunable && block.is_pruned();
+            let (hash, audited) = if pruned { audit_header(&block) } else { audit_block(&block) };
+            let links = match prev_hash {
+                Some(prev_hash) => block.header.prev_block == prev_hash,
+                None => block.header.prev_block.is_zero(),
             };
-            // blockchain.set_validator(validator);
-            assert!(blockchain.add_block_without_validation(genesis).is_ok());
-            Ok(blockchain)
-        
+            if block.header.height != height || !links || block.hash.is_some_and(|cached| cached != hash) || audited.is_err() {
+                break;
+            }
+            if pruned {
+                pruned_below = height + 1;
+            } else if height > 0 && !block.transactions.is_empty() {
+                prunable = false;
+            }
+            headers.push(block.header);
+            prev_hash = Some(hash);
+        }
+        if headers.is_empty() {
+            return Err(ChainError::InvalidGenesis);
+        }
+
+        let blockchain = Blockchain::empty(storage);
+        let mut bc = blockchain.data.write().unwrap();
+        bc.headers = headers;
+        bc.pruned_below = pruned_below;
+        std::mem::drop(bc);
+        Ok(blockchain)
     }
 
-    pub fn set_validator(&mut self, v: Box<dyn Validator>) {
+    fn empty(storage: S) -> Blockchain<S> {
+        Blockchain {
+            data: Arc::new(RwLock::new(BlockchainData {
+            store: storage,
+            headers: vec![],
+            validator: Box::new(BlockValidator::new_validator()),
+            validators: None,
+            max_future_drift: DEFAULT_MAX_FUTURE_DRIFT,
+            block_time_secs: DEFAULT_BLOCK_TIME_SECS,
+            difficulty: 0,
+            retarget: None,
+            orphans: vec![],
+            subscribers: vec![],
+            checkpoints: HashMap::new(),
+            state: None,
+            supply_policy: None,
+            pending_headers: vec![],
+            pruned_below: 0,
+            rolled_back: vec![],
+            #[cfg(feature = "metrics")]
+            metrics: crate::metrics::Metrics::new(),
+            }))
+        }
+    }
+
+    pub fn set_validator(&mut self, v: Box<dyn Validator<S>>) {
         let mut bc = self.data.write().unwrap();
         bc.validator = v
     }
 
-    pub fn add_block(&mut self, b: &mut Block) -> Result<(), ()> {
+    /// A receiver for every `ChainEvent` from now on. Dropping it simply
+    /// unsubscribes.
+    pub fn subscribe(&self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = mpsc::channel();
+        let mut bc = self.data.write().unwrap();
+        bc.subscribers.push(sender);
+        receiver
+    }
+
+    /// Restricts block proposers to `set`, rotating the leader per height.
+    pub fn set_validator_set(&mut self, set: ValidatorSet) {
+        let mut bc = self.data.write().unwrap();
+        bc.validators = Some(set);
+    }
+
+    /// The metrics this chain updates: `blocks_total`, `transactions_total`
+    /// and `chain_height` as blocks are appended, and
+    /// `invalid_blocks_total` as `add_block` rejects them.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> crate::metrics::Metrics {
+        let bc = self.data.read().unwrap();
+        bc.metrics.clone()
+    }
+
+    /// Updates `metrics` from now on instead, e.g. to share them with a
+    /// mempool.
+    #[cfg(feature = "metrics")]
+    pub fn set_metrics(&mut self, metrics: crate::metrics::Metrics) {
+        let mut bc = self.data.write().unwrap();
+        bc.metrics = metrics;
+    }
+
+    /// Seconds a block timestamp may run ahead of the local clock. A
+    /// `block_time_secs` longer than this is allowed instead.
+    pub fn max_future_drift(&self) -> i64 {
+        let bc = self.data.read().unwrap();
+        bc.max_future_drift
+    }
+
+    pub fn set_max_future_drift(&mut self, seconds: i64) {
+        let mut bc = self.data.write().unwrap();
+        bc.max_future_drift = seconds;
+    }
+
+    /// Seconds the chain expects between blocks.
+    pub fn block_time_secs(&self) -> i64 {
+        let bc = self.data.read().unwrap();
+        bc.block_time_secs
+    }
+
+    /// Panics if `seconds` is negative.
+    pub fn set_block_time_secs(&mut self, seconds: i64) {
+        assert!(seconds >= 0, "block time cannot be negative");
+        let mut bc = self.data.write().unwrap();
+        bc.block_time_secs = seconds;
+    }
+
+    /// When the next block is due: the tip's timestamp plus
+    /// `block_time_secs`.
+    pub fn expected_next_timestamp(&self) -> i64 {
+        let bc = self.data.read().unwrap();
+        let tip = bc.headers.last().expect("chain always has a genesis block");
+        tip.timestamp + bc.block_time_secs
+    }
+
+    /// Trusts `hash` as the block at `height`. Any block added or reorged in
+    /// at that height with another hash is rejected as `CheckpointMismatch`,
+    /// so no fork can rewrite history at or below a checkpoint. Blocks
+    /// already on the chain are not re-checked.
+    pub fn add_checkpoint(&mut self, height: u32, hash: Hash) {
+        let mut bc = self.data.write().unwrap();
+        bc.checkpoints.insert(height, hash);
+    }
+
+    fn check_checkpoint(&self, block: &Block) -> Result<(), ChainError> {
+        let bc = self.data.read().unwrap();
+        let height = block.header.height;
+        match bc.checkpoints.get(&height) {
+            Some(checkpoint) if BlockHasher::new().hash(&block.header).ok() != Some(*checkpoint) => {
+                Err(ChainError::CheckpointMismatch(height))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Replays the chain onto an empty `AccountState` and from then on keeps
+    /// it up to date, rejecting any block whose `state_root` is not the root
+    /// of the state after applying it as `StateRootMismatch`. Blocks already
+    /// on the chain are not re-checked.
+    pub fn track_state(&mut self) -> Result<(), ChainError> {
+        let state = self.state_at(self.height())?;
+        let mut bc = self.data.write().unwrap();
+        bc.state = Some(state);
+        Ok(())
+    }
+
+    /// The tracked account state as of the tip, if `track_state` was called.
+    pub fn state(&self) -> Option<AccountState> {
         let bc = self.data.read().unwrap();
-        assert!(bc.validator.as_ref().validate_block(self, b).is_ok());
+        bc.state.clone()
+    }
+
+    /// Tracks the state in `snapshot`, as `track_state` does but without
+    /// replaying the chain. The snapshot must be of the tip. Snapshots do
+    /// not carry the supply policy, so the chain's is applied again.
+    pub fn restore_state(&mut self, snapshot: &StateSnapshot) -> Result<(), ChainError> {
+        let height = snapshot.height;
+        if height != self.height() || snapshot.hash != self.last_hash() {
+            return Err(ChainError::InvalidState(height, "snapshot is not of the tip".to_owned()));
+        }
+        let mut state = AccountState::restore(&snapshot.state).map_err(|e| ChainError::InvalidState(height, e.to_string()))?;
+        let mut bc = self.data.write().unwrap();
+        if let Some(policy) = bc.supply_policy {
+            state.set_supply_policy(policy);
+        }
+        bc.state = Some(state);
+        Ok(())
+    }
+
+    /// What each block may mint, if configured.
+    pub fn supply_policy(&self) -> Option<SupplyPolicy> {
+        let bc = self.data.read().unwrap();
+        bc.supply_policy
+    }
+
+    /// Rejects any block that changes the total supply by other than
+    /// `policy` allows, whether or not the chain tracks state. A tracked
+    /// state, and any replayed with `track_state` or `snapshot_at`, is held
+    /// to it too. Blocks already on the chain are not re-checked.
+    pub fn set_supply_policy(&mut self, policy: SupplyPolicy) {
+        let mut bc = self.data.write().unwrap();
+        bc.supply_policy = Some(policy);
+        if let Some(state) = bc.state.as_mut() {
+            state.set_supply_policy(policy);
+        }
+    }
+
+    /// `state` with `block` applied, if the block's header commits to it.
+    fn next_state(state: &AccountState, block: &Block) -> Result<AccountState, ChainError> {
+        let height = block.header.height;
+        let mut next = state.clone();
+        next.apply_block(block).map_err(|e| ChainError::InvalidState(height, e.to_string()))?;
+        if next.root() != block.header.state_root {
+            return Err(ChainError::StateRootMismatch(height));
+        }
+        Ok(next)
+    }
+
+    /// The difficulty, in leading zero bits, that the block at `height` must
+    /// claim and meet. Blocks being proposed should use
+    /// `expected_difficulty(height() + 1)`.
+    ///
+    /// Without retargeting this is the configured difficulty. With it, the
+    /// configured difficulty holds for the first `window` blocks, after which
+    /// `consensus::retarget` is applied to the headers before `height`.
+    pub fn expected_difficulty(&self, height: u32) -> u32 {
+        let bc = self.data.read().unwrap();
+        bc.expected_difficulty(&bc.headers, height)
+    }
+
+    /// Sets the difficulty required of new blocks, or the starting one if
+    /// retargeting. Zero, the default, disables proof of work.
+    pub fn set_difficulty(&mut self, difficulty: u32) {
+        let mut bc = self.data.write().unwrap();
+        bc.difficulty = difficulty;
+    }
+
+    /// Adjusts the difficulty every block to keep blocks
+    /// `target_block_time_secs` apart, measured over the last `window`
+    /// blocks.
+    pub fn set_retarget(&mut self, target_block_time_secs: i64, window: usize) {
+        let mut bc = self.data.write().unwrap();
+        bc.retarget = Some((target_block_time_secs, window));
+    }
+
+    /// The key expected to propose the block at `height`, if a validator
+    /// set is configured.
+    pub fn leader_for_height(&self, height: u32) -> Option<PublicKey> {
+        let bc = self.data.read().unwrap();
+        bc.validators.as_ref()?.leader_for_height(height).copied()
+    }
+
+    /// Validates `block` against the current tip and appends it, then
+    /// appends any orphans that now build on the new tip.
+    ///
+    /// A block more than one past the tip is kept in the orphan pool and
+    /// reported as `UnknownParent`, provided it meets its difficulty and its
+    /// signature verifies; once the pool holds `MAX_ORPHAN_BLOCKS`, further
+    /// ones are rejected as `OrphanPoolFull`.
+    /// Blocks conflicting with a checkpoint are rejected before either.
+    pub fn add_block(&mut self, block: Block) -> Result<(), ChainError> {
+        let res = self.try_add_block(block);
+        #[cfg(feature = "metrics")]
+        if let Err(e) = &res {
+            if !matches!(e, ChainError::UnknownParent(_)) {
+                self.data.read().unwrap().metrics.block_rejected();
+            }
+        }
+        res
+    }
+
+    fn try_add_block(&mut self, block: Block) -> Result<(), ChainError> {
+        self.check_checkpoint(&block)?;
+        if block.header.height > self.height() + 1 {
+            return self.add_orphan(block);
+        }
+
+        self.validate_and_add(block)?;
+        self.connect_orphans();
+        Ok(())
+    }
+
+    /// Number of blocks waiting in the orphan pool.
+    pub fn orphan_count(&self) -> usize {
+        let bc = self.data.read().unwrap();
+        bc.orphans.len()
+    }
+
+    /// Accepts `headers` ahead of their blocks, for a headers-first sync.
+    ///
+    /// The headers must be consecutive and extend the tip, or the last
+    /// header accepted before. Each is checked as a block would be for its
+    /// height, link, timestamp, difficulty, checkpoint, signature and
+    /// leader, but nothing that needs the transactions is. Bodies are
+    /// attached in order with `attach_body`. On error no header is accepted.
+    pub fn add_headers(&mut self, headers: Vec<SignedHeader>) -> Result<(), ChainError> {
+        let mut bc = self.data.write().unwrap();
+        let mut known: Vec<Header> = bc.headers.iter().copied().chain(bc.pending_headers.iter().map(|s| s.header)).collect();
+        let max_timestamp = Utc::now().timestamp() + bc.max_future_drift.max(bc.block_time_secs);
+        for signed in &headers {
+            let header = &signed.header;
+            let parent = known.last().expect("chain always has a genesis block");
+            let height = header.height;
+            if height != parent.height + 1 {
+                return Err(ChainError::InvalidHeight(height));
+            }
+            if header.prev_block != parent.hash() {
+                return Err(ChainError::InvalidPrevHash(height));
+            }
+            if header.timestamp <= parent.timestamp || header.timestamp > max_timestamp {
+                return Err(ChainError::InvalidTimestamp(height));
+            }
+            if header.difficulty != bc.expected_difficulty(&known, height)
+                || header.hash().leading_zero_bits() < header.difficulty
+            {
+                return Err(ChainError::InvalidDifficulty(height));
+            }
+            if bc.checkpoints.get(&height).is_some_and(|checkpoint| *checkpoint != header.hash()) {
+                return Err(ChainError::CheckpointMismatch(height));
+            }
+            if let Err(e) = signed.verify() {
+                log::warn!("Rejecting header - height: {}, reason: {}", height, e);
+                return Err(ChainError::InvalidSignature(height));
+            }
+            if bc.validators.as_ref().and_then(|set| set.leader_for_height(height)).is_some_and(|leader| *leader != signed.validator) {
+                return Err(ChainError::UnexpectedValidator(height));
+            }
+            known.push(*header);
+        }
+        bc.pending_headers.extend(headers);
+        Ok(())
+    }
+
+    /// Height of the last header accepted by `add_headers`, or of the tip if
+    /// no header is waiting for its block.
+    pub fn header_height(&self) -> u32 {
+        let bc = self.data.read().unwrap();
+        bc.pending_headers.last().map_or(bc.headers.len() as u32 - 1, |s| s.header.height)
+    }
+
+    /// Appends the block of the accepted header at `height`, which must be
+    /// the one after the tip, with `transactions` as its body.
+    ///
+    /// The transactions must match the header's Merkle root, else
+    /// `MerkleMismatch`. The block, signed as its header was, is then
+    /// validated as in `add_block`.
+    pub fn attach_body(&mut self, height: u32, transactions: Vec<Transaction>) -> Result<(), ChainError> {
+        let signed = {
+            let bc = self.data.read().unwrap();
+            match bc.pending_headers.first() {
+                Some(signed) if signed.header.height == height => *signed,
+                _ => return Err(ChainError::InvalidHeight(height)),
+            }
+        };
+        if merkle_root(&transactions) != signed.header.data {
+            return Err(ChainError::MerkleMismatch(height));
+        }
+
+        self.validate_and_add(signed.into_block(transactions))?;
+        self.connect_orphans();
+        Ok(())
+    }
+
+    fn validate_and_add(&mut self, block: Block) -> Result<(), ChainError> {
+        let bc = self.data.read().unwrap();
+        bc.validator.as_ref().validate_block(self, &block)?;
+        let state = bc.state.as_ref().map(|state| Self::next_state(state, &block)).transpose()?;
         std::mem::drop(bc);
-        self.add_block_without_validation(b)
+        self.append(block, state)
+    }
+
+    /// Pools a block ahead of the tip. Only what needs no parent is checked,
+    /// so the pool cannot be filled with blocks anyone could make up.
+    fn add_orphan(&mut self, block: Block) -> Result<(), ChainError> {
+        let height = block.header.height;
+        if !block.meets_difficulty(block.header.difficulty) {
+            return Err(ChainError::InvalidDifficulty(height));
+        }
+        if block.verify().is_err() {
+            return Err(ChainError::InvalidSignature(height));
+        }
+
+        let mut bc = self.data.write().unwrap();
+        if !bc.orphans.iter().any(|o| o.header == block.header) {
+            if bc.orphans.len() >= MAX_ORPHAN_BLOCKS {
+                return Err(ChainError::OrphanPoolFull(height));
+            }
+            bc.orphans.push(block);
+        }
+        Err(ChainError::UnknownParent(height))
+    }
+
+    /// Repeatedly appends an orphan that builds on the tip, dropping orphans
+    /// that can no longer attach.
+    fn connect_orphans(&mut self) {
+        loop {
+            let height = self.height();
+            let tip = self.last_hash();
+
+            let mut bc = self.data.write().unwrap();
+            bc.orphans.retain(|b| b.header.height > height);
+            let (children, rest) = std::mem::take(&mut bc.orphans)
+                .into_iter()
+                .partition::<Vec<_>, _>(|b| b.header.height == height + 1 && b.header.prev_block == tip);
+            bc.orphans = rest;
+            std::mem::drop(bc);
+
+            if !children.into_iter().any(|b| self.validate_and_add(b).is_ok()) {
+                return;
+            }
+        }
     }
 
-    pub fn get_header(&self, h: u32) -> Header {
-        assert!(self.height() <= h);
+    pub fn get_header(&self, h: u32) -> Option<Header> {
         let bc = self.data.read().unwrap();
-        let header = bc.headers.get(h as usize).cloned();
-        header.unwrap()
+        bc.headers.get(h as usize).cloned()
     }
 
-    pub fn has_block(&self, h: u32) -> Result<(), ()> {
+    /// The full block at height `h` of this chain. Fails with `Pruned` if
+    /// only its header is left.
+    pub fn get_block(&self, h: u32) -> Result<Option<Block>, ChainError> {
+        if !self.has_block(h) {
+            return Ok(None);
+        }
         let bc = self.data.read().unwrap();
-        if h <= self.height() {
+        if bc.is_pruned(h) {
+            return Err(ChainError::Pruned(h));
+        }
+        bc.store.get_by_height(h).map_err(|e| ChainError::Storage(e.to_string()))
+    }
+
+    /// Every block of the chain from genesis to the tip, as `iter_range`.
+    pub fn iter(&self) -> impl Iterator<Item = io::Result<Block>> + '_ {
+        self.iter_range(0, self.height())
+    }
+
+    /// The blocks from height `from` to `to` inclusive, stopping at the tip.
+    /// Each block is read from storage only when the iterator reaches it.
+    /// A block that is pruned or missing from storage is a `NotFound`
+    /// error carrying the `ChainError`.
+    pub fn iter_range(&self, from: u32, to: u32) -> impl Iterator<Item = io::Result<Block>> + '_ {
+        (from..=to.min(self.height())).map(move |h| {
+            let bc = self.data.read().unwrap();
+            let not_found = |e: ChainError| io::Error::new(io::ErrorKind::NotFound, e);
+            if bc.is_pruned(h) {
+                return Err(not_found(ChainError::Pruned(h)));
+            }
+            let header = bc.headers.get(h as usize).ok_or_else(|| not_found(ChainError::MissingBlock(h)))?;
+            bc.store.get_block(&header.hash())?.ok_or_else(|| not_found(ChainError::MissingBlock(h)))
+        })
+    }
+
+    /// Strips from storage the bodies of blocks more than `keep_last`
+    /// below the tip, keeping their headers and signatures. The genesis
+    /// block is always kept whole. `get_block` then fails with `Pruned` for
+    /// those blocks, as does anything replaying the chain from genesis,
+    /// such as `snapshot_at` or a reorg with tracked state. The store can
+    /// still be reopened with `load`.
+    pub fn prune(&mut self, keep_last: u32) -> Result<(), ChainError> {
+        let mut bc = self.data.write().unwrap();
+        let tip = bc.headers.len() as u32 - 1;
+        let below = tip.saturating_sub(keep_last);
+        let from = bc.pruned_below.max(1);
+        if below <= from {
             return Ok(());
         }
-        Err(())
+
+        let hashes: Vec<Hash> = bc.headers[from as usize..below as usize].iter().map(Header::hash).collect();
+        bc.store.prune_blocks(&hashes).map_err(|e| ChainError::Storage(e.to_string()))?;
+        bc.pruned_below = below;
+        Ok(())
+    }
+
+    /// The block hashing to `hash`, if it is part of this chain. Blocks
+    /// replaced by a reorg are still stored but are not returned. Fails
+    /// with `Pruned` as `get_block` does.
+    pub fn get_block_by_hash(&self, hash: &Hash) -> Result<Option<Block>, ChainError> {
+        let bc = self.data.read().unwrap();
+        let block = bc.store.get_block(hash).map_err(|e| ChainError::Storage(e.to_string()))?;
+        match block.filter(|b| bc.headers.get(b.header.height as usize) == Some(&b.header)) {
+            Some(b) if bc.is_pruned(b.header.height) => Err(ChainError::Pruned(b.header.height)),
+            block => Ok(block),
+        }
+    }
+
+    pub fn has_block(&self, h: u32) -> bool {
+        h <= self.height()
     }
 
     pub fn height(&self) -> u32 {
@@ -63,37 +667,1458 @@ impl Blockchain {
         bc.headers.len() as u32 - 1
     }
 
-    pub fn add_block_without_validation(&mut self, b: &mut Block) -> Result<(), ()> {
+    /// Hash of the header at the tip of the chain.
+    pub fn last_hash(&self) -> Hash {
+        let bc = self.data.read().unwrap();
+        let tip = bc.headers.last().expect("chain always has a genesis block");
+        tip.hash()
+    }
+
+    /// Hashes of blocks on this chain for a peer to compare against its own,
+    /// as in Bitcoin's block locators: the tip, then blocks 1, 2, 4, 8, ...
+    /// further back, ending with genesis. The list grows with the log of the
+    /// height, yet still pins down where two chains diverge.
+    pub fn block_locator(&self) -> Vec<Hash> {
+        let bc = self.data.read().unwrap();
+        let hash = |h: usize| bc.headers[h].hash();
+
+        let mut locator = vec![];
+        let mut height = bc.headers.len() - 1;
+        let mut step = 1;
+        while height > 0 {
+            locator.push(hash(height));
+            height = height.saturating_sub(step);
+            step *= 2;
+        }
+        locator.push(hash(0));
+        locator
+    }
+
+    /// The height of the first hash in `locator` that is a block of this
+    /// chain, which for a peer's `block_locator` is the highest block both
+    /// chains share, give or take the locator's spacing. Hashes that cannot
+    /// be looked up are skipped.
+    pub fn find_fork_point(&self, locator: &[Hash]) -> Option<u32> {
+        locator
+            .iter()
+            .find_map(|hash| self.get_block_by_hash(hash).ok().flatten())
+            .map(|b| b.header.height)
+    }
+
+    /// Replays blocks from genesis up to `height` onto an empty
+    /// `AccountState` with `apply_block` and snapshots the result, so a node
+    /// can later restore it instead of replaying the chain.
+    pub fn snapshot_at(&self, height: u32) -> Result<StateSnapshot, ChainError> {
+        let state = self.state_at(height)?;
+        let header = self.get_header(height).ok_or(ChainError::MissingBlock(height))?;
+        let hash = header.hash();
+        Ok(StateSnapshot { height, hash, state: state.snapshot() })
+    }
+
+    fn state_at(&self, height: u32) -> Result<AccountState, ChainError> {
+        let mut state = AccountState::new();
+        if let Some(policy) = self.supply_policy() {
+            state.set_supply_policy(policy);
+        }
+        for h in 0..=height {
+            let block = self.get_block(h)?.ok_or(ChainError::MissingBlock(h))?;
+            state.apply_block(&block).map_err(|e| ChainError::InvalidState(h, e.to_string()))?;
+        }
+        Ok(state)
+    }
+
+    /// Re-checks every stored block from genesis to the tip with
+    /// `verify_blocks`.
+    pub fn validate_chain(&self) -> Result<(), ChainError> {
+        let bc = self.data.read().unwrap();
+        let blocks = (0..bc.headers.len() as u32)
+            .map(|height| {
+                if bc.is_pruned(height) {
+                    return Err(ChainError::Pruned(height));
+                }
+                bc.store
+                    .get_by_height(height)
+                    .map_err(|e| ChainError::Storage(e.to_string()))?
+                    .ok_or(ChainError::MissingBlock(height))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::verify_blocks(&blocks)
+    }
+
+    /// Checks that `blocks` form a valid chain from genesis: heights count
+    /// up from zero, each block links to the hash of the one before it, any
+    /// cached hash is current, every block after genesis is signed, and all
+    /// transactions verify. Reports the first problem by height.
+    pub fn verify_blocks(blocks: &[Block]) -> Result<(), ChainError> {
+        audit_links(blocks, blocks.iter().map(audit_block))
+    }
+
+    /// Same result as `verify_blocks`, but hashes and checks signatures on
+    /// all blocks concurrently; only the linkage walk is sequential.
+    #[cfg(feature = "parallel")]
+    pub fn verify_blocks_parallel(blocks: &[Block]) -> Result<(), ChainError> {
+        use rayon::prelude::*;
+
+        let audited: Vec<_> = blocks.par_iter().map(audit_block).collect();
+        audit_links(blocks, audited.into_iter())
+    }
+
+    /// Rebuilds the block `compact` announces, taking each transaction from
+    /// its `prefilled` transactions or else from `mempool`. Fails with the
+    /// hashes of any found in neither, e.g. to request them from the peer.
+    /// The block is not verified; `add_block` does that.
+    pub fn reconstruct(compact: CompactBlock, mempool: &Mempool) -> Result<Block, MissingTxs> {
+        let hasher = TxHasher::new();
+        let prefilled: HashMap<Hash, Transaction> = compact
+            .prefilled
+            .into_iter()
+            .map(|tx| (hasher.hash(&tx).expect("could not hash"), tx))
+            .collect();
+
+        let mut transactions = vec![];
+        let mut missing = vec![];
+        for hash in &compact.tx_hashes {
+            match prefilled.get(hash).cloned().or_else(|| mempool.get(hash)) {
+                Some(tx) => transactions.push(tx),
+                None => missing.push(*hash),
+            }
+        }
+        if !missing.is_empty() {
+            return Err(MissingTxs(missing));
+        }
+
+        Ok(Block {
+            header: compact.header,
+            transactions,
+            signature: compact.signature,
+            validator: compact.validator,
+            hash: None,
+            prev_hash: None,
+        })
+    }
+
+    /// Sum of the `consensus::work` of every block's difficulty, saturating
+    /// at `u128::MAX`. `try_reorg` prefers the chain with the most.
+    pub fn total_work(&self) -> u128 {
+        let bc = self.data.read().unwrap();
+        chain_work(&bc.headers)
+    }
+
+    /// Switches to `fork` if it is a valid branch off this chain with more
+    /// total work than the blocks it replaces, which a shorter fork of
+    /// harder blocks can have.
+    ///
+    /// `fork` must be consecutive blocks whose first block builds on a block
+    /// of this chain. Each fork block is checked by the chain's validator as
+    /// if it were being added in turn. Returns whether the chain switched;
+    /// the transactions of the replaced blocks that the fork does not
+    /// include are then kept for `take_rolled_back`, so they can go back to
+    /// the mempool. Returns `false`, leaving the chain untouched, if the fork
+    /// has no more work or does not attach; a fork that attaches but fails
+    /// validation is an error, and also leaves the chain untouched.
+    pub fn try_reorg(&mut self, fork: Vec<Block>) -> Result<bool, ChainError> {
+        let first = match fork.first() {
+            Some(first) => first.header,
+            None => return Ok(false),
+        };
+        if first.height == 0 || first.height > self.height() + 1 {
+            return Ok(false);
+        }
+
+        let start = first.height as usize;
+        let ancestor = self.get_header(first.height - 1).expect("height is within the chain");
+        if ancestor.hash() != first.prev_block {
+            return Ok(false);
+        }
+        let fork_headers: Vec<Header> = fork.iter().map(|b| b.header).collect();
+        let last = *fork_headers.last().expect("fork is not empty");
+        if chain_work(&fork_headers) <= chain_work(&self.data.read().unwrap().headers[start..]) {
+            return Ok(false);
+        }
+        for b in &fork {
+            self.check_checkpoint(b)?;
+        }
+        let state = match self.state() {
+            Some(_) => {
+                let mut state = self.state_at(first.height - 1)?;
+                for b in &fork {
+                    state = Self::next_state(&state, b)?;
+                }
+                Some(state)
+            }
+            None => None,
+        };
+
+        // Everything that can fail on storage is read before the headers
+        // change, and a failed write puts them back, so an error leaves the
+        // chain as it was.
+        let mut replaced_blocks = vec![];
+        {
+            let bc = self.data.read().unwrap();
+            for header in &bc.headers[start..] {
+                if let Some(b) = bc.store.get_block(&header.hash()).map_err(|e| ChainError::Storage(e.to_string()))? {
+                    replaced_blocks.push(b);
+                }
+            }
+        }
+
+        let replaced = self.data.write().unwrap().headers.split_off(start);
+        for b in &fork {
+            let bc = self.data.read().unwrap();
+            let res = bc.validator.as_ref().validate_block(self, b);
+            std::mem::drop(bc);
+
+            let mut bc = self.data.write().unwrap();
+            if let Err(e) = res {
+                bc.headers.truncate(start);
+                bc.headers.extend(replaced);
+                return Err(e);
+            }
+            bc.headers.push(b.header);
+        }
+
+        let mut bc = self.data.write().unwrap();
+        let mut included = std::collections::HashSet::new();
+        for b in &fork {
+            log::info!("Reorg block - height: {}, hash: {}", b.header.height, b.header.hash());
+            for tx in &b.transactions {
+                included.insert(TxHasher::new().hash(tx).expect("could not hash"));
+            }
+            if let Err(e) = bc.store.put_block(b) {
+                bc.headers.truncate(start);
+                bc.headers.extend(replaced);
+                for old in &replaced_blocks {
+                    if let Err(e) = bc.store.put_block(old) {
+                        log::warn!("Restoring block - height: {}, reason: {}", old.header.height, e);
+                    }
+                }
+                return Err(ChainError::Storage(e.to_string()));
+            }
+        }
+
+        bc.pending_headers.clear();
+        // The fork's bodies are stored above, pruned or not.
+        bc.pruned_below = bc.pruned_below.min(first.height);
+        let mut rolled_back: Vec<Transaction> = replaced_blocks.into_iter().flat_map(|b| b.transactions).collect();
+        rolled_back.retain(|tx| !included.contains(&TxHasher::new().hash(tx).expect("could not hash")));
+        bc.rolled_back = rolled_back;
+        if state.is_some() {
+            bc.state = state;
+        }
+        bc.publish(ChainEvent::Reorg { from: first.height, to: last.height });
+        Ok(true)
+    }
+
+    /// Takes the transactions the last `try_reorg` switch rolled back,
+    /// leaving none until the next one.
+    pub fn take_rolled_back(&mut self) -> Vec<Transaction> {
+        let mut bc = self.data.write().unwrap();
+        std::mem::take(&mut bc.rolled_back)
+    }
+
+    /// Appends `b` without any checks. A tracked account state still has
+    /// the block applied, though its `state_root` is not checked.
+    pub fn add_block_without_validation(&mut self, b: Block) -> Result<(), ChainError> {
+        let state = match self.state() {
+            Some(mut state) => {
+                state.apply_block(&b).map_err(|e| ChainError::InvalidState(b.header.height, e.to_string()))?;
+                Some(state)
+            }
+            None => None,
+        };
+        self.append(b, state)
+    }
+
+    /// Stores `b` as the new tip, with `state` as the tracked state after it.
+    fn append(&mut self, mut b: Block, state: Option<AccountState>) -> Result<(), ChainError> {
         let mut bc = self.data.write().unwrap();
         let height = b.header.height;
-        log::info!("Adding block - height: {}, hash: {}", height, b.hash(Box::new(BlockHasher::new())));
+        let hash = b.hash(Box::new(BlockHasher::new()));
+        log::info!("Adding block - height: {}, hash: {}", height, hash);
 
         bc.headers.push(b.header);
-        bc.store.put(b)
+        // A block other than the next pending header leaves the pending
+        // headers on a fork, so they are dropped.
+        match bc.pending_headers.first() {
+            Some(signed) if signed.header == b.header => {
+                bc.pending_headers.remove(0);
+            }
+            Some(_) => bc.pending_headers.clear(),
+            None => {}
+        }
+        bc.store.put_block(&b).map_err(|e| ChainError::Storage(e.to_string()))?;
+        if state.is_some() {
+            bc.state = state;
+        }
+        #[cfg(feature = "metrics")]
+        bc.metrics.block_added(height, b.transactions.len());
+        bc.publish(ChainEvent::BlockAdded(hash, height));
+        Ok(())
     }
  }
 
+impl<S: Storage> BlockchainData<S> {
+    /// The difficulty the block at `height` must meet on a chain whose
+    /// headers, from genesis, are `headers`.
+    fn expected_difficulty(&self, headers: &[Header], height: u32) -> u32 {
+        match self.retarget {
+            Some((target_block_time_secs, window)) if height as usize > window => {
+                let end = (height as usize).min(headers.len());
+                retarget(&headers[..end], target_block_time_secs, window)
+            }
+            _ => self.difficulty,
+        }
+    }
+
+    /// Whether the body of the block at `h` has been pruned.
+    fn is_pruned(&self, h: u32) -> bool {
+        h > 0 && h < self.pruned_below
+    }
+
+    /// Sends `event` to every subscriber, forgetting those whose receiver
+    /// has been dropped.
+    fn publish(&mut self, event: ChainEvent) {
+        self.subscribers.retain(|s| s.send(event).is_ok());
+    }
+}
+
+/// A `Blockchain` that can be shared between threads, such as the HTTP API
+/// and a network task.
+///
+/// Every method takes the lock, does its work and releases it before
+/// returning, so no guard ever escapes to the caller and none can be held
+/// across an `.await` or while waiting on another lock. Reads share the lock;
+/// `add_block` takes it exclusively. For anything else, `read` and `write`
+/// run a closure under the lock, which likewise cannot leak the guard.
+pub struct SharedChain<S: Storage = MemoryStorage> {
+    chain: Arc<RwLock<Blockchain<S>>>,
+}
+
+impl<S: Storage> Clone for SharedChain<S> {
+    fn clone(&self) -> Self {
+        SharedChain { chain: Arc::clone(&self.chain) }
+    }
+}
+
+impl<S: Storage> SharedChain<S> {
+    pub fn new(chain: Blockchain<S>) -> Self {
+        SharedChain { chain: Arc::new(RwLock::new(chain)) }
+    }
+
+    pub fn read_height(&self) -> u32 {
+        self.read(|bc| bc.height())
+    }
+
+    pub fn add_block(&self, block: Block) -> Result<(), ChainError> {
+        self.write(|bc| bc.add_block(block))
+    }
+
+    pub fn read<T>(&self, f: impl FnOnce(&Blockchain<S>) -> T) -> T {
+        f(&self.chain.read().unwrap())
+    }
+
+    pub fn write<T>(&self, f: impl FnOnce(&mut Blockchain<S>) -> T) -> T {
+        f(&mut self.chain.write().unwrap())
+    }
+}
+
+/// The total work of `headers`, the sum of each one's `work`, which fork
+/// choice compares. Saturates rather than overflowing.
+fn chain_work(headers: &[Header]) -> u128 {
+    headers.iter().map(|h| work(h.difficulty)).fold(0, u128::saturating_add)
+}
+
+/// Hashes a block and checks its signatures, the independent half of an
+/// audit.
+fn audit_block(b: &Block) -> (Hash, Result<(), ChainError>) {
+    let hash = b.header.hash();
+    let height = b.header.height;
+
+    let signatures = if !b.meets_difficulty(b.header.difficulty) {
+        Err(ChainError::InvalidDifficulty(height))
+    } else if height > 0 && b.verify().is_err() {
+        Err(ChainError::InvalidSignature(height))
+    } else {
+        b.verify_transactions().map_err(|i| ChainError::InvalidTransaction(height, i))
+    };
+    (hash, signatures)
+}
+
+/// What `audit_block` can check of a block pruned to its header: the
+/// difficulty and the header signature.
+fn audit_header(b: &Block) -> (Hash, Result<(), ChainError>) {
+    let hash = b.header.hash();
+    let height = b.header.height;
+
+    let signature = if !b.meets_difficulty(b.header.difficulty) {
+        Err(ChainError::InvalidDifficulty(height))
+    } else {
+        match b.signed_header() {
+            Ok(signed) if signed.verify().is_ok() => Ok(()),
+            _ => Err(ChainError::InvalidSignature(height)),
+        }
+    };
+    (hash, signature)
+}
+
+/// Walks `blocks` in order checking heights, links and cached hashes, and
+/// interleaves the `audit_block` result for each so the first failing height
+/// wins.
+fn audit_links<I>(blocks: &[Block], audited: I) -> Result<(), ChainError>
+where
+    I: Iterator<Item = (Hash, Result<(), ChainError>)>,
+{
+    let mut prev_hash = None;
+    for (height, (block, (hash, signatures))) in (0u32..).zip(blocks.iter().zip(audited)) {
+        if block.header.height != height {
+            return Err(ChainError::InvalidHeight(height));
+        }
+
+        if let Some(prev_hash) = prev_hash {
+            if block.header.prev_block != prev_hash {
+                return Err(ChainError::InvalidPrevHash(height));
+            }
+        }
+
+        if block.hash.is_some_and(|cached| cached != hash) {
+            return Err(ChainError::HashMismatch(height));
+        }
+
+        signatures?;
+        prev_hash = Some(hash);
+    }
+    Ok(())
+}
 
 #[cfg(test)]
-mod test {
-    use crate::core::block::Block;
+pub(crate) mod test {
+    use chrono::Utc;
+
+    use crate::{core::{block::{Block, Header, SignedHeader}, transaction::Transaction, transfer::Transfer}, crypto::keypair::PrivateKey, state::account::{AccountState, FeePolicy, StateError, SupplyPolicy}, consensus::validator_set::ValidatorSet, types::hash::Hash, core::hasher::{BlockHasher, Hasher, TxHasher}};
 
-    use super::Blockchain;
+    use super::{Blockchain, ChainError, CompactBlock, Mempool, MissingTxs, ChainEvent, DEFAULT_BLOCK_TIME_SECS, DEFAULT_MAX_FUTURE_DRIFT, MAX_ORPHAN_BLOCKS, MemoryStorage, SharedChain, Storage};
 
     fn new_blockchain_with_genesis() -> Blockchain {
-        let bc = Blockchain::new(&mut Block::random_block(0));
+        let bc = Blockchain::new(Block::genesis(), MemoryStorage::new());
         assert!(bc.is_ok());
         bc.unwrap()
     }
 
+    #[test]
+    fn test_new() {
+        let genesis = Block::genesis();
+        let bc = Blockchain::new(genesis.clone(), MemoryStorage::new()).unwrap();
+        assert_eq!(bc.height(), 0);
+        assert_eq!(bc.get_header(0), Some(genesis.header));
+        assert_eq!(stored_blocks(&bc).len(), 1);
+
+        let mut b = Block::genesis();
+        b.header.height = 1;
+        assert_eq!(Blockchain::new(b, MemoryStorage::new()).err(), Some(ChainError::InvalidGenesis));
+
+        let mut b = Block::genesis();
+        b.header.prev_block = Hash::random();
+        assert_eq!(Blockchain::new(b, MemoryStorage::new()).err(), Some(ChainError::InvalidGenesis));
+    }
+
+    fn tip<S: Storage>(bc: &Blockchain<S>) -> Header {
+        bc.get_header(bc.height()).unwrap()
+    }
+
+    /// An unsigned block extending the tip, one second after it.
+    fn unsigned_next_block<S: Storage>(bc: &Blockchain<S>) -> Block {
+        let mut b = Block::random_block(bc.height() + 1);
+        b.header.prev_block = bc.last_hash();
+        b.header.timestamp = tip(bc).timestamp + 1;
+        b
+    }
+
+    fn next_block<S: Storage>(bc: &Blockchain<S>) -> Block {
+        let mut b = unsigned_next_block(bc);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        b
+    }
+
     #[test]
     fn test_add_block() {
         let mut bc = new_blockchain_with_genesis();
 
         let len = 1000;
-        for i in 1..len+1 {
-            let mut rand = Block::random_block_with_signature(i);
-            assert!(bc.add_block(&mut rand).is_ok());
+        for _ in 1..len+1 {
+            let b = next_block(&bc);
+            assert!(bc.add_block(b).is_ok());
         }
+        assert_eq!(bc.height(), len);
+        assert!(bc.has_block(len));
+        assert!(!bc.has_block(len + 1));
     }
- }
\ No newline at end of file
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn test_load() {
+        use crate::core::storage::FileStorage;
+
+        let path = std::env::temp_dir().join(format!("blockchain-{}.db", Hash::random()));
+        let mut bc = Blockchain::new(Block::genesis(), FileStorage::open(&path).unwrap()).unwrap();
+        for _ in 0..5 {
+            let b = next_block(&bc);
+            assert!(bc.add_block(b).is_ok());
+        }
+        let last_hash = bc.last_hash();
+        drop(bc);
+
+        let mut storage = FileStorage::open(&path).unwrap();
+        // Neither builds on the tip, so both are ignored.
+        assert!(storage.put_block(&Block::random_block(6)).is_ok());
+        assert!(storage.put_block(&Block::random_block(9)).is_ok());
+
+        let mut bc = Blockchain::load(storage).unwrap();
+        assert_eq!(bc.height(), 5);
+        assert_eq!(bc.last_hash(), last_hash);
+        let b = next_block(&bc);
+        assert!(bc.add_block(b).is_ok());
+        assert_eq!(bc.height(), 6);
+
+        assert_eq!(Blockchain::load(MemoryStorage::new()).err(), Some(ChainError::InvalidGenesis));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_expected_next_timestamp() {
+        let mut bc = chain_of(2);
+        let tip = bc.get_header(2).unwrap().timestamp;
+        assert_eq!(bc.block_time_secs(), DEFAULT_BLOCK_TIME_SECS);
+        assert_eq!(bc.expected_next_timestamp(), tip + DEFAULT_BLOCK_TIME_SECS);
+
+        bc.set_block_time_secs(60);
+        assert_eq!(bc.expected_next_timestamp(), tip + 60);
+
+        // With the tip at the current time, a block further ahead of the
+        // clock than the drift allows is rejected, unless the next block is
+        // only due then.
+        bc.set_max_future_drift(0);
+        let now = Utc::now().timestamp();
+        let mut b = unsigned_next_block(&bc);
+        b.header.timestamp = now;
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        assert!(bc.add_block(b).is_ok());
+
+        let mut b = unsigned_next_block(&bc);
+        b.header.timestamp = now + 600;
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        assert_eq!(bc.add_block(b.clone()), Err(ChainError::InvalidTimestamp(4)));
+
+        bc.set_block_time_secs(600);
+        assert_eq!(bc.expected_next_timestamp(), b.header.timestamp);
+        assert!(bc.add_block(b).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "block time cannot be negative")]
+    fn test_negative_block_time_panics() {
+        new_blockchain_with_genesis().set_block_time_secs(-1);
+    }
+
+    #[test]
+    fn test_iter() {
+        let bc = chain_of(5);
+        assert_eq!(bc.iter().count() as u32, bc.height() + 1);
+        let heights: Vec<u32> = bc.iter().map(|b| b.unwrap().header.height).collect();
+        assert_eq!(heights, (0..=5).collect::<Vec<_>>());
+        for b in bc.iter() {
+            let b = b.unwrap();
+            assert_eq!(Some(b.header), bc.get_header(b.header.height));
+        }
+
+        let heights: Vec<u32> = bc.iter_range(2, 4).map(|b| b.unwrap().header.height).collect();
+        assert_eq!(heights, [2, 3, 4]);
+        assert_eq!(bc.iter_range(4, 100).count(), 2);
+        assert_eq!(bc.iter_range(3, 2).count(), 0);
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut bc = chain_of(6);
+        let header = bc.get_header(2).unwrap();
+        assert!(bc.prune(10).is_ok());
+        assert!(bc.get_block(0).unwrap().is_some());
+
+        assert!(bc.prune(3).is_ok());
+        assert!(bc.get_block(0).unwrap().is_some());
+        for h in 1..3 {
+            assert_eq!(bc.get_block(h), Err(ChainError::Pruned(h)));
+        }
+        assert_eq!(bc.get_header(2), Some(header));
+        assert!(bc.get_block(3).unwrap().is_some());
+        assert_eq!(bc.get_block_by_hash(&header.hash()), Err(ChainError::Pruned(2)));
+        assert_eq!(bc.validate_chain(), Err(ChainError::Pruned(1)));
+        assert_eq!(bc.snapshot_at(6).err(), Some(ChainError::Pruned(1)));
+        assert_eq!(bc.iter().filter(|b| b.is_err()).count(), 2);
+        assert_eq!(bc.iter().nth(1).unwrap().unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+        // Pruning less than before keeps what is already gone pruned.
+        assert!(bc.prune(5).is_ok());
+        assert_eq!(bc.get_block(2), Err(ChainError::Pruned(2)));
+
+        let b = next_block(&bc);
+        assert!(bc.add_block(b).is_ok());
+        assert!(bc.prune(3).is_ok());
+        assert_eq!(bc.get_block(3), Err(ChainError::Pruned(3)));
+        assert!(bc.get_block(4).unwrap().is_some());
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn test_load_after_prune() {
+        use crate::core::storage::FileStorage;
+
+        let path = std::env::temp_dir().join(format!("blockchain-{}.db", Hash::random()));
+        let mut bc = Blockchain::new(Block::genesis(), FileStorage::open(&path).unwrap()).unwrap();
+        for _ in 0..6 {
+            let b = block_with_transactions(&bc, 2);
+            assert!(bc.add_block(b).is_ok());
+        }
+        assert!(bc.prune(2).is_ok());
+        let last_hash = bc.last_hash();
+        drop(bc);
+
+        let mut bc = Blockchain::load(FileStorage::open(&path).unwrap()).unwrap();
+        assert_eq!(bc.height(), 6);
+        assert_eq!(bc.last_hash(), last_hash);
+        assert!(bc.get_block(0).unwrap().is_some());
+        for h in 1..4 {
+            assert_eq!(bc.get_block(h), Err(ChainError::Pruned(h)));
+        }
+        assert_eq!(bc.get_block(4).unwrap().unwrap().transactions.len(), 3);
+        let b = block_with_transactions(&bc, 1);
+        assert!(bc.add_block(b).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// A signed block extending the tip with a coinbase and `n` signed
+    /// transactions.
+    fn block_with_transactions<S: Storage>(bc: &Blockchain<S>, n: u8) -> Block {
+        let mut transactions = vec![];
+        for i in 0..n {
+            let mut tx = Transaction::new(vec![i]);
+            assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+            transactions.push(tx);
+        }
+        let mut b = Block::new(unsigned_next_block(bc).header, transactions);
+        b.add_coinbase(PrivateKey::generate_key().generate_public().address(), 50);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        b
+    }
+
+    #[test]
+    fn test_reconstruct() {
+        let mut bc = new_blockchain_with_genesis();
+        let b = block_with_transactions(&bc, 3);
+        let mut mempool = Mempool::new();
+        for tx in &b.transactions[1..] {
+            assert!(mempool.add(tx.clone()).is_ok());
+        }
+
+        let compact = CompactBlock::from_block(&b);
+        assert_eq!(compact.tx_hashes.len(), 4);
+        assert_eq!(compact.prefilled, vec![b.transactions[0].clone()]);
+
+        let rebuilt = <Blockchain>::reconstruct(compact, &mempool).unwrap();
+        assert_eq!(rebuilt.transactions, b.transactions);
+        assert!(rebuilt.verify().is_ok());
+        assert!(bc.add_block(rebuilt).is_ok());
+    }
+
+    #[test]
+    fn test_reconstruct_reports_missing() {
+        let bc = new_blockchain_with_genesis();
+        let b = block_with_transactions(&bc, 3);
+        let mut mempool = Mempool::new();
+        for tx in [&b.transactions[1], &b.transactions[3]] {
+            assert!(mempool.add(tx.clone()).is_ok());
+        }
+
+        let missing = TxHasher::new().hash(&b.transactions[2]).unwrap();
+        let compact = CompactBlock::from_block(&b);
+        assert_eq!(<Blockchain>::reconstruct(compact, &mempool), Err(MissingTxs(vec![missing])));
+    }
+
+    #[test]
+    fn test_headers_first_sync() {
+        let mut source = new_blockchain_with_genesis();
+        let mut blocks = vec![];
+        for _ in 0..10 {
+            let b = block_with_transactions(&source, 2);
+            assert!(source.add_block(b.clone()).is_ok());
+            blocks.push(b);
+        }
+        let headers: Vec<SignedHeader> = blocks.iter().map(|b| b.signed_header().unwrap()).collect();
+
+        let mut bc = new_blockchain_with_genesis();
+        let mut gap = headers.clone();
+        gap.remove(4);
+        assert_eq!(bc.add_headers(gap), Err(ChainError::InvalidHeight(6)));
+        let mut forged = headers.clone();
+        forged[2].header.prev_block = Hash::random();
+        assert_eq!(bc.add_headers(forged), Err(ChainError::InvalidPrevHash(3)));
+        let mut resigned = headers.clone();
+        resigned[3].validator = PrivateKey::generate_key().generate_public();
+        assert_eq!(bc.add_headers(resigned), Err(ChainError::InvalidSignature(4)));
+        let mut set = ValidatorSet::new();
+        set.add(PrivateKey::generate_key().generate_public());
+        bc.set_validator_set(set);
+        assert_eq!(bc.add_headers(headers.clone()), Err(ChainError::UnexpectedValidator(1)));
+        bc.set_validator_set(ValidatorSet::new());
+        assert_eq!(bc.header_height(), 0);
+
+        assert!(bc.add_headers(headers).is_ok());
+        assert_eq!(bc.header_height(), 10);
+        assert_eq!(bc.height(), 0);
+
+        let mut mismatched = blocks[0].transactions.clone();
+        mismatched.pop();
+        assert_eq!(bc.attach_body(1, mismatched), Err(ChainError::MerkleMismatch(1)));
+        assert_eq!(bc.attach_body(2, blocks[1].transactions.clone()), Err(ChainError::InvalidHeight(2)));
+
+        assert!(bc.attach_body(1, blocks[0].transactions.clone()).is_ok());
+        assert_eq!(bc.height(), 1);
+        assert_eq!(bc.last_hash(), source.get_header(1).unwrap().hash());
+        assert_eq!(bc.get_block(1).unwrap().unwrap().transactions, blocks[0].transactions);
+        assert_eq!(bc.header_height(), 10);
+
+        for b in &blocks[1..] {
+            assert!(bc.attach_body(b.header.height, b.transactions.clone()).is_ok());
+        }
+        assert_eq!(bc.height(), 10);
+        assert_eq!(bc.validate_chain(), Ok(()));
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn test_load_after_headers_first_sync() {
+        use crate::core::storage::FileStorage;
+
+        let mut source = new_blockchain_with_genesis();
+        let mut blocks = vec![];
+        for _ in 0..3 {
+            let b = block_with_transactions(&source, 2);
+            assert!(source.add_block(b.clone()).is_ok());
+            blocks.push(b);
+        }
+
+        let path = std::env::temp_dir().join(format!("blockchain-{}.db", Hash::random()));
+        let mut bc = Blockchain::new(Block::genesis(), FileStorage::open(&path).unwrap()).unwrap();
+        assert!(bc.add_headers(blocks.iter().map(|b| b.signed_header().unwrap()).collect()).is_ok());
+        for b in &blocks {
+            assert!(bc.attach_body(b.header.height, b.transactions.clone()).is_ok());
+        }
+        drop(bc);
+
+        let bc = Blockchain::load(FileStorage::open(&path).unwrap()).unwrap();
+        assert_eq!(bc.height(), 3);
+        assert_eq!(bc.last_hash(), source.last_hash());
+        assert_eq!(bc.validate_chain(), Ok(()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_add_block_updates_metrics() {
+        let mut bc = new_blockchain_with_genesis();
+        let metrics = bc.metrics();
+        let before = metrics.snapshot()["blocks_total"];
+
+        assert!(bc.add_block(next_block(&bc)).is_ok());
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot["blocks_total"], before + 1.0);
+        assert_eq!(snapshot["chain_height"], 1.0);
+
+        let mut bad = next_block(&bc);
+        bad.header.timestamp += 1;
+        assert!(bc.add_block(bad).is_err());
+        assert_eq!(metrics.snapshot()["invalid_blocks_total"], 1.0);
+        assert_eq!(metrics.snapshot()["blocks_total"], before + 1.0);
+    }
+
+    #[test]
+    fn test_get_header() {
+        let mut bc = new_blockchain_with_genesis();
+        let b = next_block(&bc);
+        let header = b.header;
+
+        assert!(bc.add_block(b).is_ok());
+        assert_eq!(bc.get_header(1), Some(header));
+        assert_eq!(bc.get_header(2), None);
+    }
+
+    #[test]
+    fn test_get_block() {
+        let mut bc = new_blockchain_with_genesis();
+        let mut b = next_block(&bc);
+        let hash = b.hash(Box::new(BlockHasher::new()));
+        assert!(bc.add_block(b.clone()).is_ok());
+
+        assert_eq!(bc.get_block(1), Ok(Some(b.clone())));
+        assert_eq!(bc.get_block(2), Ok(None));
+        assert_eq!(bc.get_block_by_hash(&hash), Ok(Some(b)));
+        assert_eq!(bc.get_block_by_hash(&Hash::random()), Ok(None));
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let mut bc = new_blockchain_with_genesis();
+        let events = bc.subscribe();
+        drop(bc.subscribe());
+
+        let mut b = next_block(&bc);
+        let hash = b.hash(Box::new(BlockHasher::new()));
+        assert!(bc.add_block(b).is_ok());
+        assert_eq!(events.try_recv(), Ok(ChainEvent::BlockAdded(hash, 1)));
+        assert!(events.try_recv().is_err());
+
+        drop(events);
+        let b = next_block(&bc);
+        assert!(bc.add_block(b).is_ok());
+        assert_eq!(bc.height(), 2);
+    }
+
+    #[test]
+    fn test_add_block_out_of_order() {
+        let mut bc = new_blockchain_with_genesis();
+
+        let mut b = Block::random_block(2);
+        b.header.prev_block = bc.last_hash();
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+
+        assert_eq!(bc.add_block(b), Err(ChainError::UnknownParent(2)));
+        assert_eq!(bc.height(), 0);
+        assert_eq!(bc.orphan_count(), 1);
+    }
+
+    #[test]
+    fn test_add_block_connects_orphans() {
+        let mut bc = chain_of(3);
+        let blocks = branch(tip(&bc), 4..=6, &[]);
+
+        assert_eq!(bc.add_block(blocks[2].clone()), Err(ChainError::UnknownParent(6)));
+        assert_eq!(bc.add_block(blocks[1].clone()), Err(ChainError::UnknownParent(5)));
+        assert_eq!(bc.height(), 3);
+
+        assert!(bc.add_block(blocks[0].clone()).is_ok());
+        assert_eq!(bc.height(), 6);
+        assert_eq!(bc.get_header(5), Some(blocks[1].header));
+        assert_eq!(bc.get_header(6), Some(blocks[2].header));
+        assert_eq!(bc.orphan_count(), 0);
+        assert!(bc.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_shared_chain() {
+        let chain = SharedChain::new(chain_of(3));
+        let blocks = branch(chain.read(tip), 4..=5, &[]);
+
+        let threads: Vec<_> = blocks
+            .into_iter()
+            .map(|b| {
+                let chain = chain.clone();
+                std::thread::spawn(move || {
+                    // Block 5 waits as an orphan if it arrives first.
+                    let res = chain.add_block(b);
+                    assert!(matches!(res, Ok(()) | Err(ChainError::UnknownParent(5))), "{:?}", res);
+                })
+            })
+            .collect();
+        for t in threads {
+            assert!(t.join().is_ok());
+        }
+
+        assert_eq!(chain.read_height(), 5);
+        assert!(chain.read(|bc| bc.validate_chain()).is_ok());
+    }
+
+    #[test]
+    fn test_orphan_pool_is_capped() {
+        let mut bc = new_blockchain_with_genesis();
+        for _ in 0..MAX_ORPHAN_BLOCKS {
+            assert_eq!(bc.add_block(Block::random_block_with_signature(5)), Err(ChainError::UnknownParent(5)));
+        }
+        assert_eq!(bc.add_block(Block::random_block_with_signature(5)), Err(ChainError::OrphanPoolFull(5)));
+        assert_eq!(bc.orphan_count(), MAX_ORPHAN_BLOCKS);
+    }
+
+    #[test]
+    fn test_orphan_pool_checks_blocks() {
+        let mut bc = new_blockchain_with_genesis();
+        assert_eq!(bc.add_block(Block::random_block(5)), Err(ChainError::InvalidSignature(5)));
+
+        let mut forged = Block::random_block_with_signature(5);
+        forged.header.timestamp += 1;
+        assert_eq!(bc.add_block(forged), Err(ChainError::InvalidSignature(5)));
+
+        let mut hard = Block::random_block_with_signature(5);
+        hard.header.difficulty = 64;
+        assert_eq!(bc.add_block(hard), Err(ChainError::InvalidDifficulty(5)));
+        assert_eq!(bc.orphan_count(), 0);
+    }
+
+    #[test]
+    fn test_add_block_invalid_prev_hash() {
+        let mut bc = new_blockchain_with_genesis();
+        let b = Block::random_block_with_signature(1);
+
+        assert_eq!(bc.add_block(b), Err(ChainError::InvalidPrevHash(1)));
+    }
+
+    #[test]
+    fn test_add_block_unsigned() {
+        let mut bc = new_blockchain_with_genesis();
+        let b = unsigned_next_block(&bc);
+
+        assert_eq!(bc.add_block(b), Err(ChainError::InvalidSignature(1)));
+    }
+
+    #[test]
+    fn test_add_block_unsigned_transaction() {
+        let mut bc = new_blockchain_with_genesis();
+        let header = next_block(&bc).header;
+        let mut b = Block::new(header, vec![Transaction::new(b"foo".to_vec())]);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+
+        assert_eq!(bc.add_block(b), Err(ChainError::InvalidTransaction(1, 0)));
+    }
+
+    #[test]
+    fn test_add_block_rejects_duplicate_transactions() {
+        let mut bc = new_blockchain_with_genesis();
+        let tx = signed_tx(b"foo");
+        let hash = TxHasher::new().hash(&tx).unwrap();
+        let mut b = Block::new(unsigned_next_block(&bc).header, vec![tx.clone(), tx]);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+
+        assert_eq!(bc.add_block(b), Err(ChainError::DuplicateTransaction(1, hash)));
+        assert_eq!(bc.height(), 0);
+    }
+
+    #[test]
+    fn test_add_block_checks_coinbase() {
+        let mut bc = new_blockchain_with_genesis();
+        let key = PrivateKey::generate_key();
+        let address = key.generate_public().address();
+
+        let mut b = unsigned_next_block(&bc);
+        b.add_coinbase(address, 50);
+        b.add_coinbase(address, 40);
+        assert!(b.sign(key.clone()).is_ok());
+        assert_eq!(bc.add_block(b), Err(ChainError::InvalidTransaction(1, 1)));
+
+        let mut b = unsigned_next_block(&bc);
+        b.add_coinbase(address, 50);
+        assert!(b.sign(key).is_ok());
+        assert!(bc.add_block(b).is_ok());
+    }
+
+    #[test]
+    fn test_supply_policy() {
+        let mut bc = new_blockchain_with_genesis();
+        bc.set_supply_policy(SupplyPolicy { reward: 50, fees: FeePolicy::Burn });
+        let key = PrivateKey::generate_key();
+        let coinbase_block = |bc: &Blockchain, reward| {
+            let mut b = unsigned_next_block(bc);
+            b.add_coinbase(key.generate_public().address(), reward);
+            assert!(b.sign(key.clone()).is_ok());
+            b
+        };
+
+        let overminting = coinbase_block(&bc, 1_000_000);
+        assert!(matches!(bc.add_block(overminting), Err(ChainError::InvalidState(1, _))));
+        assert!(bc.add_block(coinbase_block(&bc, 50)).is_ok());
+
+        // A restored state is held to the chain's policy, though the
+        // snapshot does not carry it.
+        let snapshot = bc.snapshot_at(1).unwrap();
+        assert!(bc.restore_state(&snapshot).is_ok());
+        let mut state = bc.state().unwrap();
+        assert!(matches!(state.apply_block(&coinbase_block(&bc, 60)), Err(StateError::SupplyMismatch { .. })));
+        assert!(bc.restore_state(&bc.snapshot_at(0).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_at() {
+        let mut bc = new_blockchain_with_genesis();
+        let key = PrivateKey::generate_key();
+        let from = key.generate_public().address();
+        let to = PrivateKey::generate_key().generate_public().address();
+
+        let mut state = AccountState::new();
+        for nonce in 0..4 {
+            let transfer = Transfer { from, to, amount: 5, nonce, fee: 1 };
+            let mut tx = Transaction::transfer(transfer);
+            assert!(tx.sign(key.clone()).is_ok());
+
+            let mut b = unsigned_next_block(&bc);
+            b.add_coinbase(from, 50);
+            b.add_transaction(tx);
+            assert!(b.sign(key.clone()).is_ok());
+            assert!(state.apply_block(&b).is_ok());
+            assert!(bc.add_block(b).is_ok());
+        }
+
+        let snapshot = bc.snapshot_at(4).unwrap();
+        assert_eq!(snapshot.height, 4);
+        assert_eq!(snapshot.hash, bc.last_hash());
+        let restored = AccountState::restore(&snapshot.state).unwrap();
+        assert_eq!(restored, state);
+        assert_eq!(restored.balance(&from), 4 * (50 - 6));
+        assert_eq!(restored.balance(&to), 20);
+
+        let earlier = AccountState::restore(&bc.snapshot_at(1).unwrap().state).unwrap();
+        assert_eq!(earlier.balance(&from), 44);
+        assert_eq!(bc.snapshot_at(5), Err(ChainError::MissingBlock(5)));
+    }
+
+    #[test]
+    fn test_add_block_checks_state_root() {
+        let mut bc = new_blockchain_with_genesis();
+        assert!(bc.state().is_none());
+        assert!(bc.track_state().is_ok());
+        assert_eq!(bc.state(), Some(AccountState::new()));
+
+        let key = PrivateKey::generate_key();
+        let miner = key.generate_public().address();
+        let coinbase_block = |bc: &Blockchain, state_root: Hash| {
+            let mut b = unsigned_next_block(bc);
+            b.add_coinbase(miner, 50);
+            b.header.state_root = state_root;
+            assert!(b.sign(key.clone()).is_ok());
+            b
+        };
+
+        let mut expected = AccountState::new();
+        assert!(expected.credit(miner, 50).is_ok());
+        assert!(bc.add_block(coinbase_block(&bc, expected.root())).is_ok());
+        assert_eq!(bc.state(), Some(expected.clone()));
+
+        let wrong = coinbase_block(&bc, AccountState::new().root());
+        assert_eq!(bc.add_block(wrong), Err(ChainError::StateRootMismatch(2)));
+        assert_eq!(bc.height(), 1);
+        assert_eq!(bc.state(), Some(expected.clone()));
+
+        assert!(expected.credit(miner, 50).is_ok());
+        assert!(bc.add_block(coinbase_block(&bc, expected.root())).is_ok());
+        assert_eq!(bc.state().unwrap().balance(&miner), 100);
+    }
+
+    /// A chain of `len` signed blocks on top of the genesis block.
+    pub(crate) fn chain_of(len: u32) -> Blockchain {
+        let mut bc = new_blockchain_with_genesis();
+        for _ in 0..len {
+            let b = next_block(&bc);
+            assert!(bc.add_block(b).is_ok());
+        }
+        bc
+    }
+
+    /// Overwrites the stored block at `height` behind the chain's back.
+    fn tamper(bc: &Blockchain, height: u32, f: impl FnOnce(&mut Block)) {
+        let mut data = bc.data.write().unwrap();
+        let mut b = data.store.get_by_height(height).unwrap().unwrap();
+        f(&mut b);
+        assert!(data.store.put_block(&b).is_ok());
+    }
+
+    #[test]
+    fn test_validate_chain() {
+        let bc = chain_of(10);
+        assert!(bc.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_validate_chain_detects_broken_link() {
+        let bc = chain_of(10);
+        tamper(&bc, 5, |b| b.header.prev_block = Hash::random());
+
+        assert_eq!(bc.validate_chain(), Err(ChainError::InvalidPrevHash(5)));
+    }
+
+    #[test]
+    fn test_validate_chain_detects_bad_transaction() {
+        let bc = chain_of(3);
+        // Re-signed so only the unsigned transaction is wrong; the signature
+        // covers the Merkle root.
+        tamper(&bc, 3, |b| {
+            b.transactions.push(Transaction::new(b"foo".to_vec()));
+            assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        });
+
+        assert_eq!(bc.validate_chain(), Err(ChainError::InvalidTransaction(3, 0)));
+    }
+
+    fn stored_blocks(bc: &Blockchain) -> Vec<Block> {
+        let data = bc.data.read().unwrap();
+        (0..=bc.height())
+            .map(|h| data.store.get_by_height(h).unwrap().unwrap())
+            .collect()
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_verify_blocks_parallel() {
+        let mut bc = new_blockchain_with_genesis();
+        for i in 0..300 {
+            let header = next_block(&bc).header;
+            let mut b = Block::new(header, vec![signed_tx(format!("tx-{}", i).as_bytes())]);
+            assert!(b.sign(PrivateKey::generate_key()).is_ok());
+            assert!(bc.add_block(b).is_ok());
+        }
+        let blocks = stored_blocks(&bc);
+
+        assert!(<Blockchain>::verify_blocks(&blocks).is_ok());
+        assert!(<Blockchain>::verify_blocks_parallel(&blocks).is_ok());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_verify_blocks_parallel_finds_first_bad_block() {
+        let blocks = stored_blocks(&chain_of(200));
+
+        let mut bad = blocks.clone();
+        bad[150].signature = None;
+        bad[170].header.prev_block = Hash::random();
+        bad[120].transactions.push(Transaction::new(b"foo".to_vec()));
+        bad[120].hash = None;
+
+        // The new transaction no longer matches the signed Merkle root.
+        let expected = Err(ChainError::InvalidSignature(120));
+        assert_eq!(<Blockchain>::verify_blocks(&bad), expected);
+        assert_eq!(<Blockchain>::verify_blocks_parallel(&bad), expected);
+
+        bad[120] = blocks[120].clone();
+        let expected = Err(ChainError::InvalidSignature(150));
+        assert_eq!(<Blockchain>::verify_blocks(&bad), expected);
+        assert_eq!(<Blockchain>::verify_blocks_parallel(&bad), expected);
+    }
+
+    #[test]
+    fn test_validate_chain_detects_stale_hash() {
+        let bc = chain_of(3);
+        tamper(&bc, 2, |b| b.hash = Some(Hash::random()));
+
+        assert_eq!(bc.validate_chain(), Err(ChainError::HashMismatch(2)));
+    }
+
+    fn signed_tx(data: &[u8]) -> Transaction {
+        let mut tx = Transaction::new(data.to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+        tx
+    }
+
+    /// Signed blocks at `heights` building on `parent`, a second apart.
+    fn branch(parent: Header, heights: std::ops::RangeInclusive<u32>, txs: &[Transaction]) -> Vec<Block> {
+        let mut prev = BlockHasher::new().hash(&parent).unwrap();
+        let mut timestamp = parent.timestamp;
+        heights
+            .map(|h| {
+                timestamp += 1;
+                let mut header = Block::random_block(h).header;
+                header.prev_block = prev;
+                header.timestamp = timestamp;
+                let mut b = Block::new(header, txs.to_vec());
+                assert!(b.sign(PrivateKey::generate_key()).is_ok());
+                prev = b.hash(Box::new(BlockHasher::new()));
+                b
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_block_locator() {
+        let bc = chain_of(20);
+        let hashes: Vec<_> = (0..=20).map(|h| BlockHasher::new().hash(&bc.get_header(h).unwrap()).unwrap()).collect();
+
+        let expected: Vec<_> = [20, 19, 17, 13, 5, 0].iter().map(|&h| hashes[h]).collect();
+        assert_eq!(bc.block_locator(), expected);
+        assert_eq!(new_blockchain_with_genesis().block_locator(), vec![hashes[0]]);
+    }
+
+    #[test]
+    fn test_find_fork_point() {
+        let a = chain_of(12);
+        let mut b = new_blockchain_with_genesis();
+        for h in 1..=9 {
+            assert!(b.add_block(a.get_block(h).unwrap().unwrap()).is_ok());
+        }
+        for block in branch(tip(&b), 10..=30, &[signed_tx(b"fork")]) {
+            assert!(b.add_block(block).is_ok());
+        }
+
+        // b's locator passes heights 30, 29, 27, 23, 15, then 0; past the
+        // fork only genesis is shared. a's locator hits height 9 on b.
+        assert_eq!(a.find_fork_point(&b.block_locator()), Some(0));
+        assert_eq!(b.find_fork_point(&a.block_locator()), Some(9));
+        assert_eq!(a.find_fork_point(&a.block_locator()), Some(12));
+        assert_eq!(a.find_fork_point(&[Hash::random()]), None);
+    }
+
+    #[test]
+    fn test_reorg_to_longer_fork() {
+        let mut bc = chain_of(2);
+        let kept = signed_tx(b"kept");
+        let dropped = signed_tx(b"dropped");
+        for b in branch(tip(&bc), 3..=3, &[kept.clone(), dropped.clone()]) {
+            assert!(bc.add_block(b).is_ok());
+        }
+        for b in branch(tip(&bc), 4..=4, &[]) {
+            assert!(bc.add_block(b).is_ok());
+        }
+        assert_eq!(bc.height(), 4);
+
+        let fork = branch(bc.get_header(2).unwrap(), 3..=5, &[kept]);
+        let fork_tip = fork[2].header;
+
+        let events = bc.subscribe();
+        assert_eq!(bc.try_reorg(fork), Ok(true));
+        assert_eq!(events.try_recv(), Ok(ChainEvent::Reorg { from: 3, to: 5 }));
+        assert_eq!(bc.take_rolled_back(), vec![dropped]);
+        assert!(bc.take_rolled_back().is_empty());
+        assert_eq!(bc.height(), 5);
+        assert_eq!(bc.get_header(5), Some(fork_tip));
+        assert!(bc.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let mut bc = chain_of(3);
+        let checkpointed = next_block(&bc);
+        bc.add_checkpoint(4, BlockHasher::new().hash(&checkpointed.header).unwrap());
+
+        let mut other = unsigned_next_block(&bc);
+        other.header.nonce = 1;
+        assert!(other.sign(PrivateKey::generate_key()).is_ok());
+        assert_eq!(bc.add_block(other), Err(ChainError::CheckpointMismatch(4)));
+        assert!(bc.add_block(checkpointed).is_ok());
+
+        // A longer fork from below the checkpoint cannot replace it.
+        let fork = branch(bc.get_header(2).unwrap(), 3..=8, &[signed_tx(b"fork")]);
+        assert_eq!(bc.try_reorg(fork), Err(ChainError::CheckpointMismatch(4)));
+        assert_eq!(bc.height(), 4);
+        assert!(bc.validate_chain().is_ok());
+
+        // One that keeps the checkpointed block can.
+        let fork = branch(bc.get_header(4).unwrap(), 5..=6, &[]);
+        assert_eq!(bc.try_reorg(fork), Ok(true));
+        assert_eq!(bc.height(), 6);
+    }
+
+    /// A signed block on `parent`, `secs` later, mined to `difficulty`.
+    fn mined_block(parent: &Header, secs: i64, difficulty: u32) -> Block {
+        let mut b = Block::random_block(parent.height + 1);
+        b.header.prev_block = parent.hash();
+        b.header.timestamp = parent.timestamp + secs;
+        b.mine(difficulty);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        b
+    }
+
+    #[test]
+    fn test_reorg_prefers_more_work() {
+        // Retargeting over two blocks with a 10 second target: blocks 10
+        // seconds apart keep the difficulty, one second apart raise it 2.
+        let mut bc = new_blockchain_with_genesis();
+        bc.set_retarget(10, 2);
+        for (secs, difficulty) in [(10, 0), (1, 0), (10, 2), (10, 2), (10, 2), (10, 2)] {
+            let b = mined_block(&tip(&bc), secs, difficulty);
+            assert!(bc.add_block(b).is_ok());
+        }
+        assert_eq!(bc.height(), 6);
+        assert_eq!(bc.total_work(), 3 + 4 * 4);
+
+        let mut fork = vec![];
+        let mut parent = bc.get_header(2).unwrap();
+        for difficulty in [2, 4, 6] {
+            let b = mined_block(&parent, 1, difficulty);
+            parent = b.header;
+            fork.push(b);
+        }
+
+        assert_eq!(bc.try_reorg(fork[..1].to_vec()), Ok(false));
+        assert_eq!(bc.try_reorg(fork), Ok(true));
+        assert_eq!(bc.height(), 5);
+        assert_eq!(bc.last_hash(), parent.hash());
+        assert_eq!(bc.total_work(), 3 + 4 + 16 + 64);
+        assert!(bc.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_reorg_ignores_shorter_or_detached_fork() {
+        let mut bc = chain_of(4);
+        let tip = bc.last_hash();
+
+        let shorter = branch(bc.get_header(2).unwrap(), 3..=4, &[]);
+        assert_eq!(bc.try_reorg(shorter), Ok(false));
+
+        let detached = branch(Block::random_block(2).header, 3..=6, &[]);
+        assert_eq!(bc.try_reorg(detached), Ok(false));
+
+        assert_eq!(bc.try_reorg(vec![]), Ok(false));
+        assert_eq!(bc.height(), 4);
+        assert_eq!(bc.last_hash(), tip);
+    }
+
+    #[test]
+    fn test_reorg_rejects_invalid_fork() {
+        let mut bc = chain_of(4);
+        let tip = bc.last_hash();
+
+        let mut fork = branch(bc.get_header(2).unwrap(), 3..=5, &[]);
+        fork[1].signature = None;
+
+        assert_eq!(bc.try_reorg(fork), Err(ChainError::InvalidSignature(4)));
+        assert_eq!(bc.height(), 4);
+        assert_eq!(bc.last_hash(), tip);
+        assert!(bc.validate_chain().is_ok());
+    }
+
+    /// A `MemoryStorage` that fails to store blocks at `fail_at` or above.
+    struct FailingStorage {
+        inner: MemoryStorage,
+        fail_at: u32,
+    }
+
+    impl Storage for FailingStorage {
+        fn put_block(&mut self, block: &Block) -> std::io::Result<()> {
+            if block.header.height >= self.fail_at {
+                return Err(std::io::Error::other("disk full"));
+            }
+            self.inner.put_block(block)
+        }
+
+        fn get_block(&self, hash: &Hash) -> std::io::Result<Option<Block>> {
+            self.inner.get_block(hash)
+        }
+
+        fn get_by_height(&self, height: u32) -> std::io::Result<Option<Block>> {
+            self.inner.get_by_height(height)
+        }
+
+        fn prune_blocks(&mut self, hashes: &[Hash]) -> std::io::Result<()> {
+            self.inner.prune_blocks(hashes)
+        }
+
+        fn get_by_prefix(&self, prefix: &str) -> std::io::Result<Vec<Hash>> {
+            self.inner.get_by_prefix(prefix)
+        }
+    }
+
+    #[test]
+    fn test_reorg_storage_error_keeps_chain() {
+        let mut bc = Blockchain::new(Block::genesis(), FailingStorage { inner: MemoryStorage::new(), fail_at: 5 }).unwrap();
+        for _ in 0..4 {
+            let b = next_block(&bc);
+            assert!(bc.add_block(b).is_ok());
+        }
+        let tip = bc.last_hash();
+
+        let fork = branch(bc.get_header(2).unwrap(), 3..=5, &[signed_tx(b"fork")]);
+        assert_eq!(bc.try_reorg(fork), Err(ChainError::Storage("disk full".to_owned())));
+        assert_eq!(bc.height(), 4);
+        assert_eq!(bc.last_hash(), tip);
+        assert!(bc.take_rolled_back().is_empty());
+        assert!(bc.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_add_block_checks_difficulty() {
+        let mut bc = new_blockchain_with_genesis();
+        bc.set_difficulty(8);
+
+        let mut b = unsigned_next_block(&bc);
+        b.mine(4);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        assert_eq!(bc.add_block(b), Err(ChainError::InvalidDifficulty(1)));
+
+        // Claiming the right difficulty without doing the work fails too.
+        let mut b = unsigned_next_block(&bc);
+        b.header.difficulty = 8;
+        while b.meets_difficulty(8) {
+            b.header.nonce += 1;
+        }
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        assert_eq!(bc.add_block(b), Err(ChainError::InvalidDifficulty(1)));
+
+        let mut b = unsigned_next_block(&bc);
+        b.mine(8);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        assert!(bc.add_block(b).is_ok());
+        assert!(bc.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_add_block_retargets_difficulty() {
+        let mut bc = new_blockchain_with_genesis();
+        bc.set_retarget(60, 4);
+        for _ in 0..4 {
+            assert_eq!(bc.expected_difficulty(bc.height() + 1), 0);
+            let b = next_block(&bc);
+            assert!(bc.add_block(b).is_ok());
+        }
+
+        // The last four blocks came a second apart instead of a minute.
+        assert_eq!(bc.expected_difficulty(5), 2);
+        let b = next_block(&bc);
+        assert_eq!(bc.add_block(b), Err(ChainError::InvalidDifficulty(5)));
+
+        let mut b = unsigned_next_block(&bc);
+        b.mine(2);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        assert!(bc.add_block(b).is_ok());
+        assert_eq!(bc.expected_difficulty(6), 4);
+    }
+
+    #[test]
+    fn test_add_block_checks_leader() {
+        let mut bc = new_blockchain_with_genesis();
+        let keys: Vec<_> = (0..2).map(|_| PrivateKey::generate_key()).collect();
+        let mut set = ValidatorSet::new();
+        for key in &keys {
+            set.add(key.generate_public());
+        }
+        bc.set_validator_set(set);
+
+        let mut b = unsigned_next_block(&bc);
+        assert!(b.sign(keys[0].clone()).is_ok());
+        assert_eq!(bc.add_block(b.clone()), Err(ChainError::UnexpectedValidator(1)));
+
+        assert!(b.sign(keys[1].clone()).is_ok());
+        assert!(bc.add_block(b).is_ok());
+
+        let mut b = unsigned_next_block(&bc);
+        assert!(b.sign(keys[0].clone()).is_ok());
+        assert!(bc.add_block(b).is_ok());
+    }
+
+    #[test]
+    fn test_add_block_checks_timestamp() {
+        let mut bc = new_blockchain_with_genesis();
+        let parent = tip(&bc).timestamp;
+
+        for timestamp in [parent - 1, parent] {
+            let mut b = unsigned_next_block(&bc);
+            b.header.timestamp = timestamp;
+            assert!(b.sign(PrivateKey::generate_key()).is_ok());
+            assert_eq!(bc.add_block(b), Err(ChainError::InvalidTimestamp(1)));
+        }
+
+        let mut b = unsigned_next_block(&bc);
+        b.header.timestamp = Utc::now().timestamp() + 60;
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        assert!(bc.add_block(b).is_ok());
+
+        let mut b = unsigned_next_block(&bc);
+        b.header.timestamp = Utc::now().timestamp() + DEFAULT_MAX_FUTURE_DRIFT + 60;
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        assert_eq!(bc.add_block(b.clone()), Err(ChainError::InvalidTimestamp(2)));
+
+        bc.set_max_future_drift(DEFAULT_MAX_FUTURE_DRIFT + 120);
+        assert!(bc.add_block(b).is_ok());
+    }
+ }
@@ -0,0 +1,156 @@
+use sha2::{Digest, Sha256};
+
+use crate::types::{address::Address, hash::Hash};
+
+use super::{block::Block, hasher::{Hasher, TxHasher}};
+
+/// Filter bits allotted per inserted item by `BlockFilter::for_items`, for a
+/// false positive rate of about 1%.
+pub const BITS_PER_ITEM: usize = 10;
+
+/// Bit positions set per item by `BlockFilter::for_items`.
+pub const NUM_HASHES: u32 = 7;
+
+/// A Bloom filter over the addresses and transaction hashes in a block, so a
+/// light client can skip blocks that cannot concern it.
+///
+/// `may_contain` never returns false for an inserted item, but may return
+/// true for one that was not inserted.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockFilter {
+    bits: Vec<u8>,
+    num_hashes: u32,
+}
+
+impl BlockFilter {
+    /// An empty filter of `num_bytes * 8` bits setting `num_hashes` bits per
+    /// item.
+    pub fn new(num_bytes: usize, num_hashes: u32) -> Self {
+        assert!(num_bytes > 0, "a filter needs at least one byte");
+        BlockFilter { bits: vec![0; num_bytes], num_hashes }
+    }
+
+    /// An empty filter sized for `items` items.
+    pub fn for_items(items: usize) -> Self {
+        BlockFilter::new((items * BITS_PER_ITEM).div_ceil(8).max(1), NUM_HASHES)
+    }
+
+    /// A filter holding the hash of every transaction in `block`, along
+    /// with every address the transactions name: the signer, `from` and
+    /// `to`, and the sender and recipient of a `Transfer` in `data`.
+    pub fn from_block(block: &Block) -> Self {
+        let hasher = TxHasher::new();
+        let mut hashes = vec![];
+        let mut addresses: Vec<Address> = vec![];
+        for tx in &block.transactions {
+            hashes.push(hasher.hash(tx).expect("could not hash"));
+            addresses.extend(tx.key.map(|key| key.address()));
+            addresses.extend(tx.from);
+            addresses.extend(tx.to);
+            if let Ok(transfer) = tx.decode_transfer() {
+                addresses.extend([transfer.from, transfer.to]);
+            }
+        }
+
+        let mut filter = BlockFilter::for_items(hashes.len() + addresses.len());
+        for hash in &hashes {
+            filter.insert_hash(hash);
+        }
+        for address in &addresses {
+            filter.insert_address(address);
+        }
+        filter
+    }
+
+    pub fn insert(&mut self, item: &[u8]) {
+        for bit in self.positions(item) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    pub fn may_contain(&self, item: &[u8]) -> bool {
+        self.positions(item).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    pub fn insert_address(&mut self, address: &Address) {
+        self.insert(&address.to_vec());
+    }
+
+    pub fn may_contain_address(&self, address: &Address) -> bool {
+        self.may_contain(&address.to_vec())
+    }
+
+    pub fn insert_hash(&mut self, hash: &Hash) {
+        self.insert(&hash.to_vec());
+    }
+
+    pub fn may_contain_hash(&self, hash: &Hash) -> bool {
+        self.may_contain(&hash.to_vec())
+    }
+
+    /// The bits for `item`, by double hashing: two 64-bit values `a` and `b`
+    /// from the SHA256 of the item give positions `a + i * b` for each of
+    /// the `num_hashes` values of `i`.
+    fn positions(&self, item: &[u8]) -> impl Iterator<Item = usize> {
+        let digest = Sha256::digest(item);
+        let a = u64::from_le_bytes(digest[..8].try_into().unwrap());
+        let b = u64::from_le_bytes(digest[8..16].try_into().unwrap());
+        let len = self.bits.len() as u64 * 8;
+        (0..self.num_hashes as u64).map(move |i| (a.wrapping_add(i.wrapping_mul(b)) % len) as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{core::{block::Block, hasher::{Hasher, TxHasher}, transaction::Transaction, transfer::Transfer}, crypto::keypair::PrivateKey, types::address::Address};
+
+    use super::BlockFilter;
+
+    fn address() -> Address {
+        PrivateKey::generate_key().generate_public().address()
+    }
+
+    #[test]
+    fn test_block_filter() {
+        let key = PrivateKey::generate_key();
+        let (to, recipient, miner) = (address(), address(), address());
+
+        let transfer = Transfer { from: key.generate_public().address(), to: recipient, amount: 5, nonce: 0, fee: 0 };
+        let mut payment = Transaction::transfer(transfer);
+        assert!(payment.sign(key.clone()).is_ok());
+        let mut direct = Transaction { to: Some(to), amount: 3, ..Transaction::new(vec![]) };
+        assert!(direct.sign(PrivateKey::generate_key()).is_ok());
+
+        let mut b = Block::new(Block::random_block(1).header, vec![payment, direct]);
+        b.add_coinbase(miner, 50);
+        let filter = BlockFilter::from_block(&b);
+
+        for tx in &b.transactions {
+            assert!(filter.may_contain_hash(&TxHasher::new().hash(tx).unwrap()));
+            if let Some(key) = tx.key {
+                assert!(filter.may_contain_address(&key.address()));
+            }
+        }
+        for address in [key.generate_public().address(), recipient, to, miner, Address::zero()] {
+            assert!(filter.may_contain_address(&address));
+        }
+
+        let summary = b.summary();
+        assert_eq!(summary.filter, filter);
+        assert_eq!(summary.tx_count, 3);
+
+        let false_positives = (0..1000).filter(|_| filter.may_contain_address(&address())).count();
+        assert!(false_positives < 100, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_insert_may_contain() {
+        let mut filter = BlockFilter::new(1, 3);
+        assert!(!filter.may_contain(b"foo"));
+
+        filter.insert(b"foo");
+        assert!(filter.may_contain(b"foo"));
+        assert_eq!(BlockFilter::for_items(0), BlockFilter::new(1, 7));
+    }
+}
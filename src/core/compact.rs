@@ -0,0 +1,49 @@
+use std::fmt;
+
+use crate::{crypto::scheme::{ChainKey, ChainSignature}, types::hash::Hash};
+
+use super::{block::{Block, Header}, hasher::{Hasher, TxHasher}, transaction::Transaction};
+
+/// A block announced by the hashes of its transactions, so a peer that
+/// already has them in its mempool need not download them again.
+///
+/// `tx_hashes` lists every transaction in block order. `prefilled` carries
+/// the ones the receiver is unlikely to have, such as the coinbase, in
+/// full. The signature and validator are the block's, so the header can be
+/// checked before the transactions are found. It goes on the wire through
+/// `CompactBlockEncoder` and `CompactBlockDecoder`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CompactBlock {
+    pub header: Header,
+    pub tx_hashes: Vec<Hash>,
+    pub prefilled: Vec<Transaction>,
+    pub signature: Option<ChainSignature>,
+    pub validator: Option<ChainKey>,
+}
+
+impl CompactBlock {
+    /// Compacts `block`, prefilling its coinbase if it has one.
+    pub fn from_block(block: &Block) -> CompactBlock {
+        let hasher = TxHasher::new();
+        CompactBlock {
+            header: block.header,
+            tx_hashes: block.transactions.iter().map(|tx| hasher.hash(tx).expect("could not hash")).collect(),
+            prefilled: block.coinbase().cloned().into_iter().collect(),
+            signature: block.signature,
+            validator: block.validator,
+        }
+    }
+}
+
+/// The hashes, in block order, of the transactions of a `CompactBlock` that
+/// are neither prefilled nor in the mempool.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MissingTxs(pub Vec<Hash>);
+
+impl fmt::Display for MissingTxs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} transactions of the block are missing", self.0.len())
+    }
+}
+
+impl std::error::Error for MissingTxs {}
@@ -1,8 +1,11 @@
-use std::io::{Write, Read, Result};
+use std::collections::HashMap;
+use std::io::{Write, Read, Result, Error, ErrorKind};
 
-use byteorder::{LittleEndian, WriteBytesExt};
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt, ReadBytesExt};
 
-use super::block::Header;
+use crate::{types::{hash::Hash, address::Address}, crypto::{keypair::PublicKey, signature::Signature}};
+
+use super::{block::{Header, Block, MAX_TRANSACTIONS, MAX_BLOCK_BYTES}, compact::CompactBlock, transaction::{Transaction, MAX_TX_DATA_BYTES}, transfer::Transfer};
 
 pub trait Encoder<T: ?Sized> {
     fn encode<W: Write>(&self, writer: &mut W, t: &T) -> Result<()>;
@@ -20,6 +23,44 @@ pub trait Decode {
     fn decode_binary<R: Read, D: Decoder<Self>>(writer: &mut R, decoder: D) -> Result<Box<Self>>;
 }
 
+/// Writes `value` as an unsigned LEB128 varint: seven bits per byte, least
+/// significant group first, with the high bit set on all but the last byte.
+pub fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> Result<()> {
+    while value >= 0x80 {
+        writer.write_u8((value as u8 & 0x7f) | 0x80)?;
+        value >>= 7;
+    }
+    writer.write_u8(value as u8)
+}
+
+/// Reads a varint written by `write_varint`, rejecting encodings with
+/// redundant trailing zero groups and values that do not fit in a `u64`.
+pub fn read_varint<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut value = 0u64;
+    for i in 0..10 {
+        let byte = reader.read_u8()?;
+        let group = (byte & 0x7f) as u64;
+        if i == 9 && byte > 1 {
+            return Err(Error::new(ErrorKind::InvalidData, "varint overflows u64"));
+        }
+        value |= group << (7 * i);
+
+        if byte & 0x80 == 0 {
+            if i > 0 && byte == 0 {
+                return Err(Error::new(ErrorKind::InvalidData, "overlong varint"));
+            }
+            return Ok(value);
+        }
+    }
+    unreachable!("the tenth byte never has its continuation bit set")
+}
+
+/// Number of bytes `write_varint` uses for `value`.
+pub fn varint_len(value: u64) -> usize {
+    let bits = 64 - value.leading_zeros() as usize;
+    bits.max(1).div_ceil(7)
+}
+
 pub struct HeaderEncoder {}
 
 impl HeaderEncoder {
@@ -28,14 +69,43 @@ impl HeaderEncoder {
     }
 }
 
+/// Headers start with their version, which selects the layout of the rest:
+/// version 1 is data, previous block, timestamp and height; version 2
+/// appends the nonce, version 3 the difficulty and version 4 the state root.
+/// The timestamp is 8 big-endian bytes; every other integer is
+/// little-endian.
 impl Encoder<Header> for HeaderEncoder {
 
 
     fn encode<W: Write>(&self, writer: &mut W, h: &Header) -> Result<()> {
+        match h.version {
+            1 if h.nonce != 0 => {
+                return Err(Error::new(ErrorKind::InvalidInput, "version 1 headers have no nonce"));
+            }
+            1 | 2 if h.difficulty != 0 => {
+                return Err(Error::new(ErrorKind::InvalidInput, "headers before version 3 have no difficulty"));
+            }
+            1..=3 if !h.state_root.is_zero() => {
+                return Err(Error::new(ErrorKind::InvalidInput, "headers before version 4 have no state root"));
+            }
+            1..=4 => {}
+            v => return Err(Error::new(ErrorKind::InvalidInput, format!("unknown header version {}", v))),
+        }
+
         writer.write_u32::<LittleEndian>(h.version)?;
-        // self.prev_block.encode_binary(writer)?;
-        writer.write_i64::<LittleEndian>(h.timestamp)?;
+        h.data.encode_binary(writer)?;
+        h.prev_block.encode_binary(writer)?;
+        writer.write_i64::<BigEndian>(h.timestamp)?;
         writer.write_u32::<LittleEndian>(h.height)?;
+        if h.version >= 2 {
+            writer.write_u64::<LittleEndian>(h.nonce)?;
+        }
+        if h.version >= 3 {
+            writer.write_u32::<LittleEndian>(h.difficulty)?;
+        }
+        if h.version >= 4 {
+            h.state_root.encode_binary(writer)?;
+        }
         Ok(())
     }
 }
@@ -49,7 +119,673 @@ impl HeaderDecoder {
 }
 
 impl Decoder<Header> for HeaderDecoder {
-    fn decode<R: Read>(&self, _reader: &mut R) -> Result<Box<Header>> {
-        todo!()
+    fn decode<R: Read>(&self, reader: &mut R) -> Result<Box<Header>> {
+        let version = reader.read_u32::<LittleEndian>()?;
+        if !(1..=4).contains(&version) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("unknown header version {}", version)));
+        }
+
+        let data = Hash::decode_binary(reader)?;
+        let prev_block = Hash::decode_binary(reader)?;
+        let timestamp = reader.read_i64::<BigEndian>()?;
+        let height = reader.read_u32::<LittleEndian>()?;
+        let nonce = match version {
+            1 => 0,
+            _ => reader.read_u64::<LittleEndian>()?,
+        };
+        let difficulty = match version {
+            1 | 2 => 0,
+            _ => reader.read_u32::<LittleEndian>()?,
+        };
+        let state_root = match version {
+            1..=3 => Hash::zero(),
+            _ => Hash::decode_binary(reader)?,
+        };
+
+        Ok(Box::new(Header {
+            version,
+            data,
+            prev_block,
+            timestamp,
+            height,
+            nonce,
+            difficulty,
+            state_root,
+        }))
+    }
+}
+pub struct TxEncoder {}
+
+impl TxEncoder {
+    pub fn new() -> Self {
+        TxEncoder {}
+    }
+
+    /// Writes the part of the encoding a signature covers: everything but
+    /// the key and signature.
+    pub fn encode_unsigned<W: Write>(&self, writer: &mut W, tx: &Transaction) -> Result<()> {
+        write_varint(writer, tx.data.len() as u64)?;
+        writer.write_all(&tx.data)?;
+        encode_address(writer, &tx.from)?;
+        encode_address(writer, &tx.to)?;
+        writer.write_u64::<LittleEndian>(tx.amount)?;
+        encode_height(writer, &tx.valid_until)
+    }
+}
+
+/// Fields are always written in this order, whichever are set:
+///
+/// 1. `data`: its length as a varint, then the bytes
+/// 2. `from`: presence byte, then 20 bytes
+/// 3. `to`: presence byte, then 20 bytes
+/// 4. `amount`: u64 little-endian
+/// 5. `valid_until`: presence byte, then u32 little-endian
+/// 6. `key`: presence byte, then the 33-byte compressed SEC1 key
+/// 7. `signature`: presence byte, then the 64-byte `r || s`
+///
+/// A presence byte is 0 for `None` and 1 for `Some`; the decoder rejects
+/// anything else. The cached `hash` is never encoded.
+impl Encoder<Transaction> for TxEncoder {
+    fn encode<W: Write>(&self, writer: &mut W, tx: &Transaction) -> Result<()> {
+        self.encode_unsigned(writer, tx)?;
+        encode_public_key(writer, &tx.key)?;
+        encode_signature(writer, &tx.signature)?;
+        Ok(())
+    }
+}
+
+pub struct TxDecoder {
+    max_data_bytes: usize,
+}
+
+impl TxDecoder {
+    pub fn new() -> Self {
+        TxDecoder::with_max_data_bytes(MAX_TX_DATA_BYTES)
+    }
+
+    /// A decoder that rejects transactions whose `data` is longer than
+    /// `max_data_bytes` instead of `MAX_TX_DATA_BYTES`.
+    pub fn with_max_data_bytes(max_data_bytes: usize) -> Self {
+        TxDecoder { max_data_bytes }
+    }
+}
+
+impl Decoder<Transaction> for TxDecoder {
+    fn decode<R: Read>(&self, reader: &mut R) -> Result<Box<Transaction>> {
+        let len = read_varint(reader)?;
+        if len > self.max_data_bytes as u64 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("transaction data of {} bytes exceeds the limit of {}", len, self.max_data_bytes)));
+        }
+
+        let mut data = vec![];
+        reader.take(len).read_to_end(&mut data)?;
+        if data.len() as u64 != len {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "transaction data is truncated"));
+        }
+
+        Ok(Box::new(Transaction {
+            data,
+            from: decode_address(reader)?,
+            to: decode_address(reader)?,
+            amount: reader.read_u64::<LittleEndian>()?,
+            valid_until: decode_height(reader)?,
+            key: decode_public_key(reader)?,
+            signature: decode_signature(reader)?,
+            hash: None,
+        }))
+    }
+}
+
+/// Writes a block: the header, a varint count of transactions and the
+/// transactions, then the signature and validator. The cached `hash` and
+/// `prev_hash` are left out; `BlockDecoder` derives them from the header.
+pub struct BlockEncoder {}
+
+impl BlockEncoder {
+    pub fn new() -> Self {
+        BlockEncoder {}
+    }
+}
+
+impl Encoder<Block> for BlockEncoder {
+    fn encode<W: Write>(&self, writer: &mut W, b: &Block) -> Result<()> {
+        HeaderEncoder::new().encode(writer, &b.header)?;
+
+        write_varint(writer, b.transactions.len() as u64)?;
+        let tx_encoder = TxEncoder::new();
+        for tx in &b.transactions {
+            tx_encoder.encode(writer, tx)?;
+        }
+
+        encode_signature(writer, &b.signature)?;
+        encode_public_key(writer, &b.validator)?;
+        Ok(())
+    }
+}
+
+pub struct BlockDecoder {}
+
+impl BlockDecoder {
+    pub fn new() -> Self {
+        BlockDecoder {}
+    }
+}
+
+impl BlockDecoder {
+    /// Reads the header and transactions, the only variable-sized part of a
+    /// block.
+    fn decode_body<R: Read>(reader: &mut R) -> Result<(Header, Vec<Transaction>)> {
+        let header = *HeaderDecoder::new().decode(reader).map_err(truncated_in("header"))?;
+
+        let count = read_varint(reader).map_err(truncated_in("transaction count"))?;
+        if count > MAX_TRANSACTIONS as u64 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("block has {} transactions, at most {} allowed", count, MAX_TRANSACTIONS),
+            ));
+        }
+
+        let tx_decoder = TxDecoder::new();
+        let mut transactions = vec![];
+        for i in 0..count {
+            let tx = tx_decoder.decode(reader).map_err(truncated_in(&format!("transaction {}", i)))?;
+            transactions.push(*tx);
+        }
+        Ok((header, transactions))
+    }
+}
+
+impl Decoder<Block> for BlockDecoder {
+    /// Refuses blocks over `MAX_TRANSACTIONS` or `MAX_BLOCK_BYTES` without
+    /// reading past the limit, so hostile input cannot exhaust memory.
+    fn decode<R: Read>(&self, reader: &mut R) -> Result<Box<Block>> {
+        let mut limited = reader.by_ref().take(MAX_BLOCK_BYTES as u64 + 1);
+        let body = Self::decode_body(&mut limited);
+        if limited.limit() == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("block is larger than {} bytes", MAX_BLOCK_BYTES),
+            ));
+        }
+        let (header, transactions) = body?;
+
+        Ok(Box::new(Block {
+            header,
+            transactions,
+            signature: decode_signature(reader).map_err(truncated_in("signature"))?,
+            validator: decode_public_key(reader).map_err(truncated_in("validator"))?,
+            hash: Some(header.hash()),
+            prev_hash: Some(header.prev_block),
+        }))
+    }
+}
+
+/// Writes a compact block: the header, a varint count of transaction
+/// hashes and the hashes, a varint count of prefilled transactions and the
+/// transactions, then the signature and validator as in a block.
+pub struct CompactBlockEncoder {}
+
+impl CompactBlockEncoder {
+    pub fn new() -> Self {
+        CompactBlockEncoder {}
+    }
+}
+
+impl Encoder<CompactBlock> for CompactBlockEncoder {
+    fn encode<W: Write>(&self, writer: &mut W, b: &CompactBlock) -> Result<()> {
+        HeaderEncoder::new().encode(writer, &b.header)?;
+
+        write_varint(writer, b.tx_hashes.len() as u64)?;
+        for hash in &b.tx_hashes {
+            hash.encode_binary(writer)?;
+        }
+
+        write_varint(writer, b.prefilled.len() as u64)?;
+        let tx_encoder = TxEncoder::new();
+        for tx in &b.prefilled {
+            tx_encoder.encode(writer, tx)?;
+        }
+
+        encode_signature(writer, &b.signature)?;
+        encode_public_key(writer, &b.validator)?;
+        Ok(())
+    }
+}
+
+/// Refuses more than `MAX_TRANSACTIONS` hashes or prefilled transactions.
+pub struct CompactBlockDecoder {}
+
+impl CompactBlockDecoder {
+    pub fn new() -> Self {
+        CompactBlockDecoder {}
+    }
+}
+
+impl Decoder<CompactBlock> for CompactBlockDecoder {
+    fn decode<R: Read>(&self, reader: &mut R) -> Result<Box<CompactBlock>> {
+        let header = *HeaderDecoder::new().decode(reader).map_err(truncated_in("header"))?;
+
+        let count = |reader: &mut R, what: &str| -> Result<u64> {
+            let count = read_varint(reader).map_err(truncated_in(what))?;
+            if count > MAX_TRANSACTIONS as u64 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("compact block has {} {}, at most {} allowed", count, what, MAX_TRANSACTIONS),
+                ));
+            }
+            Ok(count)
+        };
+
+        let mut tx_hashes = vec![];
+        for _ in 0..count(reader, "transaction hashes")? {
+            tx_hashes.push(Hash::decode_binary(reader).map_err(truncated_in("transaction hashes"))?);
+        }
+
+        let tx_decoder = TxDecoder::new();
+        let mut prefilled = vec![];
+        for i in 0..count(reader, "prefilled transactions")? {
+            prefilled.push(*tx_decoder.decode(reader).map_err(truncated_in(&format!("prefilled transaction {}", i)))?);
+        }
+
+        Ok(Box::new(CompactBlock {
+            header,
+            tx_hashes,
+            prefilled,
+            signature: decode_signature(reader).map_err(truncated_in("signature"))?,
+            validator: decode_public_key(reader).map_err(truncated_in("validator"))?,
+        }))
+    }
+}
+
+pub struct TransferEncoder {}
+
+impl TransferEncoder {
+    pub fn new() -> Self {
+        TransferEncoder {}
+    }
+}
+
+impl Encoder<Transfer> for TransferEncoder {
+    fn encode<W: Write>(&self, writer: &mut W, t: &Transfer) -> Result<()> {
+        t.from.encode_binary(writer)?;
+        t.to.encode_binary(writer)?;
+        writer.write_u64::<LittleEndian>(t.amount)?;
+        writer.write_u64::<LittleEndian>(t.nonce)?;
+        writer.write_u64::<LittleEndian>(t.fee)?;
+        Ok(())
+    }
+}
+
+pub struct TransferDecoder {}
+
+impl TransferDecoder {
+    pub fn new() -> Self {
+        TransferDecoder {}
+    }
+}
+
+impl Decoder<Transfer> for TransferDecoder {
+    fn decode<R: Read>(&self, reader: &mut R) -> Result<Box<Transfer>> {
+        Ok(Box::new(Transfer {
+            from: Address::decode_binary(reader)?,
+            to: Address::decode_binary(reader)?,
+            amount: reader.read_u64::<LittleEndian>()?,
+            nonce: reader.read_u64::<LittleEndian>()?,
+            fee: reader.read_u64::<LittleEndian>()?,
+        }))
+    }
+}
+
+/// Encodes a per-account map, such as the balances or nonces of an
+/// `AccountState`: a varint count, then each address (20 bytes) and value
+/// (u64 LE) in ascending address order, so equal maps always encode to the
+/// same bytes.
+pub struct AccountMapEncoder {}
+
+impl AccountMapEncoder {
+    pub fn new() -> Self {
+        AccountMapEncoder {}
+    }
+}
+
+impl Encoder<HashMap<Address, u64>> for AccountMapEncoder {
+    fn encode<W: Write>(&self, writer: &mut W, map: &HashMap<Address, u64>) -> Result<()> {
+        let mut entries: Vec<(&Address, &u64)> = map.iter().collect();
+        entries.sort();
+
+        write_varint(writer, entries.len() as u64)?;
+        for (address, value) in entries {
+            address.encode_binary(writer)?;
+            writer.write_u64::<LittleEndian>(*value)?;
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Decodes what `AccountMapEncoder` writes. Addresses out of order or
+/// repeated are rejected, so every map has a single encoding.
+pub struct AccountMapDecoder {}
+
+impl AccountMapDecoder {
+    pub fn new() -> Self {
+        AccountMapDecoder {}
+    }
+}
+
+impl Decoder<HashMap<Address, u64>> for AccountMapDecoder {
+    fn decode<R: Read>(&self, reader: &mut R) -> Result<Box<HashMap<Address, u64>>> {
+        let count = read_varint(reader)?;
+        let mut map = HashMap::new();
+        let mut last: Option<Address> = None;
+        for _ in 0..count {
+            let address = Address::decode_binary(reader)?;
+            if last.is_some_and(|last| address <= last) {
+                return Err(Error::new(ErrorKind::InvalidData, format!("account {} is out of order", address)));
+            }
+            map.insert(address, reader.read_u64::<LittleEndian>()?);
+            last = Some(address);
+        }
+        Ok(Box::new(map))
+    }
+}
+
+impl Encode for HashMap<Address, u64> {
+    fn encode_binary<W: Write, E: Encoder<Self>>(&self, writer: &mut W, encoder: E) -> Result<()> {
+        encoder.encode(writer, self)
+    }
+}
+
+impl Decode for HashMap<Address, u64> {
+    fn decode_binary<R: Read, D: Decoder<Self>>(reader: &mut R, decoder: D) -> Result<Box<Self>> {
+        decoder.decode(reader)
+    }
+}
+
+/// Writes a byte array as exactly its `N` bytes, with no length prefix, for
+/// values such as hashes and keys whose size both sides know.
+pub struct FixedBytesEncoder {}
+
+impl FixedBytesEncoder {
+    pub fn new() -> Self {
+        FixedBytesEncoder {}
+    }
+}
+
+impl<const N: usize> Encoder<[u8; N]> for FixedBytesEncoder {
+    fn encode<W: Write>(&self, writer: &mut W, bytes: &[u8; N]) -> Result<()> {
+        writer.write_all(bytes)
+    }
+}
+
+/// Reads exactly `N` bytes, failing with `UnexpectedEof` on fewer.
+pub struct FixedBytesDecoder {}
+
+impl FixedBytesDecoder {
+    pub fn new() -> Self {
+        FixedBytesDecoder {}
+    }
+}
+
+impl<const N: usize> Decoder<[u8; N]> for FixedBytesDecoder {
+    fn decode<R: Read>(&self, reader: &mut R) -> Result<Box<[u8; N]>> {
+        let mut bytes = [0u8; N];
+        reader.read_exact(&mut bytes)?;
+        Ok(Box::new(bytes))
+    }
+}
+
+impl<const N: usize> Encode for [u8; N] {
+    fn encode_binary<W: Write, E: Encoder<Self>>(&self, writer: &mut W, encoder: E) -> Result<()> {
+        encoder.encode(writer, self)
+    }
+}
+
+impl<const N: usize> Decode for [u8; N] {
+    fn decode_binary<R: Read, D: Decoder<Self>>(reader: &mut R, decoder: D) -> Result<Box<Self>> {
+        decoder.decode(reader)
+    }
+}
+
+// Optional fields are written as a presence byte (0 or 1) followed by the
+// value when present.
+
+fn encode_presence<W: Write>(writer: &mut W, present: bool) -> Result<()> {
+    writer.write_u8(present as u8)
+}
+
+fn decode_presence<R: Read>(reader: &mut R) -> Result<bool> {
+    match reader.read_u8()? {
+        0 => Ok(false),
+        1 => Ok(true),
+        b => Err(Error::new(ErrorKind::InvalidData, format!("invalid presence byte {}", b))),
+    }
+}
+
+fn encode_hash<W: Write>(writer: &mut W, hash: &Option<Hash>) -> Result<()> {
+    encode_presence(writer, hash.is_some())?;
+    if let Some(hash) = hash {
+        hash.encode_binary(writer)?;
+    }
+    Ok(())
+}
+
+/// Names `field` in an end-of-input error, so a truncated block says where
+/// it was cut off. Other errors pass through unchanged.
+fn truncated_in(field: &str) -> impl FnOnce(Error) -> Error + '_ {
+    move |e| match e.kind() {
+        ErrorKind::UnexpectedEof => Error::new(ErrorKind::UnexpectedEof, format!("block is truncated in {}", field)),
+        _ => e,
+    }
+}
+
+fn decode_hash<R: Read>(reader: &mut R) -> Result<Option<Hash>> {
+    if !decode_presence(reader)? {
+        return Ok(None);
+    }
+    Ok(Some(Hash::decode_binary(reader)?))
+}
+
+fn encode_address<W: Write>(writer: &mut W, address: &Option<Address>) -> Result<()> {
+    encode_presence(writer, address.is_some())?;
+    if let Some(address) = address {
+        address.encode_binary(writer)?;
+    }
+    Ok(())
+}
+
+fn decode_address<R: Read>(reader: &mut R) -> Result<Option<Address>> {
+    if !decode_presence(reader)? {
+        return Ok(None);
+    }
+    Ok(Some(Address::decode_binary(reader)?))
+}
+
+fn encode_height<W: Write>(writer: &mut W, height: &Option<u32>) -> Result<()> {
+    encode_presence(writer, height.is_some())?;
+    if let Some(height) = height {
+        writer.write_u32::<LittleEndian>(*height)?;
+    }
+    Ok(())
+}
+
+fn decode_height<R: Read>(reader: &mut R) -> Result<Option<u32>> {
+    if !decode_presence(reader)? {
+        return Ok(None);
+    }
+    Ok(Some(reader.read_u32::<LittleEndian>()?))
+}
+
+fn encode_public_key<W: Write>(writer: &mut W, key: &Option<PublicKey>) -> Result<()> {
+    encode_presence(writer, key.is_some())?;
+    if let Some(key) = key {
+        writer.write_all(&key.to_bytes())?;
+    }
+    Ok(())
+}
+
+fn decode_public_key<R: Read>(reader: &mut R) -> Result<Option<PublicKey>> {
+    if !decode_presence(reader)? {
+        return Ok(None);
+    }
+
+    let bytes = <[u8; 33]>::decode_binary(reader, FixedBytesDecoder::new())?;
+    let key = PublicKey::from_bytes(&*bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    Ok(Some(key))
+}
+
+fn encode_signature<W: Write>(writer: &mut W, signature: &Option<Signature>) -> Result<()> {
+    encode_presence(writer, signature.is_some())?;
+    if let Some(signature) = signature {
+        writer.write_all(&signature.to_bytes())?;
+    }
+    Ok(())
+}
+
+fn decode_signature<R: Read>(reader: &mut R) -> Result<Option<Signature>> {
+    if !decode_presence(reader)? {
+        return Ok(None);
+    }
+
+    let bytes = <[u8; 64]>::decode_binary(reader, FixedBytesDecoder::new())?;
+    let signature = Signature::from_bytes(&*bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    Ok(Some(signature))
+}
+
+#[cfg(test)]
+mod vectors;
+
+#[cfg(test)]
+mod fuzz;
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::io::ErrorKind;
+
+    use crate::{core::transaction::{Transaction, MAX_TX_DATA_BYTES}, types::{address::Address, hash::Hash}};
+
+    use super::{write_varint, read_varint, varint_len, Encode, Decode, TxDecoder, AccountMapEncoder, AccountMapDecoder, FixedBytesEncoder, FixedBytesDecoder};
+
+    fn address(byte: u8) -> Address {
+        Address::from_bytes(&[byte; 20]).unwrap()
+    }
+
+    fn encode_map(map: &HashMap<Address, u64>) -> Vec<u8> {
+        let mut bytes = vec![];
+        assert!(map.encode_binary(&mut bytes, AccountMapEncoder::new()).is_ok());
+        bytes
+    }
+
+    #[test]
+    fn test_account_map_round_trip() {
+        let map: HashMap<Address, u64> = (1..=50).map(|i| (address(i), i as u64 * 1000)).collect();
+        let bytes = encode_map(&map);
+        assert_eq!(bytes.len(), 1 + 50 * 28);
+
+        let decoded = *HashMap::<Address, u64>::decode_binary(&mut bytes.as_slice(), AccountMapDecoder::new()).unwrap();
+        assert_eq!(decoded, map);
+
+        let empty = encode_map(&HashMap::new());
+        assert_eq!(empty, vec![0]);
+        assert!(HashMap::<Address, u64>::decode_binary(&mut empty.as_slice(), AccountMapDecoder::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_account_map_encoding_is_deterministic() {
+        let mut a = HashMap::new();
+        let mut b = HashMap::new();
+        for i in 1..=20 {
+            a.insert(address(i), i as u64);
+            b.insert(address(21 - i), 21 - i as u64);
+        }
+        assert_eq!(encode_map(&a), encode_map(&b));
+
+        // The same two entries written in descending order.
+        let mut unordered = vec![2];
+        for i in [2, 1] {
+            unordered.extend_from_slice(&[i; 20]);
+            unordered.extend_from_slice(&(i as u64).to_le_bytes());
+        }
+        let err = HashMap::<Address, u64>::decode_binary(&mut unordered.as_slice(), AccountMapDecoder::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_fixed_bytes_have_no_length_prefix() {
+        let hash = Hash::random();
+        let mut buf = vec![];
+        assert!(hash.encode_binary(&mut buf).is_ok());
+        assert_eq!(buf.len(), 32);
+        assert_eq!(buf, hash.to_vec());
+
+        let bytes: [u8; 32] = buf.clone().try_into().unwrap();
+        let mut array = vec![];
+        assert!(bytes.encode_binary(&mut array, FixedBytesEncoder::new()).is_ok());
+        assert_eq!(array, buf);
+        assert_eq!(*<[u8; 32]>::decode_binary(&mut array.as_slice(), FixedBytesDecoder::new()).unwrap(), bytes);
+        assert_eq!(Hash::decode_binary(&mut buf.as_slice()).unwrap(), hash);
+
+        let err = <[u8; 32]>::decode_binary(&mut &buf[..31], FixedBytesDecoder::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(Hash::decode_binary(&mut &buf[..31]).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        let cases = [
+            (0, 1), (1, 1), (127, 1), (128, 2), (300, 2), (16_383, 2), (16_384, 3),
+            (u32::MAX as u64, 5), (u64::MAX - 1, 10), (u64::MAX, 10),
+        ];
+        for (value, len) in cases {
+            let mut buf = vec![];
+            write_varint(&mut buf, value).unwrap();
+            assert_eq!(buf.len(), len, "length of {}", value);
+            assert_eq!(varint_len(value), len, "varint_len of {}", value);
+            assert_eq!(read_varint(&mut buf.as_slice()).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_varint_known_encodings() {
+        let mut buf = vec![];
+        write_varint(&mut buf, 300).unwrap();
+        assert_eq!(buf, [0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_varint_rejects_bad_encodings() {
+        let invalid = |bytes: &[u8]| read_varint(&mut &bytes[..]).unwrap_err().kind();
+
+        // Zero and one padded with an empty continuation group.
+        assert_eq!(invalid(&[0x80, 0x00]), ErrorKind::InvalidData);
+        assert_eq!(invalid(&[0x81, 0x80, 0x00]), ErrorKind::InvalidData);
+
+        // u64::MAX with its tenth byte carrying an extra bit.
+        let mut overflow = [0xffu8; 10];
+        overflow[9] = 0x02;
+        assert_eq!(invalid(&overflow), ErrorKind::InvalidData);
+        assert_eq!(invalid(&[0xff; 11]), ErrorKind::InvalidData);
+
+        assert_eq!(invalid(&[0x80]), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_tx_decoder_rejects_huge_data_length() {
+        let mut buf = vec![];
+        write_varint(&mut buf, u64::MAX).unwrap();
+        let err = Transaction::decode_binary(&mut buf.as_slice(), TxDecoder::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut buf = vec![];
+        write_varint(&mut buf, MAX_TX_DATA_BYTES as u64 + 1).unwrap();
+        let err = Transaction::decode_binary(&mut buf.as_slice(), TxDecoder::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // Nine bytes and the kind tag.
+        let tx = Transaction::new(vec![0; 9]);
+        let bytes = tx.as_bytes();
+        assert!(Transaction::decode_binary(&mut bytes.as_slice(), TxDecoder::with_max_data_bytes(10)).is_ok());
+        let err = Transaction::decode_binary(&mut bytes.as_slice(), TxDecoder::with_max_data_bytes(9)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
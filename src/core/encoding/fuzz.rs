@@ -0,0 +1,121 @@
+//! Hostile input for the decoders: random bytes, and valid encodings with
+//! bytes flipped, inserted or cut off. Decoding may fail but must never
+//! panic. The inputs come from a fixed seed, so a failure reproduces.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{core::{block::{Block, Header}, transaction::Transaction, transfer::Transfer}, crypto::keypair::PrivateKey, types::address::Address};
+
+use super::{Encode, Decode, HeaderEncoder, HeaderDecoder, TxEncoder, TxDecoder, BlockEncoder, BlockDecoder};
+
+const ROUNDS: usize = 2000;
+
+/// Runs every decoder over `bytes`, discarding the results.
+fn decode_all(bytes: &[u8]) {
+    let _ = Header::decode_binary(&mut &bytes[..], HeaderDecoder::new());
+    let _ = Transaction::decode_binary(&mut &bytes[..], TxDecoder::new());
+    let _ = Block::decode_binary(&mut &bytes[..], BlockDecoder::new());
+}
+
+fn random_bytes(rng: &mut StdRng, max_len: usize) -> Vec<u8> {
+    let len = rng.gen_range(0..=max_len);
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+/// `bytes` with a few random bytes overwritten, inserted or dropped, and
+/// possibly cut short.
+fn mutate(rng: &mut StdRng, bytes: &[u8]) -> Vec<u8> {
+    let mut bytes = bytes.to_vec();
+    for _ in 0..rng.gen_range(1..=4) {
+        let i = rng.gen_range(0..=bytes.len());
+        match rng.gen_range(0..3) {
+            0 if i < bytes.len() => bytes[i] = rng.gen(),
+            1 => bytes.insert(i, rng.gen()),
+            _ if i < bytes.len() => {
+                bytes.remove(i);
+            }
+            _ => {}
+        }
+    }
+    if rng.gen_bool(0.2) {
+        bytes.truncate(rng.gen_range(0..=bytes.len()));
+    }
+    bytes
+}
+
+/// Encodings of a signed block with a coinbase and a transfer, its header
+/// and its transactions.
+fn seeds() -> Vec<Vec<u8>> {
+    let key = PrivateKey::from_bytes(&[1; 32]).unwrap();
+    let transfer = Transfer {
+        from: key.generate_public().address(),
+        to: Address::from_bytes(&[2; 20]).unwrap(),
+        amount: 10,
+        nonce: 0,
+        fee: 1,
+    };
+    let mut tx = Transaction { valid_until: Some(9), ..Transaction::transfer(transfer) };
+    assert!(tx.sign(key.clone()).is_ok());
+
+    let mut b = Block::new(Block::random_block_seeded(3, 7).header, vec![tx]);
+    b.add_coinbase(key.generate_public().address(), 50);
+    assert!(b.sign(key).is_ok());
+
+    let mut seeds = vec![];
+    let mut block = vec![];
+    assert!(b.encode_binary(&mut block, BlockEncoder::new()).is_ok());
+    seeds.push(block);
+    let mut header = vec![];
+    assert!(b.header.encode_binary(&mut header, HeaderEncoder::new()).is_ok());
+    seeds.push(header);
+    for tx in &b.transactions {
+        let mut bytes = vec![];
+        assert!(tx.encode_binary(&mut bytes, TxEncoder::new()).is_ok());
+        seeds.push(bytes);
+    }
+    seeds
+}
+
+#[test]
+fn test_decode_random_bytes() {
+    let mut rng = StdRng::seed_from_u64(1);
+    for _ in 0..ROUNDS {
+        decode_all(&random_bytes(&mut rng, 512));
+    }
+}
+
+#[test]
+fn test_decode_mutated_encodings() {
+    let mut rng = StdRng::seed_from_u64(2);
+    for seed in seeds() {
+        decode_all(&seed);
+        for _ in 0..ROUNDS {
+            decode_all(&mutate(&mut rng, &seed));
+        }
+    }
+}
+
+#[test]
+fn test_decode_every_truncation() {
+    for seed in seeds() {
+        for len in 0..seed.len() {
+            decode_all(&seed[..len]);
+        }
+    }
+}
+
+/// Length prefixes claiming far more than follows must fail without
+/// allocating for the claim.
+#[test]
+fn test_decode_huge_length_prefixes() {
+    // A transaction whose data length varint is u64::MAX.
+    let mut tx = vec![0xff; 9];
+    tx.push(0x01);
+    assert!(Transaction::decode_binary(&mut tx.as_slice(), TxDecoder::new()).is_err());
+
+    // A valid header followed by a transaction count of u64::MAX.
+    let mut block = vec![];
+    assert!(Block::random_block(1).header.encode_binary(&mut block, HeaderEncoder::new()).is_ok());
+    block.extend(&tx);
+    assert!(Block::decode_binary(&mut block.as_slice(), BlockDecoder::new()).is_err());
+}
@@ -0,0 +1,276 @@
+//! Fixed byte encodings of known headers, transactions and blocks. Other
+//! implementations rely on the wire format, so any change that breaks one
+//! of these has to be deliberate.
+
+use crate::{core::{block::{Block, Header}, transaction::Transaction}, crypto::keypair::PrivateKey, types::{address::Address, hash::Hash}};
+
+use super::{Encode, Decode, HeaderEncoder, HeaderDecoder, TxEncoder, TxDecoder, BlockEncoder, BlockDecoder};
+
+const HEADER: &str = concat!(
+    // version 2, u32 little-endian
+    "02000000",
+    // data
+    "1111111111111111111111111111111111111111111111111111111111111111",
+    // prev_block
+    "2222222222222222222222222222222222222222222222222222222222222222",
+    // timestamp 1_700_000_000, i64 big-endian
+    "000000006553f100",
+    // height 42, u32 little-endian
+    "2a000000",
+    // nonce 7, u64 little-endian
+    "0700000000000000",
+);
+
+const HEADER_V3: &str = concat!(
+    // version 3
+    "03000000",
+    // data, prev_block, timestamp, height and nonce as in HEADER
+    "1111111111111111111111111111111111111111111111111111111111111111",
+    "2222222222222222222222222222222222222222222222222222222222222222",
+    "000000006553f100",
+    "2a000000",
+    "0700000000000000",
+    // difficulty 20, u32 little-endian
+    "14000000",
+);
+
+const HEADER_V4: &str = concat!(
+    // version 4
+    "04000000",
+    // everything up to the difficulty as in HEADER_V3
+    "1111111111111111111111111111111111111111111111111111111111111111",
+    "2222222222222222222222222222222222222222222222222222222222222222",
+    "000000006553f100",
+    "2a000000",
+    "0700000000000000",
+    "14000000",
+    // state_root
+    "4444444444444444444444444444444444444444444444444444444444444444",
+);
+
+const UNSIGNED_TX: &str = concat!(
+    // data length 5 as a varint, then "hello"
+    "0568656c6c6f",
+    // no from, no to
+    "00",
+    "00",
+    // amount 0, u64 little-endian
+    "0000000000000000",
+    // no valid_until
+    "00",
+    // no key
+    "00",
+    // no signature
+    "00",
+);
+
+const SIGNED_TX: &str = concat!(
+    // data length 5 as a varint, then "hello"
+    "0568656c6c6f",
+    // from present, the address of the key
+    "01060489d0c2573dc99945f87ae809063aacd6b34a",
+    // to present
+    "013333333333333333333333333333333333333333",
+    // amount 1000, u64 little-endian
+    "e803000000000000",
+    // valid_until present, 100 as a u32 little-endian
+    "0164000000",
+    // key present, 33-byte compressed SEC1
+    "01026ff03b949241ce1dadd43519e6960e0a85b41a69a05c328103aa2bce1594ca16",
+    // signature present, 64 bytes, over `TX_SIGNING_TAG` followed by the
+    // unsigned encoding: every field above but the key
+    "01cc320a56ba85867e156cc91b8a9bd7eb43f2f65a0cc48482d0dec92eb47f2c5026ab4288556bcf73b2af057c39975283e16be9895ab591596e1797cc9c83e09b",
+);
+
+const EMPTY_TX: &str = concat!(
+    // no data
+    "00",
+    // no from, no to, amount 0, no valid_until, no key, no signature
+    "00",
+    "00",
+    "0000000000000000",
+    "00",
+    "00",
+    "00",
+);
+
+const MIXED_TX: &str = concat!(
+    // data length 2 as a varint, then "hi"
+    "026869",
+    // no from
+    "00",
+    // to present
+    "013333333333333333333333333333333333333333",
+    // amount 5, u64 little-endian
+    "0500000000000000",
+    // valid_until present, 7 as a u32 little-endian
+    "0107000000",
+    // key present, the same key as in SIGNED_TX
+    "01026ff03b949241ce1dadd43519e6960e0a85b41a69a05c328103aa2bce1594ca16",
+    // no signature
+    "00",
+);
+
+const SIGNED_BLOCK: &str = concat!(
+    // version 2
+    "02000000",
+    // data, the Merkle root of the one transaction
+    "7208802db55d8673272d4ab1c7066b94eac95c98a0815cd9dd805e00b6e3bb3e",
+    // prev_block, timestamp, height and nonce as in HEADER
+    "2222222222222222222222222222222222222222222222222222222222222222000000006553f1002a0000000700000000000000",
+    // one transaction
+    "01",
+    // SIGNED_TX
+    "0568656c6c6f01060489d0c2573dc99945f87ae809063aacd6b34a013333333333333333333333333333333333333333e8030000000000000164000000",
+    "01026ff03b949241ce1dadd43519e6960e0a85b41a69a05c328103aa2bce1594ca16",
+    "01cc320a56ba85867e156cc91b8a9bd7eb43f2f65a0cc48482d0dec92eb47f2c5026ab4288556bcf73b2af057c39975283e16be9895ab591596e1797cc9c83e09b",
+    // signature present, 64 bytes, over `BLOCK_SIGNING_TAG`, the header and
+    // the validator key
+    "0115a604f1b249358848eef19d82935988adc29bfd38d33c3d161fb894ead30cec1bdd67aaff7c5fdb2d5429994f99e7626e159bcfee4e82d8a4bd337c22b2a2eb",
+    // validator present, 33-byte compressed SEC1
+    "0102550f471003f3df97c3df506ac797f6721fb1a1fb7b8f6f83d224498a65c88e24",
+);
+
+fn key(byte: u8) -> PrivateKey {
+    PrivateKey::from_bytes(&[byte; 32]).unwrap()
+}
+
+fn header() -> Header {
+    Header {
+        version: 2,
+        data: Hash::from_bytes(&[0x11; 32]).unwrap(),
+        prev_block: Hash::from_bytes(&[0x22; 32]).unwrap(),
+        timestamp: 1_700_000_000,
+        height: 42,
+        nonce: 7,
+        difficulty: 0,
+        state_root: Hash::zero(),
+    }
+}
+
+/// An unsigned transaction with `data` exactly as given. The vectors pin
+/// the encoding of the fields, not the kind tag the constructors add.
+fn with_data(data: &[u8]) -> Transaction {
+    Transaction { data: data.to_vec(), ..Transaction::raw(&[]) }
+}
+
+fn signed_tx() -> Transaction {
+    let mut tx = Transaction {
+        from: Some(key(1).generate_public().address()),
+        to: Some(Address::from_bytes(&[0x33; 20]).unwrap()),
+        amount: 1000,
+        valid_until: Some(100),
+        ..with_data(b"hello")
+    };
+    assert!(tx.sign(key(1)).is_ok());
+    tx
+}
+
+fn signed_block() -> Block {
+    let mut b = Block::try_new(header(), vec![signed_tx()]).unwrap();
+    assert!(b.sign(key(2)).is_ok());
+    b
+}
+
+#[test]
+fn test_header_vector() {
+    let mut buf = vec![];
+    assert!(header().encode_binary(&mut buf, HeaderEncoder::new()).is_ok());
+    assert_eq!(hex::encode(&buf), HEADER);
+
+    let bytes = hex::decode(HEADER).unwrap();
+    assert_eq!(*Header::decode_binary(&mut bytes.as_slice(), HeaderDecoder::new()).unwrap(), header());
+
+    let v3 = Header { version: 3, difficulty: 20, ..header() };
+    let mut buf = vec![];
+    assert!(v3.encode_binary(&mut buf, HeaderEncoder::new()).is_ok());
+    assert_eq!(hex::encode(&buf), HEADER_V3);
+
+    let bytes = hex::decode(HEADER_V3).unwrap();
+    assert_eq!(*Header::decode_binary(&mut bytes.as_slice(), HeaderDecoder::new()).unwrap(), v3);
+
+    let v4 = Header { version: 4, state_root: Hash::from_bytes(&[0x44; 32]).unwrap(), ..v3 };
+    let mut buf = vec![];
+    assert!(v4.encode_binary(&mut buf, HeaderEncoder::new()).is_ok());
+    assert_eq!(hex::encode(&buf), HEADER_V4);
+
+    let bytes = hex::decode(HEADER_V4).unwrap();
+    assert_eq!(*Header::decode_binary(&mut bytes.as_slice(), HeaderDecoder::new()).unwrap(), v4);
+}
+
+#[test]
+fn test_transaction_vectors() {
+    for (tx, expected) in [(with_data(b"hello"), UNSIGNED_TX), (signed_tx(), SIGNED_TX)] {
+        let mut buf = vec![];
+        assert!(tx.encode_binary(&mut buf, TxEncoder::new()).is_ok());
+        assert_eq!(hex::encode(&buf), expected);
+
+        let bytes = hex::decode(expected).unwrap();
+        assert_eq!(*Transaction::decode_binary(&mut bytes.as_slice(), TxDecoder::new()).unwrap(), tx);
+    }
+}
+
+#[test]
+fn test_transaction_presence_flags() {
+    let mixed = Transaction {
+        to: Some(Address::from_bytes(&[0x33; 20]).unwrap()),
+        amount: 5,
+        valid_until: Some(7),
+        key: Some(key(1).generate_public()),
+        ..with_data(b"hi")
+    };
+    for (tx, expected) in [(with_data(&[]), EMPTY_TX), (mixed, MIXED_TX), (signed_tx(), SIGNED_TX)] {
+        let mut buf = vec![];
+        assert!(tx.encode_binary(&mut buf, TxEncoder::new()).is_ok());
+        assert_eq!(hex::encode(&buf), expected);
+
+        let bytes = hex::decode(expected).unwrap();
+        assert_eq!(*Transaction::decode_binary(&mut bytes.as_slice(), TxDecoder::new()).unwrap(), tx);
+    }
+
+    // The `from` presence byte of the empty transaction may only be 0 or 1.
+    let mut bytes = hex::decode(EMPTY_TX).unwrap();
+    bytes[1] = 2;
+    let err = Transaction::decode_binary(&mut bytes.as_slice(), TxDecoder::new()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_signed_block_vector() {
+    let b = signed_block();
+    let mut buf = vec![];
+    assert!(b.encode_binary(&mut buf, BlockEncoder::new()).is_ok());
+    assert_eq!(hex::encode(&buf), SIGNED_BLOCK);
+
+    let bytes = hex::decode(SIGNED_BLOCK).unwrap();
+    let decoded = *Block::decode_binary(&mut bytes.as_slice(), BlockDecoder::new()).unwrap();
+    assert_eq!(decoded, b);
+    assert!(decoded.verify_full().is_ok());
+}
+
+#[test]
+fn test_signed_block_rejects_corrupt_signature() {
+    let bytes = hex::decode(SIGNED_BLOCK).unwrap();
+    // The 64 signature bytes are followed by the validator.
+    let start = bytes.len() - 64 - 34;
+    for fill in [0x00, 0xff] {
+        // r, then s
+        for scalar in [start, start + 32] {
+            let mut corrupt = bytes.clone();
+            corrupt[scalar..scalar + 32].fill(fill);
+            let err = Block::decode_binary(&mut corrupt.as_slice(), BlockDecoder::new()).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+
+    // Flipping a single bit mostly leaves a well-formed signature, which
+    // then no longer verifies.
+    for i in start..start + 64 {
+        let mut flipped = bytes.clone();
+        flipped[i] ^= 0x80;
+        match Block::decode_binary(&mut flipped.as_slice(), BlockDecoder::new()) {
+            Ok(b) => assert!(b.verify().is_err()),
+            Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+        }
+    }
+}
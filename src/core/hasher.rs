@@ -1,45 +1,55 @@
-use sha2::{Sha256, Digest};
+use crate::{types::hash::Hash, crypto::hasher::{self, Sha256Hasher}};
 
-use crate::types::hash::Hash;
-
-use super::{block::{Block, Header}, encoding::{Encode, HeaderEncoder}, transaction::Transaction};
+use super::{block::Header, transaction::Transaction};
 
 pub trait Hasher<T> {
     fn hash(&self, obj: &T) -> Result<Hash, String>;
 }
 
-pub struct BlockHasher {
-    // encoder: dyn Encoder<Header>
+/// Hashes the encoded header with `H`, SHA256 unless built with
+/// `with_hasher`.
+pub struct BlockHasher<H: hasher::Hasher = Sha256Hasher> {
+    hasher: H,
 }
 
 impl BlockHasher {
     pub fn new() -> BlockHasher {
-        BlockHasher {}
+        BlockHasher::with_hasher(Sha256Hasher)
     }
 }
 
-impl Hasher<Header> for BlockHasher {
+impl<H: hasher::Hasher> BlockHasher<H> {
+    pub fn with_hasher(hasher: H) -> BlockHasher<H> {
+        BlockHasher { hasher }
+    }
+}
+
+impl<H: hasher::Hasher> Hasher<Header> for BlockHasher<H> {
     fn hash(&self, obj: &Header) -> Result<Hash, String> {
-        let mut hasher = Sha256::new();
-        hasher.update(obj.as_bytes());
-        let h = hasher.finalize();
-        Hash::from_bytes(&h)
+        Ok(self.hasher.hash(&obj.as_bytes().map_err(|e| e.to_string())?))
     }
 }
 
-pub struct TxHasher {}
+pub struct TxHasher<H: hasher::Hasher = Sha256Hasher> {
+    hasher: H,
+}
 
 impl TxHasher {
     pub fn new() -> TxHasher {
-        TxHasher {}
+        TxHasher::with_hasher(Sha256Hasher)
     }
 }
 
-impl Hasher<Transaction> for TxHasher {
+impl<H: hasher::Hasher> TxHasher<H> {
+    pub fn with_hasher(hasher: H) -> TxHasher<H> {
+        TxHasher { hasher }
+    }
+}
+
+/// Hashes the full encoded transaction, key and signature included, so signed
+/// and unsigned copies of the same data have different identities.
+impl<H: hasher::Hasher> Hasher<Transaction> for TxHasher<H> {
     fn hash(&self, obj: &Transaction) -> Result<Hash, String> {
-        let mut hasher = Sha256::new();
-        hasher.update(obj.data.clone());
-        let h = hasher.finalize();
-        Hash::from_bytes(&h)
+        Ok(self.hasher.hash(&obj.as_bytes()))
     }
-}
\ No newline at end of file
+}
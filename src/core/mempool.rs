@@ -0,0 +1,446 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use chrono::Utc;
+use crate::core::{transaction::Transaction, hasher::TxHasher};
+use crate::state::account::AccountState;
+use crate::types::{address::Address, hash::Hash};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum MempoolError {
+    /// A transaction with this hash is already pending.
+    Duplicate(Hash),
+    /// The transaction failed verification.
+    Invalid(String),
+    /// The transaction pays `fee_rate` per encoded byte, below the
+    /// mempool's `min_fee_rate`.
+    FeeTooLow { fee_rate: u64, min_fee_rate: u64 },
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::Duplicate(hash) => write!(f, "transaction {} is already in the mempool", hash),
+            MempoolError::Invalid(e) => write!(f, "invalid transaction: {}", e),
+            MempoolError::FeeTooLow { fee_rate, min_fee_rate } => {
+                write!(f, "fee rate {} per byte is below the minimum of {}", fee_rate, min_fee_rate)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+/// A pending transaction and when, in Unix seconds, it was added.
+struct Entry {
+    tx: Transaction,
+    added: i64,
+}
+
+/// Verified transactions waiting to be included in a block, keyed by hash.
+/// Clones share the same pending transactions, e.g. between a network task
+/// adding them and a block `Producer` draining them.
+pub struct Mempool {
+    transactions: Arc<RwLock<HashMap<Hash, Entry>>>,
+    min_fee_rate: u64,
+    #[cfg(feature = "metrics")]
+    metrics: crate::metrics::Metrics,
+}
+
+impl Clone for Mempool {
+    fn clone(&self) -> Self {
+        Mempool {
+            transactions: self.transactions.clone(),
+            min_fee_rate: self.min_fee_rate,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+impl Mempool {
+    pub fn new() -> Mempool {
+        Mempool {
+            transactions: Arc::new(HashMap::new().into()),
+            min_fee_rate: 0,
+            #[cfg(feature = "metrics")]
+            metrics: crate::metrics::Metrics::new(),
+        }
+    }
+
+    /// Keeps `mempool_size` in `metrics` up to date from now on. Clones
+    /// made before the call keep updating the old metrics.
+    #[cfg(feature = "metrics")]
+    pub fn set_metrics(&mut self, metrics: crate::metrics::Metrics) {
+        metrics.set_mempool_size(self.len());
+        self.metrics = metrics;
+    }
+
+    /// Fee per encoded byte a transaction must pay for `add` to accept it.
+    /// Zero, the default, accepts any fee. Clones made before the call keep
+    /// the old floor, and transactions already pending are kept.
+    pub fn set_min_fee_rate(&mut self, min_fee_rate: u64) {
+        self.min_fee_rate = min_fee_rate;
+    }
+
+    pub fn add(&mut self, mut tx: Transaction) -> Result<(), MempoolError> {
+        tx.verify().map_err(MempoolError::Invalid)?;
+
+        let (fee, size) = (tx.fee(), tx.as_bytes().len() as u64);
+        if fee < self.min_fee_rate.saturating_mul(size) {
+            return Err(MempoolError::FeeTooLow { fee_rate: fee / size, min_fee_rate: self.min_fee_rate });
+        }
+
+        let mut transactions = self.transactions.write().unwrap();
+        let hash = tx.hash(Box::new(TxHasher::new()));
+        if transactions.contains_key(&hash) {
+            return Err(MempoolError::Duplicate(hash));
+        }
+        transactions.insert(hash, Entry { tx, added: Utc::now().timestamp() });
+        #[cfg(feature = "metrics")]
+        self.metrics.set_mempool_size(transactions.len());
+        Ok(())
+    }
+
+    pub fn has(&self, hash: Hash) -> bool {
+        let transactions = self.transactions.read().unwrap();
+        transactions.contains_key(&hash)
+    }
+
+    /// The pending transaction with this hash.
+    pub fn get(&self, hash: &Hash) -> Option<Transaction> {
+        let transactions = self.transactions.read().unwrap();
+        transactions.get(hash).map(|e| e.tx.clone())
+    }
+
+    /// All pending transactions, in no particular order.
+    pub fn pending(&self) -> Vec<Transaction> {
+        let transactions = self.transactions.read().unwrap();
+        transactions.values().map(|e| e.tx.clone()).collect()
+    }
+
+    /// Up to `max_txs` pending transactions, highest fee first, with ties
+    /// broken by ascending hash. Transactions that are not transfers pay no
+    /// fee.
+    ///
+    /// Transfers from one sender come out in ascending nonce order, starting
+    /// at the sender's next nonce in `state`, or at its lowest pending nonce
+    /// without one, and stopping at the first gap, so each applies on top
+    /// of the one before. A sender's transfer is only considered once all of
+    /// its lower nonces have been taken, so a cheap transfer can hold back
+    /// dearer ones after it.
+    pub fn select_for_block(&self, max_txs: usize, state: Option<&AccountState>) -> Vec<Transaction> {
+        let transactions = self.transactions.read().unwrap();
+        let mut ready: Vec<(u64, &Hash, &Transaction)> = vec![];
+        let mut queues: HashMap<Address, VecDeque<(u64, &Hash, &Transaction)>> = HashMap::new();
+        for (hash, e) in transactions.iter() {
+            match e.tx.decode_transfer() {
+                Ok(transfer) => queues.entry(transfer.from).or_default().push_back((transfer.nonce, hash, &e.tx)),
+                Err(_) => ready.push((0, hash, &e.tx)),
+            }
+        }
+
+        // Keep each sender's run of consecutive nonces from the expected one.
+        for (from, queue) in queues.iter_mut() {
+            queue.make_contiguous().sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(b.1)));
+            let mut next = match state {
+                Some(state) => state.nonce(from),
+                None => queue.front().map_or(0, |(nonce, _, _)| *nonce),
+            };
+            let mut run = 0;
+            while run < queue.len() && queue[run].0 == next {
+                run += 1;
+                next += 1;
+            }
+            queue.truncate(run);
+        }
+
+        let mut selected = vec![];
+        ready.extend(queues.values_mut().filter_map(|queue| queue.pop_front()).map(|(_, hash, tx)| (tx.fee(), hash, tx)));
+        while selected.len() < max_txs {
+            let best = match ready.iter().enumerate().max_by(|(_, a), (_, b)| a.0.cmp(&b.0).then_with(|| b.1.cmp(a.1))) {
+                Some((i, _)) => i,
+                None => break,
+            };
+            let (_, _, tx) = ready.swap_remove(best);
+            if let Ok(transfer) = tx.decode_transfer() {
+                if let Some((_, hash, next)) = queues.get_mut(&transfer.from).and_then(VecDeque::pop_front) {
+                    ready.push((next.fee(), hash, next));
+                }
+            }
+            selected.push(tx.clone());
+        }
+        selected
+    }
+
+    /// Drops the given transactions, e.g. once they are included in a block.
+    /// Unknown hashes are ignored.
+    pub fn remove(&mut self, hashes: &[Hash]) {
+        let mut transactions = self.transactions.write().unwrap();
+        for hash in hashes {
+            transactions.remove(hash);
+        }
+        #[cfg(feature = "metrics")]
+        self.metrics.set_mempool_size(transactions.len());
+    }
+
+    /// Drops transactions added more than `max_age_secs` ago, returning how
+    /// many were dropped.
+    pub fn evict_expired(&mut self, max_age_secs: i64) -> usize {
+        let cutoff = Utc::now().timestamp() - max_age_secs;
+        let mut transactions = self.transactions.write().unwrap();
+        let before = transactions.len();
+        transactions.retain(|_, e| e.added >= cutoff);
+        #[cfg(feature = "metrics")]
+        self.metrics.set_mempool_size(transactions.len());
+        before - transactions.len()
+    }
+
+    pub fn len(&self) -> usize {
+        let transactions = self.transactions.read().unwrap();
+        transactions.len()
+    }
+
+    pub fn flush(&mut self) -> Result<(), ()> {
+        let mut transactions = self.transactions.write().unwrap();
+
+        transactions.clear();
+        #[cfg(feature = "metrics")]
+        self.metrics.set_mempool_size(0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{core::transfer::Transfer, crypto::keypair::PrivateKey};
+
+    use super::*;
+
+    fn signed_tx(data: &[u8]) -> Transaction {
+        let mut tx = Transaction::new(data.to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+        tx
+    }
+
+    #[test]
+    fn test_tx_pool() {
+        let p = Mempool::new();
+        assert_eq!(p.len(), 0);
+    }
+
+    #[test]
+    fn test_tx_pool_add_tx() {
+        let mut p = Mempool::new();
+        let tx = signed_tx(b"fooo");
+        assert!(p.add(tx).is_ok());
+        assert_eq!(p.len(), 1);
+
+        let _ = Transaction::new(b"fooo".to_vec());
+        assert_eq!(p.len(), 1);
+
+        let tx = signed_tx(b"sway");
+        assert!(p.add(tx).is_ok());
+        assert_eq!(p.len(), 2);
+
+        assert!(p.flush().is_ok());
+        assert_eq!(p.len(), 0);
+    }
+
+    #[test]
+    fn test_mempool_rejects_duplicates() {
+        let mut p = Mempool::new();
+        let mut tx = signed_tx(b"fooo");
+        let hash = tx.hash(Box::new(TxHasher::new()));
+
+        assert!(p.add(tx.clone()).is_ok());
+        assert_eq!(p.add(tx), Err(MempoolError::Duplicate(hash)));
+        assert_eq!(p.len(), 1);
+    }
+
+    #[test]
+    fn test_mempool_rejects_unverified() {
+        let mut p = Mempool::new();
+        let tx = Transaction::new(b"fooo".to_vec());
+
+        assert!(matches!(p.add(tx), Err(MempoolError::Invalid(_))));
+        assert_eq!(p.len(), 0);
+    }
+
+    #[test]
+    fn test_evict_expired() {
+        let mut p = Mempool::new();
+        let mut old = signed_tx(b"old");
+        let old_hash = old.hash(Box::new(TxHasher::new()));
+        assert!(p.add(old).is_ok());
+        p.transactions.write().unwrap().get_mut(&old_hash).unwrap().added -= 3600;
+
+        let mut fresh = signed_tx(b"fresh");
+        let fresh_hash = fresh.hash(Box::new(TxHasher::new()));
+        assert!(p.add(fresh).is_ok());
+
+        assert_eq!(p.evict_expired(600), 1);
+        assert!(!p.has(old_hash));
+        assert!(p.has(fresh_hash));
+        assert_eq!(p.evict_expired(600), 0);
+    }
+
+    fn transfer_tx(fee: u64) -> Transaction {
+        let key = PrivateKey::generate_key();
+        let transfer = Transfer {
+            from: key.generate_public().address(),
+            to: PrivateKey::generate_key().generate_public().address(),
+            amount: 10,
+            nonce: 0,
+            fee,
+        };
+        let mut tx = Transaction::transfer(transfer);
+        assert!(tx.sign(key).is_ok());
+        tx
+    }
+
+    #[test]
+    fn test_select_for_block_by_fee() {
+        let mut p = Mempool::new();
+        for fee in [5, 1, 9, 3, 7] {
+            assert!(p.add(transfer_tx(fee)).is_ok());
+        }
+        assert!(p.add(signed_tx(b"no fee")).is_ok());
+
+        let fees: Vec<u64> = p.select_for_block(3, None).iter().map(Transaction::fee).collect();
+        assert_eq!(fees, vec![9, 7, 5]);
+        assert_eq!(p.select_for_block(10, None).len(), 6);
+        assert_eq!(p.select_for_block(10, None)[5].fee(), 0);
+    }
+
+    #[test]
+    fn test_select_for_block_breaks_ties_by_hash() {
+        let mut p = Mempool::new();
+        let mut hashes = vec![];
+        for _ in 0..4 {
+            let mut tx = transfer_tx(1);
+            hashes.push(tx.hash(Box::new(TxHasher::new())));
+            assert!(p.add(tx).is_ok());
+        }
+        hashes.sort();
+
+        let selected: Vec<Hash> = p
+            .select_for_block(4, None)
+            .into_iter()
+            .map(|mut tx| tx.hash(Box::new(TxHasher::new())))
+            .collect();
+        assert_eq!(selected, hashes);
+    }
+
+    fn nonce_tx(key: &PrivateKey, nonce: u64, fee: u64) -> Transaction {
+        let transfer = Transfer {
+            from: key.generate_public().address(),
+            to: PrivateKey::generate_key().generate_public().address(),
+            amount: 10,
+            nonce,
+            fee,
+        };
+        let mut tx = Transaction::transfer(transfer);
+        assert!(tx.sign(key.clone()).is_ok());
+        tx
+    }
+
+    fn nonces(txs: &[Transaction]) -> Vec<u64> {
+        txs.iter().map(|tx| tx.decode_transfer().unwrap().nonce).collect()
+    }
+
+    /// A state in which `key`'s next nonce is `nonce`.
+    fn state_at(key: &PrivateKey, nonce: u64) -> AccountState {
+        let mut state = AccountState::new();
+        assert!(state.credit(key.generate_public().address(), 1000).is_ok());
+        for n in 0..nonce {
+            assert!(state.apply(&nonce_tx(key, n, 0)).is_ok());
+        }
+        state
+    }
+
+    #[test]
+    fn test_min_fee_rate() {
+        let mut p = Mempool::new();
+        p.set_min_fee_rate(2);
+
+        let large = signed_tx(&[0; 1000]);
+        assert_eq!(p.add(large), Err(MempoolError::FeeTooLow { fee_rate: 0, min_fee_rate: 2 }));
+
+        let cheap = transfer_tx(1);
+        let size = cheap.as_bytes().len() as u64;
+        assert_eq!(p.add(cheap), Err(MempoolError::FeeTooLow { fee_rate: 0, min_fee_rate: 2 }));
+        assert_eq!(p.add(transfer_tx(2 * size - 1)), Err(MempoolError::FeeTooLow { fee_rate: 1, min_fee_rate: 2 }));
+
+        assert!(p.add(transfer_tx(2 * size)).is_ok());
+        assert!(p.add(transfer_tx(1000)).is_ok());
+        assert_eq!(p.len(), 2);
+    }
+
+    #[test]
+    fn test_select_for_block_orders_nonces() {
+        let key = PrivateKey::generate_key();
+        let mut p = Mempool::new();
+        // Later nonces pay more, and are submitted first.
+        for (nonce, fee) in [(7, 3), (5, 1), (6, 2)] {
+            assert!(p.add(nonce_tx(&key, nonce, fee)).is_ok());
+        }
+        assert!(p.add(transfer_tx(2)).is_ok());
+
+        let mut state = state_at(&key, 5);
+        let selected = p.select_for_block(10, Some(&state));
+        assert_eq!(selected.len(), 4);
+        let own: Vec<Transaction> = selected.iter().filter(|tx| tx.key == Some(key.generate_public())).cloned().collect();
+        assert_eq!(nonces(&own), vec![5, 6, 7]);
+        for tx in &own {
+            assert!(state.apply(tx).is_ok());
+        }
+
+        assert_eq!(nonces(&p.select_for_block(2, Some(&state_at(&key, 5)))), vec![0, 5]);
+        assert_eq!(p.select_for_block(10, Some(&state_at(&key, 4))).len(), 1);
+        assert_eq!(p.select_for_block(10, None).len(), 4);
+    }
+
+    #[test]
+    fn test_select_for_block_stops_at_gap() {
+        let key = PrivateKey::generate_key();
+        let mut p = Mempool::new();
+        assert!(p.add(nonce_tx(&key, 7, 9)).is_ok());
+        assert!(p.add(nonce_tx(&key, 5, 1)).is_ok());
+
+        assert_eq!(nonces(&p.select_for_block(10, Some(&state_at(&key, 5)))), vec![5]);
+        assert_eq!(nonces(&p.select_for_block(10, None)), vec![5]);
+    }
+
+    #[test]
+    fn test_mempool_remove() {
+        let mut p = Mempool::new();
+        let mut included = vec![];
+        for data in [b"foo", b"bar", b"baz"] {
+            let mut tx = signed_tx(data);
+            included.push(tx.hash(Box::new(TxHasher::new())));
+            assert!(p.add(tx).is_ok());
+        }
+        let kept = included.pop().unwrap();
+
+        p.remove(&included);
+        assert_eq!(p.len(), 1);
+        assert!(p.has(kept));
+        assert_eq!(p.pending().len(), 1);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_mempool_metrics() {
+        let metrics = crate::metrics::Metrics::new();
+        let mut p = Mempool::new();
+        p.set_metrics(metrics.clone());
+
+        assert!(p.add(signed_tx(b"foo")).is_ok());
+        assert!(p.add(signed_tx(b"bar")).is_ok());
+        assert_eq!(metrics.snapshot()["mempool_size"], 2.0);
+
+        assert!(p.flush().is_ok());
+        assert_eq!(metrics.snapshot()["mempool_size"], 0.0);
+    }
+}
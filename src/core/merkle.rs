@@ -0,0 +1,198 @@
+use crate::{types::hash::Hash, crypto::hasher::{Hasher, Sha256Hasher}};
+
+use super::transaction::Transaction;
+
+/// Computes the root of a binary SHA256 Merkle tree over the encoded
+/// transactions.
+///
+/// An empty transaction list has the zero hash as its root. When a level
+/// has an odd number of nodes the last one is paired with itself.
+pub fn merkle_root(txs: &[Transaction]) -> Hash {
+    merkle_root_with(txs, &Sha256Hasher)
+}
+
+/// `merkle_root` with the leaves and nodes hashed by `hasher`.
+pub fn merkle_root_with<H: Hasher>(txs: &[Transaction], hasher: &H) -> Hash {
+    if txs.is_empty() {
+        return Hash::zero();
+    }
+
+    let mut level: Vec<Hash> = txs.iter().map(|tx| leaf_hash(hasher, tx)).collect();
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(level[level.len() - 1]);
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| node_hash(hasher, &pair[0], &pair[1]))
+            .collect();
+    }
+    level[0]
+}
+
+/// Which side of the path a sibling hash sits on.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// The sibling hashes from a leaf up to the root, lowest level first.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MerkleProof {
+    pub siblings: Vec<(Hash, Side)>,
+}
+
+/// Builds a proof that `txs[index]` is included under `merkle_root(txs)`.
+///
+/// Panics if `index` is out of bounds.
+pub fn proof(txs: &[Transaction], index: usize) -> MerkleProof {
+    proof_with(txs, index, &Sha256Hasher)
+}
+
+/// `proof` for the tree of `merkle_root_with` and `hasher`.
+pub fn proof_with<H: Hasher>(txs: &[Transaction], index: usize, hasher: &H) -> MerkleProof {
+    assert!(index < txs.len(), "transaction index {} out of bounds", index);
+
+    let mut siblings = vec![];
+    let mut level: Vec<Hash> = txs.iter().map(|tx| leaf_hash(hasher, tx)).collect();
+    let mut index = index;
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(level[level.len() - 1]);
+        }
+        let sibling = if index.is_multiple_of(2) {
+            (level[index + 1], Side::Right)
+        } else {
+            (level[index - 1], Side::Left)
+        };
+        siblings.push(sibling);
+
+        level = level
+            .chunks(2)
+            .map(|pair| node_hash(hasher, &pair[0], &pair[1]))
+            .collect();
+        index /= 2;
+    }
+    MerkleProof { siblings }
+}
+
+/// Checks that `proof` leads from the transaction hash `tx_hash` to `root`.
+pub fn verify_proof(root: &Hash, tx_hash: &Hash, proof: &MerkleProof) -> bool {
+    verify_proof_with(root, tx_hash, proof, &Sha256Hasher)
+}
+
+/// `verify_proof` for a proof built by `proof_with` and `hasher`.
+pub fn verify_proof_with<H: Hasher>(root: &Hash, tx_hash: &Hash, proof: &MerkleProof, hasher: &H) -> bool {
+    let computed = proof.siblings.iter().fold(*tx_hash, |hash, (sibling, side)| match side {
+        Side::Left => node_hash(hasher, sibling, &hash),
+        Side::Right => node_hash(hasher, &hash, sibling),
+    });
+    computed == *root
+}
+
+/// The transaction hash, as `TxHasher::with_hasher(hasher)` computes it.
+fn leaf_hash<H: Hasher>(hasher: &H, tx: &Transaction) -> Hash {
+    hasher.hash(&tx.as_bytes())
+}
+
+fn node_hash<H: Hasher>(hasher: &H, left: &Hash, right: &Hash) -> Hash {
+    let mut buf = left.to_vec();
+    buf.extend_from_slice(&right.to_vec());
+    hasher.hash(&buf)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{core::{hasher::{Hasher as _, TxHasher}, transaction::Transaction}, crypto::hasher::{Hasher, Sha256Hasher}, types::hash::Hash};
+
+    use super::{merkle_root, merkle_root_with, proof, proof_with, verify_proof, verify_proof_with};
+
+    fn leaf_hash(tx: &Transaction) -> Hash {
+        super::leaf_hash(&Sha256Hasher, tx)
+    }
+
+    fn txs(n: usize) -> Vec<Transaction> {
+        (0..n)
+            .map(|i| Transaction::new(format!("tx-{}", i).into_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merkle_root_empty() {
+        assert_eq!(merkle_root(&[]), Hash::zero());
+    }
+
+    #[test]
+    fn test_merkle_root_single() {
+        let txs = txs(1);
+        assert_eq!(merkle_root(&txs), leaf_hash(&txs[0]));
+    }
+
+    #[test]
+    fn test_merkle_root_odd_duplicates_last() {
+        let mut txs = txs(3);
+        let odd = merkle_root(&txs);
+        txs.push(txs[2].clone());
+        assert_eq!(odd, merkle_root(&txs));
+    }
+
+    #[test]
+    fn test_merkle_root_changes_with_any_tx() {
+        let txs = txs(5);
+        let root = merkle_root(&txs);
+
+        for i in 0..txs.len() {
+            let mut mutated = txs.clone();
+            mutated[i].data.push(0);
+            assert_ne!(root, merkle_root(&mutated));
+        }
+    }
+
+    #[test]
+    fn test_merkle_proof_membership() {
+        for n in [1, 2, 5, 8] {
+            let txs = txs(n);
+            let root = merkle_root(&txs);
+
+            for i in [0, n / 2, n - 1] {
+                let p = proof(&txs, i);
+                assert!(verify_proof(&root, &leaf_hash(&txs[i]), &p), "tx {} of {}", i, n);
+            }
+        }
+    }
+
+    #[test]
+    fn test_merkle_proof_rejects_wrong_transaction() {
+        let txs = txs(5);
+        let root = merkle_root(&txs);
+        let p = proof(&txs, 2);
+
+        assert!(!verify_proof(&root, &leaf_hash(&txs[3]), &p));
+        assert!(!verify_proof(&root, &Hash::random(), &p));
+        assert!(!verify_proof(&Hash::random(), &leaf_hash(&txs[2]), &p));
+    }
+
+    struct DoubleSha256Hasher;
+
+    impl Hasher for DoubleSha256Hasher {
+        fn hash(&self, data: &[u8]) -> Hash {
+            Sha256Hasher.hash(&Sha256Hasher.hash(data).to_vec())
+        }
+    }
+
+    #[test]
+    fn test_merkle_root_with_other_hasher() {
+        let txs = txs(5);
+        assert_eq!(leaf_hash(&txs[0]), TxHasher::new().hash(&txs[0]).unwrap());
+
+        let root = merkle_root_with(&txs, &DoubleSha256Hasher);
+        assert_ne!(root, merkle_root(&txs));
+        assert_eq!(root, merkle_root_with(&txs, &DoubleSha256Hasher));
+
+        let p = proof_with(&txs, 3, &DoubleSha256Hasher);
+        let leaf = TxHasher::with_hasher(DoubleSha256Hasher).hash(&txs[3]).unwrap();
+        assert!(verify_proof_with(&root, &leaf, &p, &DoubleSha256Hasher));
+        assert!(!verify_proof(&root, &leaf, &p));
+    }
+}
@@ -1,19 +1,427 @@
-use super::block::Block;
+use std::{collections::HashMap, io};
+#[cfg(feature = "native")]
+use std::{collections::HashSet, fs::{File, OpenOptions}, io::{Read, Seek, SeekFrom, Write, BufReader, BufWriter}, path::{Path, PathBuf}};
+
+#[cfg(feature = "native")]
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::types::hash::Hash;
+
+use super::{block::Block, hasher::{BlockHasher, Hasher}};
+#[cfg(feature = "native")]
+use super::encoding::{BlockEncoder, BlockDecoder, Encode, Decode};
+
+/// Shortest hash prefix, in hex characters, accepted by `get_by_prefix`.
+pub const MIN_PREFIX_LEN: usize = 4;
 
 pub trait Storage {
-    fn put(&mut self, b: &Block) -> Result<(), ()>;
+    fn put_block(&mut self, block: &Block) -> io::Result<()>;
+    fn get_block(&self, hash: &Hash) -> io::Result<Option<Block>>;
+    fn get_by_height(&self, height: u32) -> io::Result<Option<Block>>;
+
+    /// Replaces the blocks hashing to `hashes` with their
+    /// `Block::without_body`, keeping only the header, signature and
+    /// validator. Unknown hashes are ignored.
+    fn prune_blocks(&mut self, hashes: &[Hash]) -> io::Result<()>;
+
+    /// Hashes of stored blocks whose hex starts with `prefix`, in order.
+    /// Fails with `InvalidInput` if the prefix is shorter than
+    /// `MIN_PREFIX_LEN` or not hex.
+    fn get_by_prefix(&self, prefix: &str) -> io::Result<Vec<Hash>>;
+
+    /// The one stored block hash starting with `prefix`. Fails with
+    /// `NotFound` if none match and `InvalidInput` if several do.
+    fn resolve_prefix(&self, prefix: &str) -> io::Result<Hash> {
+        match self.get_by_prefix(prefix)?.as_slice() {
+            [hash] => Ok(*hash),
+            [] => Err(io::Error::new(io::ErrorKind::NotFound, format!("no block hash starts with {}", prefix))),
+            matches => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("prefix {} is ambiguous, {} block hashes match", prefix, matches.len()),
+            )),
+        }
+    }
+}
+
+fn matching_prefix<'a, I: Iterator<Item = &'a Hash>>(hashes: I, prefix: &str) -> io::Result<Vec<Hash>> {
+    if prefix.len() < MIN_PREFIX_LEN || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("hash prefix must be at least {} hex characters", MIN_PREFIX_LEN),
+        ));
+    }
+
+    let prefix = prefix.to_ascii_lowercase();
+    let mut matches: Vec<Hash> = hashes.filter(|h| h.to_string().starts_with(&prefix)).copied().collect();
+    matches.sort();
+    Ok(matches)
+}
+
+fn block_hash(block: &Block) -> io::Result<Hash> {
+    BlockHasher::new()
+        .hash(&block.header)
+        .map_err(io::Error::other)
 }
 
-pub struct MemoryStore{}
+pub struct MemoryStorage {
+    blocks: HashMap<Hash, Block>,
+    heights: HashMap<u32, Hash>,
+}
 
-impl MemoryStore {
+impl MemoryStorage {
     pub fn new() -> Self {
-        MemoryStore {  }
+        MemoryStorage {
+            blocks: HashMap::new(),
+            heights: HashMap::new(),
+        }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn put_block(&mut self, block: &Block) -> io::Result<()> {
+        let hash = block_hash(block)?;
+        self.heights.insert(block.header.height, hash);
+        self.blocks.insert(hash, block.clone());
+        Ok(())
+    }
+
+    fn get_block(&self, hash: &Hash) -> io::Result<Option<Block>> {
+        Ok(self.blocks.get(hash).cloned())
+    }
+
+    fn get_by_height(&self, height: u32) -> io::Result<Option<Block>> {
+        match self.heights.get(&height) {
+            Some(hash) => self.get_block(hash),
+            None => Ok(None),
+        }
+    }
+
+    fn prune_blocks(&mut self, hashes: &[Hash]) -> io::Result<()> {
+        for hash in hashes {
+            if let Some(block) = self.blocks.get_mut(hash) {
+                *block = block.without_body();
+            }
+        }
+        Ok(())
+    }
+
+    fn get_by_prefix(&self, prefix: &str) -> io::Result<Vec<Hash>> {
+        matching_prefix(self.blocks.keys(), prefix)
+    }
+}
+
+/// Appends blocks to a file as records of a little-endian `u32` length
+/// followed by the encoded block, keeping an in-memory index of record
+/// offsets that is rebuilt from the file on open. Needs the `native`
+/// feature.
+#[cfg(feature = "native")]
+pub struct FileStorage {
+    path: PathBuf,
+    file: File,
+    offsets: HashMap<Hash, u64>,
+    heights: HashMap<u32, Hash>,
+}
+
+#[cfg(feature = "native")]
+impl FileStorage {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        let mut storage = FileStorage {
+            path,
+            file,
+            offsets: HashMap::new(),
+            heights: HashMap::new(),
+        };
+        storage.load_index()?;
+        Ok(storage)
+    }
+
+    /// Indexes every complete record. A record running past the end of the
+    /// file, as left by a crash while appending, is cut off so the next
+    /// `put_block` starts cleanly.
+    fn load_index(&mut self) -> io::Result<()> {
+        let len = self.file.metadata()?.len();
+        let mut reader = BufReader::new(File::open(&self.path)?);
+
+        let mut offset = 0;
+        while offset < len {
+            let complete = len - offset >= 4 && {
+                let record_len = reader.read_u32::<LittleEndian>()? as u64;
+                reader.seek(SeekFrom::Start(offset))?;
+                offset + 4 + record_len <= len
+            };
+            if !complete {
+                log::warn!("Dropping partial block record - path: {}, offset: {}", self.path.display(), offset);
+                self.file.set_len(offset)?;
+                break;
+            }
+
+            let block = Self::read_record(&mut reader)?;
+            let hash = block_hash(&block)?;
+            self.heights.insert(block.header.height, hash);
+            self.offsets.insert(hash, offset);
+            offset = reader.stream_position()?;
+        }
+        Ok(())
+    }
+
+    fn read_record<R: Read>(reader: &mut R) -> io::Result<Block> {
+        let len = reader.read_u32::<LittleEndian>()?;
+        let mut record = vec![];
+        reader.take(len as u64).read_to_end(&mut record)?;
+        if record.len() != len as usize {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "block record is truncated"));
+        }
+
+        let block = Block::decode_binary(&mut record.as_slice(), BlockDecoder::new())?;
+        Ok(*block)
+    }
+
+    fn write_record<W: Write>(writer: &mut W, block: &Block) -> io::Result<()> {
+        let mut record = vec![];
+        block.encode_binary(&mut record, BlockEncoder::new())?;
+        writer.write_u32::<LittleEndian>(record.len() as u32)?;
+        writer.write_all(&record)
     }
 }
 
-impl Storage for MemoryStore {
-    fn put(&mut self, _b: &Block) -> Result<(), ()> {
+#[cfg(feature = "native")]
+impl Storage for FileStorage {
+    fn put_block(&mut self, block: &Block) -> io::Result<()> {
+        let hash = block_hash(block)?;
+
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        Self::write_record(&mut self.file, block)?;
+        self.file.flush()?;
+
+        self.heights.insert(block.header.height, hash);
+        self.offsets.insert(hash, offset);
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn get_block(&self, hash: &Hash) -> io::Result<Option<Block>> {
+        let offset = match self.offsets.get(hash) {
+            Some(offset) => *offset,
+            None => return Ok(None),
+        };
+
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(offset))?;
+        Ok(Some(Self::read_record(&mut reader)?))
+    }
+
+    fn get_by_height(&self, height: u32) -> io::Result<Option<Block>> {
+        match self.heights.get(&height) {
+            Some(hash) => self.get_block(hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Rewrites the file with the pruned blocks stripped, and without
+    /// records superseded by a later `put_block` of the same block, then
+    /// replaces the old file with it. Every call copies the whole file, so
+    /// it is better to prune many blocks at once than a few at a time.
+    fn prune_blocks(&mut self, hashes: &[Hash]) -> io::Result<()> {
+        let pruned: HashSet<&Hash> = hashes.iter().filter(|hash| self.offsets.contains_key(hash)).collect();
+        if pruned.is_empty() {
+            return Ok(());
+        }
+
+        let rewritten = self.path.with_extension("rewrite");
+        let mut writer = BufWriter::new(File::create(&rewritten)?);
+        let len = self.file.metadata()?.len();
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut offset = 0;
+        while offset < len {
+            let block = Self::read_record(&mut reader)?;
+            let hash = block_hash(&block)?;
+            if self.offsets.get(&hash) == Some(&offset) {
+                let block = if pruned.contains(&hash) { block.without_body() } else { block };
+                Self::write_record(&mut writer, &block)?;
+            }
+            offset = reader.stream_position()?;
+        }
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+        std::fs::rename(&rewritten, &self.path)?;
+        *self = FileStorage::open(&self.path)?;
+        Ok(())
+    }
+
+    fn get_by_prefix(&self, prefix: &str) -> io::Result<Vec<Hash>> {
+        matching_prefix(self.offsets.keys(), prefix)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{core::{block::Block, transaction::Transaction, hasher::BlockHasher}, crypto::keypair::PrivateKey, types::hash::Hash};
+
+    use std::io::ErrorKind;
+
+    use super::{Storage, MemoryStorage};
+    #[cfg(feature = "native")]
+    use super::FileStorage;
+
+    fn signed_block(height: u32) -> Block {
+        let mut tx = Transaction::new(b"foo".to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+
+        let mut b = Block::new(Block::random_block(height).header, vec![tx]);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        b
+    }
+
+    fn assert_round_trip<S: Storage>(storage: &mut S) {
+        let mut b = signed_block(7);
+        let hash = b.hash(Box::new(BlockHasher::new()));
+
+        assert!(storage.put_block(&b).is_ok());
+        assert_eq!(storage.get_block(&hash).unwrap(), Some(b.clone()));
+        assert_eq!(storage.get_by_height(7).unwrap(), Some(b));
+
+        assert_eq!(storage.get_block(&Hash::random()).unwrap(), None);
+        assert_eq!(storage.get_by_height(8).unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_by_prefix() {
+        let mut storage = MemoryStorage::new();
+        let mut hashes = vec![];
+        for height in 0..5 {
+            let mut b = signed_block(height);
+            hashes.push(b.hash(Box::new(BlockHasher::new())));
+            assert!(storage.put_block(&b).is_ok());
+        }
+
+        // Eight hex characters are enough to tell these few hashes apart.
+        let hash = hashes[3];
+        let prefix = &hash.to_string()[..8];
+        assert_eq!(storage.get_by_prefix(prefix).unwrap(), vec![hash]);
+        assert_eq!(storage.get_by_prefix(&prefix.to_uppercase()).unwrap(), vec![hash]);
+        assert_eq!(storage.resolve_prefix(prefix).unwrap(), hash);
+
+        let missing = if prefix.starts_with("ffff") { "0000" } else { "ffff" };
+        assert!(!hashes.iter().any(|h| h.to_string().starts_with(missing)));
+        assert_eq!(storage.get_by_prefix(missing).unwrap(), vec![]);
+        assert_eq!(storage.resolve_prefix(missing).unwrap_err().kind(), ErrorKind::NotFound);
+
+        assert_eq!(storage.get_by_prefix("abc").unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(storage.get_by_prefix("zzzz").unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_resolve_ambiguous_prefix() {
+        let mut storage = MemoryStorage::new();
+        let mut seen = std::collections::HashSet::new();
+
+        // Add random blocks until two hashes share a four-character prefix,
+        // which takes a few hundred on average.
+        let prefix = loop {
+            let mut b = Block::random_block(1);
+            let prefix = b.hash(Box::new(BlockHasher::new())).to_string()[..4].to_owned();
+            assert!(storage.put_block(&b).is_ok());
+            if !seen.insert(prefix.clone()) {
+                break prefix;
+            }
+        };
+
+        assert_eq!(storage.get_by_prefix(&prefix).unwrap().len(), 2);
+        assert_eq!(storage.resolve_prefix(&prefix).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_memory_storage_round_trip() {
+        assert_round_trip(&mut MemoryStorage::new());
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn test_file_storage_round_trip() {
+        let path = std::env::temp_dir().join(format!("blockchain-{}.db", Hash::random()));
+
+        let mut storage = FileStorage::open(&path).unwrap();
+        assert_round_trip(&mut storage);
+
+        let b = signed_block(8);
+        assert!(storage.put_block(&b).is_ok());
+        drop(storage);
+
+        let reopened = FileStorage::open(&path).unwrap();
+        assert_eq!(reopened.get_by_height(8).unwrap(), Some(b));
+        assert!(reopened.get_by_height(7).unwrap().is_some());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn test_file_storage_drops_partial_record() {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("blockchain-{}.db", Hash::random()));
+
+        let mut storage = FileStorage::open(&path).unwrap();
+        let b = signed_block(1);
+        assert!(storage.put_block(&b).is_ok());
+        drop(storage);
+        let len = std::fs::metadata(&path).unwrap().len();
+
+        // A record whose length prefix promises more than was written.
+        let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[200, 0, 0, 0, 1, 2, 3]).unwrap();
+        drop(file);
+
+        let mut storage = FileStorage::open(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        assert_eq!(storage.get_by_height(1).unwrap(), Some(b));
+        assert!(storage.put_block(&signed_block(2)).is_ok());
+        drop(storage);
+
+        let reopened = FileStorage::open(&path).unwrap();
+        assert!(reopened.get_by_height(2).unwrap().is_some());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_memory_storage_prune_blocks() {
+        let mut storage = MemoryStorage::new();
+        let b = signed_block(3);
+        let hash = b.header.hash();
+        assert!(storage.put_block(&b).is_ok());
+        assert!(storage.put_block(&signed_block(4)).is_ok());
+
+        assert!(storage.prune_blocks(&[hash, Hash::random()]).is_ok());
+        assert_eq!(storage.get_block(&hash).unwrap(), Some(b.without_body()));
+        assert_eq!(storage.get_by_height(3).unwrap(), Some(b.without_body()));
+        assert!(!storage.get_by_height(4).unwrap().unwrap().is_pruned());
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn test_file_storage_prune_blocks() {
+        let path = std::env::temp_dir().join(format!("blockchain-{}.db", Hash::random()));
+
+        let mut storage = FileStorage::open(&path).unwrap();
+        let blocks: Vec<Block> = (0..3).map(signed_block).collect();
+        for b in &blocks {
+            assert!(storage.put_block(b).is_ok());
+        }
+        let len = std::fs::metadata(&path).unwrap().len();
+
+        let hash = blocks[1].header.hash();
+        assert!(storage.prune_blocks(&[hash]).is_ok());
+        assert!(std::fs::metadata(&path).unwrap().len() < len);
+        assert_eq!(storage.get_block(&hash).unwrap(), Some(blocks[1].without_body()));
+        assert_eq!(storage.get_by_height(2).unwrap().as_ref(), Some(&blocks[2]));
+        drop(storage);
+
+        let reopened = FileStorage::open(&path).unwrap();
+        assert_eq!(reopened.get_by_height(1).unwrap(), Some(blocks[1].without_body()));
+        assert_eq!(reopened.get_by_height(0).unwrap().as_ref(), Some(&blocks[0]));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
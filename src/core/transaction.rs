@@ -1,83 +1,377 @@
-use std::{io::{Write, Read}};
+use std::io::{self, Write, Read};
 use encode_decode_derive::{Encode, Decode};
-use p256::ecdsa::Signature;
-use crate::{types::hash::Hash, core::encoding::{Encode, Decode, Encoder, Decoder}, crypto::keypair::{PublicKey, PrivateKey}};
+use crate::{types::{hash::Hash, address::Address}, state::utxo::UtxoTransaction, core::encoding::{Encode, Decode, Encoder, Decoder, TxEncoder}, crypto::{keypair::VerifyError, scheme::{ChainKey, ChainSignature, ChainSigner, Signer, Verifier}}};
 
-use super::hasher::{TxHasher, Hasher};
+use super::{hasher::Hasher, transfer::Transfer};
 
-#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+/// Most bytes of `data` a decoded transaction may carry. The length prefix
+/// is checked against this before anything is read.
+pub const MAX_TX_DATA_BYTES: usize = 64 * 1024;
+
+/// Prefix of the bytes a transaction signature covers, keeping it apart
+/// from block signatures, which start with `BLOCK_SIGNING_TAG`.
+pub const TX_SIGNING_TAG: &[u8] = b"tx-v1";
+
+/// What the payload of a `Transaction` built by `new`, `raw`, `transfer`,
+/// `contract` or `utxo` is, as given by the first byte of its `data`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TxKind {
+    /// Opaque bytes.
+    Raw = 0,
+    /// An encoded `Transfer`.
+    Transfer = 1,
+    /// A contract payload.
+    Contract = 2,
+    /// An encoded `UtxoTransaction`.
+    Utxo = 3,
+}
+
+impl TxKind {
+    pub fn from_tag(tag: u8) -> Option<TxKind> {
+        match tag {
+            0 => Some(TxKind::Raw),
+            1 => Some(TxKind::Transfer),
+            2 => Some(TxKind::Contract),
+            3 => Some(TxKind::Utxo),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Encode, Decode, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Transaction {
+    /// A `TxKind` tag followed by the payload. Being part of the signed
+    /// bytes, the tag cannot be changed without invalidating the signature.
     pub data: Vec<u8>,
-    pub key: Option<PublicKey>,
-    pub signature: Option<Signature>,
+    /// The sender. When set, it must be the address of the signing key.
+    pub from: Option<Address>,
+    pub to: Option<Address>,
+    pub amount: u64,
+    /// The last block height the transaction may be included at, if any.
+    pub valid_until: Option<u32>,
+    pub key: Option<ChainKey>,
+    pub signature: Option<ChainSignature>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub hash: Option<Hash>,
 }
 
+/// Transactions are equal when their contents are; the cached `hash` is not
+/// encoded and takes no part in the comparison.
+impl PartialEq for Transaction {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+            && self.from == other.from
+            && self.to == other.to
+            && self.amount == other.amount
+            && self.valid_until == other.valid_until
+            && self.key == other.key
+            && self.signature == other.signature
+    }
+}
+
 impl Transaction {
-    pub fn new(data: Vec<u8>) -> Result<Transaction, ()> {
-        let mut tx = Transaction {
-            data: data,
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        assert!(TxEncoder::new().encode(&mut bytes, self).is_ok());
+        bytes
+    }
+
+    /// An unsigned transaction carrying opaque `data`, tagged as
+    /// `TxKind::Raw` like `raw`, so its first byte is never mistaken for a
+    /// kind tag.
+    pub fn new(data: Vec<u8>) -> Transaction {
+        Transaction::tagged(TxKind::Raw, &data)
+    }
+
+    /// An unsigned transaction carrying `payload` tagged as `kind`.
+    fn tagged(kind: TxKind, payload: &[u8]) -> Transaction {
+        let mut data = vec![kind as u8];
+        data.extend_from_slice(payload);
+        Transaction {
+            data,
+            from: None,
+            to: None,
+            amount: 0,
+            valid_until: None,
             key: None,
             signature: None,
             hash: None,
-        };
-        Ok(tx)
+        }
+    }
+
+    /// An unsigned transaction carrying opaque `bytes`.
+    pub fn raw(bytes: &[u8]) -> Transaction {
+        Transaction::tagged(TxKind::Raw, bytes)
+    }
+
+    /// An unsigned transaction carrying `transfer`, with `from`, `to` and
+    /// `amount` set to the transfer's.
+    pub fn transfer(transfer: Transfer) -> Transaction {
+        let bytes = transfer.as_bytes().expect("encoding into memory does not fail");
+        Transaction {
+            from: Some(transfer.from),
+            to: Some(transfer.to),
+            amount: transfer.amount,
+            ..Transaction::tagged(TxKind::Transfer, &bytes)
+        }
+    }
+
+    /// An unsigned transaction carrying a contract `payload`.
+    pub fn contract(payload: &[u8]) -> Transaction {
+        Transaction::tagged(TxKind::Contract, payload)
+    }
+
+    /// An unsigned transaction carrying `utxo`.
+    pub fn utxo(utxo: &UtxoTransaction) -> Transaction {
+        let bytes = utxo.as_bytes().expect("encoding into memory does not fail");
+        Transaction::tagged(TxKind::Utxo, &bytes)
+    }
+
+    /// The kind tagged at the start of `data`, or `None` if `data` is empty
+    /// or starts with an unknown tag.
+    pub fn kind(&self) -> Option<TxKind> {
+        self.data.first().copied().and_then(TxKind::from_tag)
+    }
+
+    /// `data` after the kind tag.
+    pub fn payload(&self) -> &[u8] {
+        self.data.get(1..).unwrap_or_default()
+    }
+
+    /// The `Transfer` in `data`. Fails with `InvalidData` if the transaction
+    /// is of another kind, the transfer does not decode, or `from`, `to` or
+    /// `amount` disagree with it, so a signed transaction cannot claim one
+    /// transfer and carry another.
+    pub fn decode_transfer(&self) -> Result<Transfer, io::Error> {
+        if self.kind() != Some(TxKind::Transfer) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "transaction does not carry a transfer"));
+        }
+        let transfer = Transfer::from_bytes(self.payload())?;
+        if self.from != Some(transfer.from) || self.to != Some(transfer.to) || self.amount != transfer.amount {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "from, to or amount disagree with the transfer"));
+        }
+        Ok(transfer)
+    }
+
+    /// The `UtxoTransaction` in `data`. Fails with `InvalidData` if the
+    /// transaction is of another kind or the payload does not decode.
+    pub fn decode_utxo(&self) -> Result<UtxoTransaction, io::Error> {
+        if self.kind() != Some(TxKind::Utxo) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "transaction does not carry a UTXO transaction"));
+        }
+        UtxoTransaction::from_bytes(self.payload())
     }
 
-    pub fn sign(&mut self, private_key: &PrivateKey) -> Result<(), String> {
-        self.signature = Some(private_key.sign(&self.data).expect("could not sign"));
-        self.key = Some(private_key.generate_public());
+    /// The unsigned transaction carrying `transfer`, which should be a
+    /// `Transfer::coinbase`.
+    pub fn coinbase(transfer: Transfer) -> Transaction {
+        Transaction::transfer(transfer)
+    }
+
+    /// Whether this is an unsigned transfer from the zero address. Only the
+    /// first transaction of a block may be one.
+    pub fn is_coinbase(&self) -> bool {
+        self.key.is_none()
+            && self.signature.is_none()
+            && self.decode_transfer().is_ok_and(|t| t.from.is_zero())
+    }
+
+    /// Whether the transaction may be included in a block at `height`,
+    /// i.e. it has no `valid_until` or `height` does not exceed it.
+    pub fn is_valid_at(&self, height: u32) -> bool {
+        self.valid_until.is_none_or(|valid_until| height <= valid_until)
+    }
+
+    /// The fee of the `Transfer` in `data`, or zero for anything else.
+    pub fn fee(&self) -> u64 {
+        self.decode_transfer().map(|t| t.fee).unwrap_or(0)
+    }
+
+    pub fn builder() -> TransactionBuilder {
+        TransactionBuilder::new()
+    }
+
+    /// The bytes a signature covers: `TX_SIGNING_TAG`, then `data`, `from`,
+    /// `to`, `amount` and `valid_until` as encoded by
+    /// `TxEncoder::encode_unsigned`.
+    fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = TX_SIGNING_TAG.to_vec();
+        assert!(TxEncoder::new().encode_unsigned(&mut bytes, self).is_ok());
+        bytes
+    }
+
+    /// Signs the transaction with `key` (p256 ECDSA over the SHA256 digest
+    /// of `signing_bytes`) and stores the signature along with the signer's
+    /// public key.
+    pub fn sign(&mut self, key: ChainSigner) -> Result<(), String> {
+        let (key, signature) = self.sign_with(&key)?;
+        self.signature = Some(signature);
+        self.key = Some(key);
+        Ok(())
+    }
+
+    /// What `sign` does, for any signature scheme: signs `signing_bytes` as
+    /// `signer`, returning the public key and signature without storing
+    /// them.
+    pub fn sign_with<S: Signer>(&self, signer: &S) -> Result<(S::PublicKey, S::Signature), String> {
+        let signature = signer.sign(&self.signing_bytes())?;
+        Ok((signer.public_key(), signature))
+    }
+
+    pub fn verify(&self) -> Result<(), String> {
+        let (key, _, signature) = self.signed_message()?;
+        if self.verify_with(&key, &signature).is_err() {
+            return Err("Could not verify".to_owned());
+        }
         Ok(())
     }
-    
 
-    pub fn verify(&self) -> Result<(), p256::ecdsa::Error> {
-        assert_eq!(self.signature.is_none(), false);
-        assert_eq!(self.key.is_none(), false);
-        self.key.as_ref().unwrap().verify(&self.data, self.signature.as_ref().unwrap())
+    /// Checks `signature` over `signing_bytes` against `key`, for any
+    /// signature scheme. Addresses are p256 keys, so unlike `verify` this
+    /// does not check `from`.
+    pub fn verify_with<V: Verifier>(&self, key: &V, signature: &V::Signature) -> Result<(), VerifyError> {
+        key.verify(&self.signing_bytes(), signature)
     }
 
+    /// The key, signed bytes and signature `verify` checks, for verifying
+    /// many transactions at once with `crypto::verify_batch`. Fails if the
+    /// transaction is unsigned or `from` is not the signer's address.
+    pub fn signed_message(&self) -> Result<(ChainKey, Vec<u8>, ChainSignature), String> {
+        let (key, signature) = match (&self.key, &self.signature) {
+            (Some(key), Some(signature)) => (key, signature),
+            _ => return Err("no signature".to_owned()),
+        };
+
+        if self.from.is_some_and(|from| from != key.address()) {
+            return Err("sender is not the signer".to_owned());
+        }
+        Ok((*key, self.signing_bytes(), *signature))
+    }
+
+    /// Hashes the transaction and refreshes the cached `hash` field. Like
+    /// `Block::hash`, the value is always recomputed so it tracks changes to
+    /// the public fields.
     pub fn hash(&mut self, hasher: Box<dyn Hasher<Transaction>>) -> Hash {
-        if self.hash.is_none() {
-            self.hash = Some(hasher.hash(&self).expect("could not hash"));
+        let hash = hasher.hash(self).expect("could not hash");
+        self.hash = Some(hash);
+        hash
+    }
+}
+
+/// Builds a transaction, optionally signing it on `build`.
+pub struct TransactionBuilder {
+    data: Vec<u8>,
+    from: Option<Address>,
+    to: Option<Address>,
+    amount: u64,
+    valid_until: Option<u32>,
+    key: Option<ChainSigner>,
+}
+
+impl TransactionBuilder {
+    pub fn new() -> Self {
+        TransactionBuilder {
+            data: vec![],
+            from: None,
+            to: None,
+            amount: 0,
+            valid_until: None,
+            key: None,
+        }
+    }
+
+    /// The opaque payload, tagged as `TxKind::Raw` on `build`.
+    pub fn data(mut self, data: Vec<u8>) -> Self {
+        self.data = data;
+        self
+    }
+
+    pub fn from(mut self, from: Address) -> Self {
+        self.from = Some(from);
+        self
+    }
+
+    pub fn to(mut self, to: Address) -> Self {
+        self.to = Some(to);
+        self
+    }
+
+    pub fn amount(mut self, amount: u64) -> Self {
+        self.amount = amount;
+        self
+    }
+
+    pub fn valid_until(mut self, height: u32) -> Self {
+        self.valid_until = Some(height);
+        self
+    }
+
+    pub fn signed_by(mut self, key: ChainSigner) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn build(self) -> Result<Transaction, String> {
+        let mut tx = Transaction {
+            from: self.from,
+            to: self.to,
+            amount: self.amount,
+            valid_until: self.valid_until,
+            ..Transaction::new(self.data)
+        };
+        if let Some(key) = self.key {
+            tx.sign(key)?;
         }
-        self.hash.unwrap()
+        Ok(tx)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::crypto::keypair::PrivateKey;
+    use crate::{crypto::{keypair::{PrivateKey, VerifyError}, scheme::test::MockKey}, core::{hasher::TxHasher, encoding::{Decode, TxDecoder}, transfer::Transfer}, state::utxo::UtxoTransaction, types::address::Address};
 
-    use super::Transaction;
+    use super::{Transaction, TxKind};
+
+    fn transfer(key: &PrivateKey, to: Address, amount: u64) -> Transaction {
+        Transaction::builder()
+            .from(key.generate_public().address())
+            .to(to)
+            .amount(amount)
+            .signed_by(key.clone())
+            .build()
+            .unwrap()
+    }
 
     #[test]
     fn test_sign_transaction() {
         let key = PrivateKey::generate_key();
-        let mut tx = Transaction {
-            data: br#"foo"#.to_vec(),
-            key: None,
-            signature: None,
-            hash: None,
-        };
+        let mut tx = Transaction::new(br#"foo"#.to_vec());
 
-        assert!(tx.sign(&key).is_ok());
+        assert!(tx.sign(key).is_ok());
         assert!(tx.key.is_some());
         assert!(tx.signature.is_some());
         // assert!(tx.hash().is_ok());
     }
 
+    #[test]
+    fn test_sign_with_other_scheme() {
+        let mut tx = Transaction::raw(b"foo");
+        let (key, signature) = tx.sign_with(&MockKey(*b"key1")).unwrap();
+        assert_eq!(key, MockKey(*b"key1"));
+        assert!(tx.verify_with(&key, &signature).is_ok());
+
+        assert_eq!(tx.verify_with(&MockKey(*b"key2"), &signature), Err(VerifyError::Mismatch));
+        tx.amount = 1;
+        assert_eq!(tx.verify_with(&key, &signature), Err(VerifyError::Mismatch));
+    }
+
     #[test]
     fn test_verify_transaction() {
         let key = PrivateKey::generate_key();
-        let mut tx = Transaction {
-            data: br#"foo"#.to_vec(),
-            key: None,
-            signature: None,
-            hash: None,
-        };
+        let mut tx = Transaction::new(br#"foo"#.to_vec());
 
-        assert!(tx.sign(&key).is_ok());
+        assert!(tx.sign(key).is_ok());
         assert!(tx.verify().is_ok());
 
         let old_key = tx.key;
@@ -91,4 +385,161 @@ mod test {
         assert!(tx.verify().is_err());
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_new_then_sign_verifies() {
+        let mut tx = Transaction::new(b"foo".to_vec());
+        assert!(tx.key.is_none() && tx.signature.is_none() && tx.hash.is_none());
+
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+        assert!(tx.verify().is_ok());
+    }
+
+    #[test]
+    fn test_transaction_builder() {
+        let key = PrivateKey::generate_key();
+        let tx = Transaction::builder()
+            .data(b"foo".to_vec())
+            .signed_by(key.clone())
+            .build()
+            .unwrap();
+        assert_eq!(tx.kind(), Some(TxKind::Raw));
+        assert_eq!(tx.payload(), b"foo");
+        assert_eq!(tx.key, Some(key.generate_public()));
+        assert!(tx.verify().is_ok());
+
+        let unsigned = Transaction::builder().data(b"foo".to_vec()).build().unwrap();
+        assert_eq!(unsigned, Transaction::new(b"foo".to_vec()));
+    }
+
+    #[test]
+    fn test_transfer_fields_encode_decode() {
+        let key = PrivateKey::generate_key();
+        let tx = transfer(&key, Address::from_bytes(&[7; 20]).unwrap(), 42);
+
+        let bytes = tx.as_bytes();
+        let decoded = *Transaction::decode_binary(&mut bytes.as_slice(), TxDecoder::new()).unwrap();
+        assert_eq!(decoded, tx);
+        assert_eq!(decoded.from, Some(key.generate_public().address()));
+        assert_eq!(decoded.to, Some(Address::from_bytes(&[7; 20]).unwrap()));
+        assert_eq!(decoded.amount, 42);
+        assert!(decoded.verify().is_ok());
+    }
+
+    #[test]
+    fn test_signature_covers_transfer_fields() {
+        let key = PrivateKey::generate_key();
+        let tx = transfer(&key, Address::from_bytes(&[7; 20]).unwrap(), 42);
+        assert!(tx.verify().is_ok());
+
+        let mut tampered = tx.clone();
+        tampered.to = Some(Address::from_bytes(&[8; 20]).unwrap());
+        assert!(tampered.verify().is_err());
+
+        let mut tampered = tx.clone();
+        tampered.amount = 43;
+        assert!(tampered.verify().is_err());
+
+        let mut tampered = tx.clone();
+        tampered.from = None;
+        assert!(tampered.verify().is_err());
+
+        let mut tampered = tx.clone();
+        tampered.valid_until = Some(3);
+        assert!(tampered.verify().is_err());
+
+        let other = PrivateKey::generate_key();
+        let mut forged = Transaction { from: Some(key.generate_public().address()), ..Transaction::new(vec![]) };
+        assert!(forged.sign(other).is_ok());
+        assert_eq!(forged.verify(), Err("sender is not the signer".to_owned()));
+    }
+
+    #[test]
+    fn test_valid_until() {
+        let key = PrivateKey::generate_key();
+        let tx = Transaction::builder().data(b"foo".to_vec()).valid_until(3).signed_by(key).build().unwrap();
+        assert!(tx.verify().is_ok());
+        assert!(tx.is_valid_at(2) && tx.is_valid_at(3));
+        assert!(!tx.is_valid_at(4));
+        assert!(Transaction::new(vec![]).is_valid_at(u32::MAX));
+
+        let bytes = tx.as_bytes();
+        assert_eq!(*Transaction::decode_binary(&mut bytes.as_slice(), TxDecoder::new()).unwrap(), tx);
+    }
+
+    #[test]
+    fn test_verify_rejects_malleated_signature() {
+        let mut tx = Transaction::new(b"foo".to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+
+        tx.signature = tx.signature.map(|signature| signature.malleate());
+        assert!(tx.verify().is_err());
+    }
+
+    #[test]
+    fn test_verify_unsigned_transaction() {
+        let tx = Transaction::new(br#"foo"#.to_vec());
+        assert_eq!(tx.verify(), Err("no signature".to_owned()));
+    }
+
+    #[test]
+    fn test_hash_transaction() {
+        let mut a = Transaction::new(br#"foo"#.to_vec());
+        let mut b = Transaction::new(br#"foo"#.to_vec());
+        let hash = a.hash(Box::new(TxHasher::new()));
+        assert_eq!(hash, b.hash(Box::new(TxHasher::new())));
+        assert_eq!(a.hash, Some(hash));
+
+        b.data = br#"bar"#.to_vec();
+        assert_ne!(hash, b.hash(Box::new(TxHasher::new())));
+
+        assert!(a.sign(PrivateKey::generate_key()).is_ok());
+        assert_ne!(hash, a.hash(Box::new(TxHasher::new())));
+    }
+
+    #[test]
+    fn test_transaction_kinds() {
+        let transfer = Transfer {
+            from: Address::from_bytes(&[1u8; 20]).unwrap(),
+            to: Address::from_bytes(&[2u8; 20]).unwrap(),
+            amount: 5,
+            nonce: 0,
+            fee: 1,
+        };
+        let cases = [
+            (Transaction::raw(b"foo"), TxKind::Raw),
+            (Transaction::transfer(transfer), TxKind::Transfer),
+            (Transaction::contract(b"code"), TxKind::Contract),
+            (Transaction::utxo(&UtxoTransaction { inputs: vec![], outputs: vec![] }), TxKind::Utxo),
+        ];
+        for (mut tx, kind) in cases {
+            assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+            let decoded = *Transaction::decode_binary(&mut tx.as_bytes().as_slice(), TxDecoder::new()).unwrap();
+            assert_eq!(decoded, tx);
+            assert_eq!(decoded.kind(), Some(kind));
+            assert_eq!(decoded.data[0], kind as u8);
+            assert_eq!(decoded.decode_transfer().is_ok(), kind == TxKind::Transfer);
+            assert_eq!(decoded.decode_utxo().is_ok(), kind == TxKind::Utxo);
+
+            // The tag is signed, so retagging breaks the signature.
+            let mut retagged = decoded;
+            retagged.data[0] = (kind as u8 + 1) % 4;
+            assert!(retagged.verify().is_err());
+        }
+
+        assert_eq!(Transaction::raw(b"foo").payload(), b"foo");
+        assert_eq!(Transaction::transfer(transfer).decode_transfer().unwrap(), transfer);
+        let mut claimed = Transaction::transfer(transfer);
+        claimed.amount += 1;
+        assert_eq!(claimed.decode_transfer().unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        // `new` tags its data too, whatever the first byte.
+        let tx = Transaction::new(vec![1, 2]);
+        assert_eq!((tx.kind(), tx.payload()), (Some(TxKind::Raw), &[1, 2][..]));
+        assert!(tx.decode_transfer().is_err());
+
+        let untagged = |data: Vec<u8>| Transaction { data, ..Transaction::raw(&[]) };
+        assert_eq!(untagged(vec![]).kind(), None);
+        assert_eq!(untagged(vec![4, 1]).kind(), None);
+        assert_eq!(untagged(vec![]).payload(), b"");
+    }
+}
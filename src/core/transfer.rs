@@ -0,0 +1,72 @@
+use std::io::{self, Write, Read};
+
+use encode_decode_derive::{Encode, Decode};
+
+use crate::types::address::Address;
+
+use super::encoding::{Encoder, Decoder, Encode, Decode, TransferEncoder, TransferDecoder};
+
+/// A value transfer between accounts, carried encoded in the `data` of a
+/// `Transaction::transfer`.
+///
+/// `nonce` must equal the sender's count of previously applied transfers,
+/// which orders a sender's transfers and stops them being replayed. `fee`
+/// is paid by the sender on top of `amount`; block producers prefer
+/// transfers paying higher fees.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+pub struct Transfer {
+    pub from: Address,
+    pub to: Address,
+    pub amount: u64,
+    pub nonce: u64,
+    pub fee: u64,
+}
+
+impl Transfer {
+    /// The transfer minting `reward` to `to` in the block at `height`. It
+    /// comes from the zero address, and the height as nonce keeps coinbases
+    /// of different blocks distinct.
+    pub fn coinbase(to: Address, reward: u64, height: u32) -> Transfer {
+        Transfer { from: Address::zero(), to, amount: reward, nonce: height as u64, fee: 0 }
+    }
+
+    pub fn as_bytes(&self) -> Result<Vec<u8>, io::Error> {
+        let mut buf = vec![];
+        self.encode_binary(&mut buf, TransferEncoder::new())?;
+        Ok(buf)
+    }
+
+    /// Decodes a transfer, rejecting trailing bytes.
+    pub fn from_bytes(mut b: &[u8]) -> Result<Transfer, io::Error> {
+        let transfer = Transfer::decode_binary(&mut b, TransferDecoder::new())?;
+        if !b.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "trailing bytes after transfer"));
+        }
+        Ok(*transfer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::types::address::Address;
+
+    use super::Transfer;
+
+    #[test]
+    fn test_transfer_encode_decode() {
+        let t = Transfer {
+            from: Address::from_bytes(&[1u8; 20]).unwrap(),
+            to: Address::from_bytes(&[2u8; 20]).unwrap(),
+            amount: 500,
+            nonce: 3,
+            fee: 2,
+        };
+
+        let mut bytes = t.as_bytes().unwrap();
+        assert_eq!(Transfer::from_bytes(&bytes).unwrap(), t);
+
+        bytes.push(0);
+        assert!(Transfer::from_bytes(&bytes).is_err());
+        assert!(Transfer::from_bytes(&bytes[..10]).is_err());
+    }
+}
@@ -1,9 +1,13 @@
-use super::{block::Block, blockchain::Blockchain};
+use chrono::Utc;
 
+use super::{block::Block, blockchain::{Blockchain, ChainError}, storage::Storage};
 
 
-pub trait Validator {
-    fn validate_block(&self, bc: &Blockchain, b: &Block) -> Result<(), ()>;
+
+/// Validators are `Send + Sync` so a chain can be shared between threads,
+/// as with `SharedChain`.
+pub trait Validator<S: Storage>: Send + Sync {
+    fn validate_block(&self, bc: &Blockchain<S>, b: &Block) -> Result<(), ChainError>;
 }
 
 pub struct BlockValidator {}
@@ -14,11 +18,52 @@ impl BlockValidator {
     }
 }
 
-impl Validator for BlockValidator {
-    fn validate_block(&self, bc: &Blockchain, b: &Block) -> Result<(), ()> {
-        assert!(bc.has_block(b.header.height).is_err());
-        assert!(b.verify().is_ok());
+impl<S: Storage> Validator<S> for BlockValidator {
+    fn validate_block(&self, bc: &Blockchain<S>, b: &Block) -> Result<(), ChainError> {
+        let height = b.header.height;
+        if height != bc.height() + 1 {
+            return Err(ChainError::InvalidHeight(height));
+        }
+
+        if b.header.prev_block != bc.last_hash() {
+            return Err(ChainError::InvalidPrevHash(height));
+        }
+
+        // A producer stamping the expected time may run up to a block
+        // interval ahead of this node's clock, even with a smaller drift.
+        let parent = bc.get_header(height - 1).expect("parent is the current tip");
+        let tolerance = bc.max_future_drift().max(bc.block_time_secs());
+        if b.header.timestamp <= parent.timestamp || b.header.timestamp > Utc::now().timestamp() + tolerance {
+            return Err(ChainError::InvalidTimestamp(height));
+        }
+
+        if b.header.difficulty != bc.expected_difficulty(height) || !b.meets_difficulty(b.header.difficulty) {
+            return Err(ChainError::InvalidDifficulty(height));
+        }
+
+        if let Some(hash) = b.duplicate_transaction() {
+            return Err(ChainError::DuplicateTransaction(height, hash));
+        }
+
+        if let Err(e) = b.verify() {
+            log::warn!("Rejecting block - height: {}, reason: {}", height, e);
+            return Err(ChainError::InvalidSignature(height));
+        }
+
+        if let Some(leader) = bc.leader_for_height(height) {
+            if b.validator != Some(leader) {
+                return Err(ChainError::UnexpectedValidator(height));
+            }
+        }
+
+        if let Err(i) = b.verify_transactions() {
+            return Err(ChainError::InvalidTransaction(height, i));
+        }
+
+        if let Some(policy) = bc.supply_policy() {
+            policy.check(b).map_err(|e| ChainError::InvalidState(height, e.to_string()))?;
+        }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -1 +1,7 @@
-pub mod keypair;
\ No newline at end of file
+pub mod keypair;
+pub mod signature;
+pub mod hasher;
+pub mod scheme;
+pub mod batch;
+
+pub use batch::verify_batch;
@@ -0,0 +1,51 @@
+use super::{keypair::PublicKey, signature::Signature};
+
+/// Verifies each `(key, message, signature)` in `items`, returning the index
+/// of the first that fails.
+///
+/// p256 has no true batch verification, so this is one `PublicKey::verify`
+/// per item. With the `parallel` feature they run concurrently, and the
+/// lowest failing index is still the one reported.
+pub fn verify_batch(items: &[(PublicKey, &[u8], Signature)]) -> Result<(), usize> {
+    let valid = |(key, message, signature): &(PublicKey, &[u8], Signature)| key.verify(message, signature).is_ok();
+
+    #[cfg(feature = "parallel")]
+    let failed = {
+        use rayon::prelude::*;
+        items.par_iter().position_first(|item| !valid(item))
+    };
+    #[cfg(not(feature = "parallel"))]
+    let failed = items.iter().position(|item| !valid(item));
+
+    match failed {
+        Some(i) => Err(i),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto::keypair::PrivateKey;
+
+    use super::verify_batch;
+
+    #[test]
+    fn test_verify_batch() {
+        let messages: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i; 8]).collect();
+        let mut items: Vec<_> = messages
+            .iter()
+            .map(|message| {
+                let key = PrivateKey::generate_key();
+                (key.generate_public(), message.as_slice(), key.sign(message).unwrap())
+            })
+            .collect();
+        assert_eq!(verify_batch(&items), Ok(()));
+        assert_eq!(verify_batch(&[]), Ok(()));
+
+        items[13].1 = b"tampered";
+        assert_eq!(verify_batch(&items), Err(13));
+
+        items[17].0 = PrivateKey::generate_key().generate_public();
+        assert_eq!(verify_batch(&items), Err(13));
+    }
+}
@@ -0,0 +1,20 @@
+use sha2::{Sha256, Digest};
+
+use crate::types::hash::Hash;
+
+/// Digests bytes into a `Hash`. Block and transaction hashing go through
+/// this, so another algorithm can be tried by implementing it.
+pub trait Hasher {
+    fn hash(&self, data: &[u8]) -> Hash;
+}
+
+/// Single SHA256, the hash used throughout the chain by default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Sha256Hasher;
+
+impl Hasher for Sha256Hasher {
+    fn hash(&self, data: &[u8]) -> Hash {
+        let digest = Sha256::digest(data);
+        Hash::from_bytes(&digest).expect("sha256 digest is 32 bytes")
+    }
+}
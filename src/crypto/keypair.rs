@@ -1,43 +1,107 @@
+use std::{cmp::Ordering, fmt, hash::{Hash, Hasher}};
+
 use p256::{
     ecdsa::{
-        signature::{Signer, Verifier},
-        SigningKey, VerifyingKey, Signature,
+        signature::{DigestSigner, DigestVerifier},
+        SigningKey, VerifyingKey,
     },
-    pkcs8::EncodePrivateKey,
+    elliptic_curve::sec1::ToEncodedPoint,
     PublicKey as P256PublicKey, SecretKey,
     elliptic_curve::rand_core::OsRng
 };
+use ripemd::Ripemd160;
 use sha2::{Digest, Sha256};
 
 use crate::types::address::Address;
 
+use super::signature::Signature;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum KeyError {
+    /// Not a 32-byte big-endian scalar in `1..n`.
+    InvalidPrivateKey,
+    /// Not a valid SEC1-encoded point on the curve.
+    InvalidPublicKey,
+    /// Not 64 bytes of `r || s` with both scalars in `1..n`.
+    MalformedSignature,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::InvalidPrivateKey => write!(f, "invalid private key encoding"),
+            KeyError::InvalidPublicKey => write!(f, "invalid public key encoding"),
+            KeyError::MalformedSignature => write!(f, "invalid signature encoding"),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+/// Why a signature failed to verify.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VerifyError {
+    /// The signature is not a valid `r || s` encoding, or is the high-S
+    /// form that `PrivateKey::sign` never produces.
+    BadSignatureEncoding,
+    /// The key is not a valid SEC1-encoded point. Only `verify_encoded`
+    /// reports it.
+    BadKey,
+    /// A well-formed signature that was not made by this key over this
+    /// message.
+    Mismatch,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::BadSignatureEncoding => write!(f, "malformed signature"),
+            VerifyError::BadKey => write!(f, "malformed public key"),
+            VerifyError::Mismatch => write!(f, "signature does not match"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct PrivateKey {
-    key: String
+    key: SecretKey
 }
 
 impl PrivateKey {
+    /// Signs the SHA256 digest of `message`, so messages of any length can
+    /// be signed; ECDSA itself only signs a 32-byte digest. Signing is
+    /// deterministic (RFC6979) and normalized to low-S so every message has
+    /// a single valid encoding per key.
     pub fn sign(&self, message: &[u8]) -> Result<Signature, String> {
-        let signing_key: SigningKey = self.key.parse::<SecretKey>().expect("error").into();
-        Ok(signing_key.sign(message))
+        let signing_key: SigningKey = self.key.clone().into();
+        let signature: p256::ecdsa::Signature = signing_key
+            .try_sign_digest(Sha256::new_with_prefix(message))
+            .map_err(|e| format!("could not sign: {}", e))?;
+        Ok(Signature { signature: signature.normalize_s().unwrap_or(signature) })
     }
 
     pub fn generate_key() -> Self {
-        let secret_key = SecretKey::random(&mut OsRng);
-        let secret_key_serialized = secret_key
-        .to_pkcs8_pem(Default::default())
-        .unwrap()
-        .to_string();
-        PrivateKey{key: secret_key_serialized}
+        PrivateKey{key: SecretKey::random(&mut OsRng)}
     }
 
     pub fn generate_public(&self) -> PublicKey {
-        let parsed = self.key.parse::<SecretKey>().unwrap().public_key();
-        PublicKey{ key: parsed }
+        PublicKey{ key: self.key.public_key() }
+    }
+
+    /// The 32-byte big-endian scalar.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.key.to_be_bytes().into()
+    }
+
+    pub fn from_bytes(b: &[u8]) -> Result<Self, KeyError> {
+        let key = SecretKey::from_be_bytes(b).map_err(|_| KeyError::InvalidPrivateKey)?;
+        Ok(PrivateKey { key })
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, Clone, Copy)]
 pub struct PublicKey {
     key: P256PublicKey
 }
@@ -47,22 +111,100 @@ impl PublicKey {
         self.key.to_string().into()
     }
 
-    pub fn address(&self) -> Result<Address, String> {
-        let mut hasher = Sha256::new();
-        hasher.update(&self.to_slice());
-        let result = hasher.finalize();
-        let address_bytes = &result[result.len() - 20..];
-        Address::from_bytes(address_bytes.try_into().unwrap())
+    /// Compressed SEC1 encoding of the key (33 bytes).
+    pub fn to_bytes(self) -> Vec<u8> {
+        self.key.to_encoded_point(true).as_bytes().to_vec()
+    }
+
+    /// Accepts compressed or uncompressed SEC1 encodings.
+    pub fn from_bytes(b: &[u8]) -> Result<Self, KeyError> {
+        let key = P256PublicKey::from_sec1_bytes(b).map_err(|_| KeyError::InvalidPublicKey)?;
+        Ok(PublicKey { key })
+    }
+
+    /// RIPEMD160 of the SHA256 of the compressed key, as in Bitcoin.
+    pub fn address(&self) -> Address {
+        let sha = Sha256::digest(self.to_bytes());
+        let ripemd = Ripemd160::digest(sha);
+        Address::from_bytes(&ripemd).expect("ripemd160 digest is 20 bytes")
     }
 
-    pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<(), p256::ecdsa::Error>
+    /// Checks `signature` against the SHA256 digest of `message`, as
+    /// produced by `PrivateKey::sign`.
+    ///
+    /// Rejects high-S signatures, which are valid ECDSA but malleable copies
+    /// of the low-S signature `PrivateKey::sign` produces.
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<(), VerifyError>
     {
+        if signature.signature.normalize_s().is_some() {
+            return Err(VerifyError::BadSignatureEncoding);
+        }
+
         let verifying_key: VerifyingKey = self.key.into();
-        verifying_key.verify(message, signature)
+        verifying_key
+            .verify_digest(Sha256::new_with_prefix(message), &signature.signature)
+            .map_err(|_| VerifyError::Mismatch)
+    }
+
+    /// Like `verify`, but for a key and signature still in their wire
+    /// encodings, as received from an external signer, so a failure to
+    /// decode either is reported as such. A decoded `PublicKey` is always a
+    /// valid point, so this is the only path that yields `BadKey`.
+    pub fn verify_encoded(key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), VerifyError> {
+        let key = PublicKey::from_bytes(key).map_err(|_| VerifyError::BadKey)?;
+        let signature = Signature::from_bytes(signature).map_err(|_| VerifyError::BadSignatureEncoding)?;
+        key.verify(message, &signature)
     }
 
 }
 
+/// Compared, hashed and ordered by the compressed SEC1 encoding, so a key
+/// decoded from either encoding equals and hashes the same as the key it
+/// was derived from.
+impl PartialEq for PublicKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+}
+
+impl Eq for PublicKey {}
+
+impl Hash for PublicKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_bytes().hash(state);
+    }
+}
+
+impl PartialOrd for PublicKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PublicKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.to_bytes().cmp(&other.to_bytes())
+    }
+}
+
+/// Serialized as the hex of the compressed SEC1 encoding.
+#[cfg(feature = "serde")]
+impl serde::Serialize for PublicKey {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(self.to_bytes()))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for PublicKey {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        let bytes = hex::decode(s.as_ref()).map_err(D::Error::custom)?;
+        PublicKey::from_bytes(&bytes).map_err(D::Error::custom)
+    }
+}
 
 #[cfg(test)]
 mod test {
@@ -97,4 +239,134 @@ mod test {
         assert!(public.verify("hello".as_bytes(), signature.as_ref().unwrap()).is_err());
         assert!(other_public.verify(message, &signature.unwrap()).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_sign_large_message() {
+        let private = PrivateKey::generate_key();
+        let public = private.generate_public();
+        let mut message = vec![7u8; 4096];
+
+        let signature = private.sign(&message).unwrap();
+        assert!(public.verify(&message, &signature).is_ok());
+
+        // The digest covers the whole message, not just a 32-byte prefix.
+        *message.last_mut().unwrap() = 8;
+        assert!(public.verify(&message, &signature).is_err());
+    }
+
+    #[test]
+    fn test_sign_is_deterministic_and_low_s() {
+        let private = PrivateKey::generate_key();
+        let message = b"Hello World";
+
+        let a = private.sign(message).unwrap();
+        let b = private.sign(message).unwrap();
+        assert_eq!(a.to_bytes(), b.to_bytes());
+        assert!(a.signature.normalize_s().is_none());
+    }
+
+    #[test]
+    fn test_verify_rejects_high_s() {
+        let private = PrivateKey::generate_key();
+        let public = private.generate_public();
+        let message = b"Hello World";
+
+        let signature = private.sign(message).unwrap();
+        let malleated = signature.malleate();
+        assert_ne!(malleated.to_bytes(), signature.to_bytes());
+        assert_eq!(malleated.signature.normalize_s(), Some(signature.signature));
+
+        assert!(public.verify(message, &signature).is_ok());
+        assert_eq!(public.verify(message, &malleated), Err(VerifyError::BadSignatureEncoding));
+    }
+
+    #[test]
+    fn test_verify_errors() {
+        let private = PrivateKey::generate_key();
+        let key = private.generate_public().to_bytes();
+        let message = b"Hello World";
+        let signature = private.sign(message).unwrap().to_bytes();
+
+        assert_eq!(PublicKey::verify_encoded(&key, message, &signature), Ok(()));
+        assert_eq!(PublicKey::verify_encoded(&key, b"hello", &signature), Err(VerifyError::Mismatch));
+        assert_eq!(PublicKey::verify_encoded(&key[..32], message, &signature), Err(VerifyError::BadKey));
+        assert_eq!(PublicKey::verify_encoded(&key, message, &[0u8; 64]), Err(VerifyError::BadSignatureEncoding));
+        assert_eq!(PublicKey::verify_encoded(&key, message, &signature[..63]), Err(VerifyError::BadSignatureEncoding));
+    }
+
+    #[test]
+    fn test_public_key_set() {
+        use p256::elliptic_curve::sec1::ToEncodedPoint;
+
+        let public = PrivateKey::generate_key().generate_public();
+        let uncompressed = public.key.to_encoded_point(false);
+        let decoded = PublicKey::from_bytes(uncompressed.as_bytes()).unwrap();
+
+        let mut set = std::collections::HashSet::new();
+        set.insert(public);
+        set.insert(decoded);
+        assert_eq!(set.len(), 1);
+
+        let other = PrivateKey::generate_key().generate_public();
+        set.insert(other);
+        assert_eq!(set.len(), 2);
+        assert_eq!(public.cmp(&other), public.to_bytes().cmp(&other.to_bytes()));
+    }
+
+    #[test]
+    fn test_public_key_equality() {
+        let private = PrivateKey::generate_key();
+        let public = private.generate_public();
+        assert_eq!(private.generate_public(), public);
+        assert_eq!(PrivateKey::from_bytes(&private.to_bytes()).unwrap().generate_public(), public);
+
+        let uncompressed = public.key.to_encoded_point(false);
+        assert_eq!(uncompressed.as_bytes().len(), 65);
+        let decoded = PublicKey::from_bytes(uncompressed.as_bytes()).unwrap();
+        assert_eq!(decoded, public);
+        assert_eq!(decoded.to_bytes(), public.to_bytes());
+        assert_ne!(PrivateKey::generate_key().generate_public(), public);
+    }
+
+    #[test]
+    fn test_address_is_stable() {
+        let public = PrivateKey::generate_key().generate_public();
+        let other = PrivateKey::generate_key().generate_public();
+
+        assert_eq!(public.address(), public.address());
+        assert_ne!(public.address(), other.address());
+        assert_eq!(public.address().to_string().parse(), Ok(public.address()));
+    }
+
+    #[test]
+    fn test_key_bytes_round_trip() {
+        let private = PrivateKey::generate_key();
+        let public = private.generate_public();
+
+        assert_eq!(PrivateKey::from_bytes(&private.to_bytes()), Ok(private));
+        assert_eq!(public.to_bytes().len(), 33);
+        assert_eq!(PublicKey::from_bytes(&public.to_bytes()), Ok(public));
+    }
+
+    #[test]
+    fn test_loaded_keys_sign_and_verify() {
+        let original = PrivateKey::generate_key();
+        let private = PrivateKey::from_bytes(&original.to_bytes()).unwrap();
+        let public = PublicKey::from_bytes(&original.generate_public().to_bytes()).unwrap();
+
+        let message = "Hello World".as_bytes();
+        let signature = private.sign(message).unwrap();
+        assert!(public.verify(message, &signature).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_key_bytes_rejected() {
+        assert_eq!(PrivateKey::from_bytes(&[0u8; 32]), Err(KeyError::InvalidPrivateKey));
+        assert_eq!(PrivateKey::from_bytes(&[0xffu8; 32]), Err(KeyError::InvalidPrivateKey));
+        assert_eq!(PrivateKey::from_bytes(&[1u8; 31]), Err(KeyError::InvalidPrivateKey));
+
+        let point = PrivateKey::generate_key().generate_public().to_bytes();
+        assert_eq!(PublicKey::from_bytes(&point[..32]), Err(KeyError::InvalidPublicKey));
+        assert_eq!(PublicKey::from_bytes(&[]), Err(KeyError::InvalidPublicKey));
+    }
+}
@@ -0,0 +1,107 @@
+//! Signing and verifying behind traits, so another signature scheme can be
+//! plugged into the code paths that sign blocks and transactions.
+//!
+//! `ChainSigner` names the scheme blocks and transactions store and put on
+//! the wire, p256 ECDSA as implemented by `PrivateKey` and `PublicKey`.
+//! `sign_with` and `verify_with` on `Block` and `Transaction` take any
+//! scheme.
+
+use super::{keypair::{PrivateKey, PublicKey, VerifyError}, signature::Signature};
+
+/// The scheme whose keys and signatures `Block` and `Transaction` store.
+/// The encoders, addresses and batch verification are written for p256, so
+/// changing it means changing those along with it.
+pub type ChainSigner = PrivateKey;
+
+/// The public key `Block` and `Transaction` store.
+pub type ChainKey = <ChainSigner as Signer>::PublicKey;
+
+/// The signature `Block` and `Transaction` store.
+pub type ChainSignature = <ChainSigner as Signer>::Signature;
+
+/// A private key of some signature scheme.
+pub trait Signer {
+    type PublicKey: Verifier<Signature = Self::Signature>;
+    type Signature;
+
+    fn public_key(&self) -> Self::PublicKey;
+
+    fn sign(&self, message: &[u8]) -> Result<Self::Signature, String>;
+}
+
+/// A public key checking signatures made by the matching `Signer`.
+pub trait Verifier {
+    type Signature;
+
+    /// The canonical encoding of the key. Block signatures commit to it.
+    fn key_bytes(&self) -> Vec<u8>;
+
+    fn verify(&self, message: &[u8], signature: &Self::Signature) -> Result<(), VerifyError>;
+}
+
+impl Signer for PrivateKey {
+    type PublicKey = PublicKey;
+    type Signature = Signature;
+
+    fn public_key(&self) -> PublicKey {
+        self.generate_public()
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Signature, String> {
+        PrivateKey::sign(self, message)
+    }
+}
+
+impl Verifier for PublicKey {
+    type Signature = Signature;
+
+    /// The compressed SEC1 encoding.
+    fn key_bytes(&self) -> Vec<u8> {
+        self.to_bytes()
+    }
+
+    fn verify(&self, message: &[u8], signature: &Signature) -> Result<(), VerifyError> {
+        PublicKey::verify(self, message, signature)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::{crypto::{hasher::{Hasher, Sha256Hasher}, keypair::VerifyError}, types::hash::Hash};
+
+    use super::{Signer, Verifier};
+
+    /// A toy scheme whose public key is the secret and whose signature is
+    /// the SHA256 of the secret and message, standing in for a second
+    /// backend.
+    #[derive(Debug, PartialEq)]
+    pub(crate) struct MockKey(pub [u8; 4]);
+
+    impl Signer for MockKey {
+        type PublicKey = MockKey;
+        type Signature = Hash;
+
+        fn public_key(&self) -> MockKey {
+            MockKey(self.0)
+        }
+
+        fn sign(&self, message: &[u8]) -> Result<Hash, String> {
+            Ok(Sha256Hasher.hash(&[&self.0[..], message].concat()))
+        }
+    }
+
+    impl Verifier for MockKey {
+        type Signature = Hash;
+
+        fn key_bytes(&self) -> Vec<u8> {
+            self.0.to_vec()
+        }
+
+        fn verify(&self, message: &[u8], signature: &Hash) -> Result<(), VerifyError> {
+            match self.sign(message) {
+                Ok(expected) if expected == *signature => Ok(()),
+                _ => Err(VerifyError::Mismatch),
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::{cmp::Ordering, fmt, hash::{Hash, Hasher}};
+
+use p256::ecdsa::Signature as P256Signature;
+
+use super::keypair::KeyError;
+
+/// A p256 ECDSA signature.
+///
+/// Equality, hashing and ordering all use the 64-byte `r || s` encoding, so
+/// signatures can key maps and sets. `PrivateKey::sign` only produces low-S
+/// signatures and `PublicKey::verify` rejects the rest, so a signature that
+/// verifies has exactly one encoding.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct Signature {
+    pub(super) signature: P256Signature,
+}
+
+impl Signature {
+    /// The 64-byte big-endian `r || s` encoding.
+    pub fn to_bytes(self) -> [u8; 64] {
+        self.signature.to_bytes().into()
+    }
+
+    pub fn from_bytes(b: &[u8]) -> Result<Self, KeyError> {
+        let signature = P256Signature::try_from(b).map_err(|_| KeyError::MalformedSignature)?;
+        Ok(Signature { signature })
+    }
+
+    /// The same signature with `s` replaced by `n - s`, which is valid ECDSA
+    /// but must be rejected by `PublicKey::verify`.
+    #[cfg(test)]
+    pub fn malleate(self) -> Signature {
+        let (r, s) = self.signature.split_scalars();
+        let signature = P256Signature::from_scalars(r.to_bytes(), (-s).to_bytes()).unwrap();
+        Signature { signature }
+    }
+}
+
+impl Hash for Signature {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_bytes().hash(state);
+    }
+}
+
+impl PartialOrd for Signature {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Signature {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.to_bytes().cmp(&other.to_bytes())
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Signature({})", hex::encode(self.to_bytes()))
+    }
+}
+
+/// Serialized as the hex of the 64 `r || s` bytes.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Signature {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(self.to_bytes()))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Signature {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        let bytes = hex::decode(s.as_ref()).map_err(D::Error::custom)?;
+        Signature::from_bytes(&bytes).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::crypto::keypair::{KeyError, PrivateKey};
+
+    use super::Signature;
+
+    #[test]
+    fn test_signature_bytes_round_trip() {
+        let signature = PrivateKey::generate_key().sign(b"foo").unwrap();
+        assert_eq!(Signature::from_bytes(&signature.to_bytes()), Ok(signature));
+
+        assert_eq!(Signature::from_bytes(&[0u8; 64]), Err(KeyError::MalformedSignature));
+        assert_eq!(Signature::from_bytes(&[1u8; 63]), Err(KeyError::MalformedSignature));
+    }
+
+    #[test]
+    fn test_signature_set() {
+        let key = PrivateKey::generate_key();
+        let mut set = HashSet::new();
+        set.insert(key.sign(b"foo").unwrap());
+        set.insert(key.sign(b"foo").unwrap());
+        assert_eq!(set.len(), 1);
+
+        set.insert(key.sign(b"bar").unwrap());
+        assert_eq!(set.len(), 2);
+    }
+}
@@ -0,0 +1,21 @@
+//! The chain core, usable without the node: blocks, transactions, hashing,
+//! crypto, state and the in-memory chain. `native` adds files, sockets and
+//! the CLI; see the crate features in `Cargo.toml`.
+
+// Encoders, decoders and containers are built with `new()` throughout and
+// were written while the crate was only a binary, where these public-API
+// lints do not fire.
+#![allow(clippy::new_without_default, clippy::len_without_is_empty, clippy::result_unit_err)]
+
+pub mod network;
+pub mod core;
+pub mod types;
+pub mod crypto;
+pub mod consensus;
+pub mod state;
+#[cfg(feature = "native")]
+pub mod cli;
+#[cfg(feature = "http")]
+pub mod api;
+#[cfg(feature = "metrics")]
+pub mod metrics;
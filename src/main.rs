@@ -1,13 +1,21 @@
-use std::{time, thread};
+#[cfg(feature = "native")]
+use std::{env, io, process, time, thread};
+#[cfg(feature = "native")]
 use simple_logger::SimpleLogger;
-use network::{local_transport::LocalTransport, transport::Transport, server::{ServerOpts, Server}};
-
-mod network;
-mod core;
-mod types;
-mod crypto;
+#[cfg(feature = "native")]
+use blockchain::{cli, network::{local_transport::LocalTransport, transport::Transport, server::{ServerOpts, Server}}};
 
+#[cfg(feature = "native")]
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = cli::parse(&args).and_then(|cmd| cli::run(cmd, &mut io::stdout())) {
+            eprintln!("error: {}\n{}", e, cli::USAGE);
+            process::exit(1);
+        }
+        return;
+    }
+
     SimpleLogger::new().with_threads(true).init().unwrap();
 
     let mut tr_local = LocalTransport::new("LOCAL".to_owned());
@@ -32,6 +40,8 @@ fn main() {
 
     let mut opts = ServerOpts {
         transports: Vec::new(),
+        block_time: time::Duration::from_secs(5),
+        key: None,
     };
 
     opts.transports.push(Box::new(tr_local.clone()));
@@ -41,3 +51,8 @@ fn main() {
     server.start();
 
 }
+
+/// Without `native` there is no node or CLI to run; the build only checks
+/// that the core compiles, e.g. for `wasm32-unknown-unknown`.
+#[cfg(not(feature = "native"))]
+fn main() {}
@@ -0,0 +1,95 @@
+//! Counters and gauges for operators, updated by `Blockchain` and
+//! `Mempool`. Only built with the `metrics` feature.
+
+use std::{collections::HashMap, fmt::Write, sync::{atomic::{AtomicU64, Ordering}, Arc}};
+
+#[derive(Default)]
+struct Values {
+    blocks_total: AtomicU64,
+    transactions_total: AtomicU64,
+    mempool_size: AtomicU64,
+    chain_height: AtomicU64,
+    invalid_blocks_total: AtomicU64,
+}
+
+/// A set of metrics. Clones share the same values, so one `Metrics` can be
+/// handed to a chain and its mempool and read from anywhere.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    values: Arc<Values>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Counts a block appended at `height` with `transactions` transactions.
+    pub fn block_added(&self, height: u32, transactions: usize) {
+        self.values.blocks_total.fetch_add(1, Ordering::Relaxed);
+        self.values.transactions_total.fetch_add(transactions as u64, Ordering::Relaxed);
+        self.values.chain_height.store(height as u64, Ordering::Relaxed);
+    }
+
+    pub fn block_rejected(&self) {
+        self.values.invalid_blocks_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_mempool_size(&self, size: usize) {
+        self.values.mempool_size.store(size as u64, Ordering::Relaxed);
+    }
+
+    /// Every metric by name.
+    pub fn snapshot(&self) -> HashMap<String, f64> {
+        self.entries().map(|(name, _, value)| (name.to_owned(), value as f64)).collect()
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, kind, value) in self.entries() {
+            writeln!(out, "# TYPE {} {}\n{} {}", name, kind, name, value).expect("writing to a String does not fail");
+        }
+        out
+    }
+
+    /// Name, Prometheus type and value of each metric.
+    fn entries(&self) -> impl Iterator<Item = (&'static str, &'static str, u64)> + '_ {
+        let v = &self.values;
+        [
+            ("blocks_total", "counter", &v.blocks_total),
+            ("transactions_total", "counter", &v.transactions_total),
+            ("mempool_size", "gauge", &v.mempool_size),
+            ("chain_height", "gauge", &v.chain_height),
+            ("invalid_blocks_total", "counter", &v.invalid_blocks_total),
+        ]
+        .into_iter()
+        .map(|(name, kind, value)| (name, kind, value.load(Ordering::Relaxed)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Metrics;
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Metrics::new();
+        let shared = metrics.clone();
+        shared.block_added(1, 3);
+        shared.block_added(2, 2);
+        shared.block_rejected();
+        shared.set_mempool_size(7);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot["blocks_total"], 2.0);
+        assert_eq!(snapshot["transactions_total"], 5.0);
+        assert_eq!(snapshot["chain_height"], 2.0);
+        assert_eq!(snapshot["invalid_blocks_total"], 1.0);
+        assert_eq!(snapshot["mempool_size"], 7.0);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE blocks_total counter\nblocks_total 2\n"));
+        assert!(text.contains("# TYPE mempool_size gauge\nmempool_size 7\n"));
+    }
+}
@@ -1,5 +1,6 @@
 pub mod local_transport;
 pub mod server;
 pub mod transport;
-pub mod txpool;
-pub mod channel;
\ No newline at end of file
+pub mod channel;
+pub mod message;
+pub mod peer;
\ No newline at end of file
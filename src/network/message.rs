@@ -0,0 +1,247 @@
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use encode_decode_derive::{Encode, Decode};
+
+use crate::core::{
+    block::Block,
+    compact::CompactBlock,
+    encoding::{read_varint, write_varint, BlockDecoder, BlockEncoder, CompactBlockDecoder, CompactBlockEncoder, Decode, Decoder, Encode, Encoder, TxDecoder, TxEncoder},
+    transaction::Transaction,
+};
+
+/// Marks the start of every frame.
+pub const MAGIC: [u8; 4] = *b"BLKC";
+
+/// Version of the frame and message layout.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Most payload bytes a single frame may carry.
+pub const MAX_MESSAGE_BYTES: usize = 32 << 20;
+
+/// Most blocks a single `Blocks` message may carry.
+pub const MAX_BLOCKS_PER_MESSAGE: usize = 500;
+
+const TAG_NEW_BLOCK: u8 = 0;
+const TAG_NEW_TRANSACTION: u8 = 1;
+const TAG_GET_BLOCKS: u8 = 2;
+const TAG_BLOCKS: u8 = 3;
+const TAG_COMPACT_BLOCK: u8 = 4;
+
+/// Messages exchanged between peers.
+#[derive(Debug, PartialEq, Clone, Encode, Decode)]
+pub enum Message {
+    NewBlock(Block),
+    NewTransaction(Transaction),
+    /// Asks a peer for its blocks starting at `from_height`.
+    GetBlocks { from_height: u32 },
+    Blocks(Vec<Block>),
+    /// Announces a block by its transaction hashes, to be rebuilt with
+    /// `Blockchain::reconstruct`.
+    CompactBlock(CompactBlock),
+}
+
+impl Message {
+    /// Encodes the message as a frame: `MAGIC`, `PROTOCOL_VERSION`, the
+    /// payload length as a little-endian `u32` and the payload.
+    pub fn to_frame(&self) -> io::Result<Vec<u8>> {
+        let mut payload = vec![];
+        self.encode_binary(&mut payload, MessageEncoder::new())?;
+        if payload.len() > MAX_MESSAGE_BYTES {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "message is too large"));
+        }
+
+        let mut frame = Vec::with_capacity(payload.len() + 9);
+        frame.write_all(&MAGIC)?;
+        frame.write_u8(PROTOCOL_VERSION)?;
+        frame.write_u32::<LittleEndian>(payload.len() as u32)?;
+        frame.write_all(&payload)?;
+        Ok(frame)
+    }
+
+    /// Reads one frame written by `to_frame`.
+    pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Message> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad frame magic"));
+        }
+
+        let version = reader.read_u8()?;
+        if version != PROTOCOL_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported protocol version {}", version)));
+        }
+
+        let len = reader.read_u32::<LittleEndian>()? as usize;
+        if len > MAX_MESSAGE_BYTES {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes is too large", len)));
+        }
+
+        let mut payload = vec![];
+        reader.take(len as u64).read_to_end(&mut payload)?;
+        if payload.len() != len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "frame is truncated"));
+        }
+
+        let mut rest = payload.as_slice();
+        let message = Message::decode_binary(&mut rest, MessageDecoder::new())?;
+        if !rest.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "trailing bytes after message"));
+        }
+        Ok(*message)
+    }
+}
+
+/// Writes a message payload: a one-byte tag followed by the variant's fields.
+pub struct MessageEncoder {}
+
+impl MessageEncoder {
+    pub fn new() -> Self {
+        MessageEncoder {}
+    }
+}
+
+impl Encoder<Message> for MessageEncoder {
+    fn encode<W: Write>(&self, writer: &mut W, m: &Message) -> io::Result<()> {
+        match m {
+            Message::NewBlock(b) => {
+                writer.write_u8(TAG_NEW_BLOCK)?;
+                BlockEncoder::new().encode(writer, b)
+            }
+            Message::NewTransaction(tx) => {
+                writer.write_u8(TAG_NEW_TRANSACTION)?;
+                TxEncoder::new().encode(writer, tx)
+            }
+            Message::GetBlocks { from_height } => {
+                writer.write_u8(TAG_GET_BLOCKS)?;
+                writer.write_u32::<LittleEndian>(*from_height)
+            }
+            Message::Blocks(blocks) => {
+                writer.write_u8(TAG_BLOCKS)?;
+                write_varint(writer, blocks.len() as u64)?;
+                let encoder = BlockEncoder::new();
+                for b in blocks {
+                    encoder.encode(writer, b)?;
+                }
+                Ok(())
+            }
+            Message::CompactBlock(b) => {
+                writer.write_u8(TAG_COMPACT_BLOCK)?;
+                CompactBlockEncoder::new().encode(writer, b)
+            }
+        }
+    }
+}
+
+pub struct MessageDecoder {}
+
+impl MessageDecoder {
+    pub fn new() -> Self {
+        MessageDecoder {}
+    }
+}
+
+impl Decoder<Message> for MessageDecoder {
+    fn decode<R: Read>(&self, reader: &mut R) -> io::Result<Box<Message>> {
+        let message = match reader.read_u8()? {
+            TAG_NEW_BLOCK => Message::NewBlock(*BlockDecoder::new().decode(reader)?),
+            TAG_NEW_TRANSACTION => Message::NewTransaction(*TxDecoder::new().decode(reader)?),
+            TAG_GET_BLOCKS => Message::GetBlocks { from_height: reader.read_u32::<LittleEndian>()? },
+            TAG_BLOCKS => {
+                let count = read_varint(reader)?;
+                if count > MAX_BLOCKS_PER_MESSAGE as u64 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} blocks in one message", count)));
+                }
+
+                let decoder = BlockDecoder::new();
+                let mut blocks = vec![];
+                for _ in 0..count {
+                    blocks.push(*decoder.decode(reader)?);
+                }
+                Message::Blocks(blocks)
+            }
+            TAG_COMPACT_BLOCK => Message::CompactBlock(*CompactBlockDecoder::new().decode(reader)?),
+            tag => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown message tag {}", tag))),
+        };
+        Ok(Box::new(message))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+
+    use crate::{core::{block::Block, compact::CompactBlock, hasher::BlockHasher, transaction::Transaction}, crypto::keypair::PrivateKey};
+
+    use super::{Message, MAGIC, MAX_MESSAGE_BYTES, PROTOCOL_VERSION};
+
+    fn round_trip(m: Message) {
+        let frame = m.to_frame().unwrap();
+        assert_eq!(&frame[..4], &MAGIC);
+        assert_eq!(frame[4], PROTOCOL_VERSION);
+        assert_eq!(Message::read_frame(&mut frame.as_slice()).unwrap(), m);
+    }
+
+    fn signed_block(height: u32) -> Block {
+        let mut tx = Transaction::new(b"foo".to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+
+        let mut b = Block::new(Block::random_block(height).header, vec![tx]);
+        assert!(b.sign(PrivateKey::generate_key()).is_ok());
+        b.hash(Box::new(BlockHasher::new()));
+        b
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let mut tx = Transaction::new(b"foo".to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+
+        round_trip(Message::NewBlock(signed_block(1)));
+        round_trip(Message::NewTransaction(tx));
+        round_trip(Message::GetBlocks { from_height: 42 });
+        round_trip(Message::Blocks(vec![]));
+        round_trip(Message::Blocks((1..4).map(signed_block).collect()));
+        round_trip(Message::CompactBlock(CompactBlock::from_block(&signed_block(1))));
+    }
+
+    #[test]
+    fn test_frames_are_read_back_to_back() {
+        let mut stream = Message::GetBlocks { from_height: 1 }.to_frame().unwrap();
+        stream.extend(Message::GetBlocks { from_height: 2 }.to_frame().unwrap());
+
+        let mut reader = stream.as_slice();
+        assert_eq!(Message::read_frame(&mut reader).unwrap(), Message::GetBlocks { from_height: 1 });
+        assert_eq!(Message::read_frame(&mut reader).unwrap(), Message::GetBlocks { from_height: 2 });
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_read_frame_rejects_bad_input() {
+        let frame = Message::GetBlocks { from_height: 42 }.to_frame().unwrap();
+        let invalid = |frame: &[u8]| Message::read_frame(&mut &frame[..]).unwrap_err().kind();
+
+        let mut unknown_tag = frame.clone();
+        unknown_tag[9] = 9;
+        assert_eq!(invalid(&unknown_tag), ErrorKind::InvalidData);
+
+        let mut bad_magic = frame.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(invalid(&bad_magic), ErrorKind::InvalidData);
+
+        let mut bad_version = frame.clone();
+        bad_version[4] = PROTOCOL_VERSION + 1;
+        assert_eq!(invalid(&bad_version), ErrorKind::InvalidData);
+
+        let mut too_large = frame.clone();
+        too_large[5..9].copy_from_slice(&(MAX_MESSAGE_BYTES as u32 + 1).to_le_bytes());
+        assert_eq!(invalid(&too_large), ErrorKind::InvalidData);
+
+        let mut trailing = frame.clone();
+        trailing[5] += 1;
+        trailing.push(0);
+        assert_eq!(invalid(&trailing), ErrorKind::InvalidData);
+
+        assert_eq!(invalid(&frame[..frame.len() - 1]), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::{HashMap, HashSet};
+
+use super::transport::NetAddr;
+
+/// Invalid messages a peer may send before it is banned, unless configured
+/// otherwise.
+pub const DEFAULT_BAN_THRESHOLD: u32 = 5;
+
+/// Messages a peer may send per second, unless configured otherwise.
+pub const DEFAULT_MAX_MESSAGES_PER_SEC: u32 = 100;
+
+/// Counts the invalid messages each peer has sent, such as undecodable
+/// frames or blocks whose signature does not verify, and bans a peer once
+/// its count exceeds `ban_threshold`. Bans are permanent for the life of
+/// the score.
+///
+/// Peers are also rate limited to `max_messages_per_sec` in each second of
+/// the clock. Every message over the limit counts as invalid, so a peer
+/// that keeps flooding is banned too.
+#[derive(Debug, Clone)]
+pub struct PeerScore {
+    invalid: HashMap<NetAddr, u32>,
+    banned: HashSet<NetAddr>,
+    ban_threshold: u32,
+    /// The second each peer last sent in and how many messages it sent in
+    /// it.
+    windows: HashMap<NetAddr, (u64, u32)>,
+    max_messages_per_sec: u32,
+}
+
+impl PeerScore {
+    pub fn new(ban_threshold: u32) -> Self {
+        PeerScore {
+            invalid: HashMap::new(),
+            banned: HashSet::new(),
+            ban_threshold,
+            windows: HashMap::new(),
+            max_messages_per_sec: DEFAULT_MAX_MESSAGES_PER_SEC,
+        }
+    }
+
+    pub fn set_max_messages_per_sec(&mut self, max_messages_per_sec: u32) {
+        self.max_messages_per_sec = max_messages_per_sec;
+    }
+
+    /// Counts a message from `peer` received in unix second `now`,
+    /// returning whether it is within the rate limit. One that is not is
+    /// also recorded as invalid.
+    pub fn record_message(&mut self, peer: &NetAddr, now: u64) -> bool {
+        let window = self.windows.entry(peer.clone()).or_insert((now, 0));
+        if window.0 != now {
+            *window = (now, 0);
+        }
+        window.1 += 1;
+        if window.1 <= self.max_messages_per_sec {
+            return true;
+        }
+        self.record_invalid(peer);
+        false
+    }
+
+    pub fn set_ban_threshold(&mut self, ban_threshold: u32) {
+        self.ban_threshold = ban_threshold;
+    }
+
+    /// Counts an invalid message from `peer`, returning whether the peer is
+    /// now banned.
+    pub fn record_invalid(&mut self, peer: &NetAddr) -> bool {
+        let count = self.invalid.entry(peer.clone()).or_insert(0);
+        *count += 1;
+        if *count > self.ban_threshold {
+            self.banned.insert(peer.clone());
+        }
+        self.is_banned(peer)
+    }
+
+    pub fn invalid_count(&self, peer: &NetAddr) -> u32 {
+        self.invalid.get(peer).copied().unwrap_or(0)
+    }
+
+    pub fn is_banned(&self, peer: &NetAddr) -> bool {
+        self.banned.contains(peer)
+    }
+}
+
+impl Default for PeerScore {
+    fn default() -> Self {
+        PeerScore::new(DEFAULT_BAN_THRESHOLD)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PeerScore;
+
+    #[test]
+    fn test_peer_score() {
+        let mut scores = PeerScore::new(2);
+        let (bad, good) = ("10.0.0.1".to_owned(), "10.0.0.2".to_owned());
+
+        assert!(!scores.record_invalid(&bad));
+        assert!(!scores.record_invalid(&bad));
+        assert!(!scores.is_banned(&bad));
+        assert!(scores.record_invalid(&bad));
+        assert!(scores.is_banned(&bad));
+        assert_eq!(scores.invalid_count(&bad), 3);
+
+        assert_eq!(scores.invalid_count(&good), 0);
+        assert!(!scores.is_banned(&good));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut scores = PeerScore::new(1);
+        scores.set_max_messages_per_sec(2);
+        let (flood, calm) = ("10.0.0.1".to_owned(), "10.0.0.2".to_owned());
+
+        assert!(scores.record_message(&flood, 10));
+        assert!(scores.record_message(&flood, 10));
+        assert!(!scores.record_message(&flood, 10));
+        assert!(scores.record_message(&calm, 10));
+        assert_eq!(scores.invalid_count(&flood), 1);
+        assert!(!scores.is_banned(&flood));
+
+        // The limit starts over each second.
+        assert!(scores.record_message(&flood, 11));
+        assert!(scores.record_message(&flood, 11));
+        assert!(!scores.record_message(&flood, 11));
+        assert!(scores.is_banned(&flood));
+        assert!(!scores.is_banned(&calm));
+    }
+}
@@ -6,24 +6,24 @@ use std::time::{Duration, Instant};
 use log::info;
 
 use crate::core::hasher::TxHasher;
+use crate::core::mempool::{Mempool, MempoolError};
 use crate::core::transaction::Transaction;
 use crate::crypto::keypair::PrivateKey;
 
 use super::channel::Channel;
 use super::transport::{Transport, RPC};
-use super::txpool::TxPool;
 
 
 pub struct ServerOpts {
     pub transports: Vec<Box<dyn Transport>>,
-    block_time: Duration,
-    key: Option<PrivateKey>,
+    pub block_time: Duration,
+    pub key: Option<PrivateKey>,
 }
 
 pub struct Server<> {
     opts: ServerOpts,
     block_time: Duration,
-    pool: TxPool,
+    pool: Mempool,
     validator: bool,
     rpc_ch: Channel<RPC>,
     quit_ch: Channel<()>,
@@ -32,12 +32,12 @@ pub struct Server<> {
 impl Server {
     pub fn new(opts: ServerOpts) -> Server {
         Server {
+            block_time: opts.block_time,
+            validator: opts.key.is_some(),
             opts,
             rpc_ch: Channel::new(),
             quit_ch: Channel::new(),
-            block_time: opts.block_time,
-            pool: TxPool::new(),
-            validator: opts.key.is_some(),
+            pool: Mempool::new(),
         }
     }
 
@@ -72,22 +72,15 @@ impl Server {
         println!("Server shutdown");
     }
 
-    fn handle_transaction(&self, tx: &Transaction) -> Result<(), Box<dyn std::error::Error>> {
-        if let Err(e) = tx.verify() {
-            return Err(Box::new(e));
-        }
-
+    fn handle_transaction(&mut self, tx: &mut Transaction) -> Result<(), Box<dyn std::error::Error>> {
         let hash = tx.hash(Box::new(TxHasher::new()));
 
-        if self.pool.has(hash.clone()) {
-            info!("transaction already in mempool: hash={}", hash);
-            return Ok(());
+        match self.pool.add(tx.clone()) {
+            Ok(()) => info!("adding new tx to the mempool: hash={}", hash),
+            Err(MempoolError::Duplicate(_)) => info!("transaction already in mempool: hash={}", hash),
+            Err(e) => return Err(e.into()),
         }
 
-        info!("adding new tx to the mempool: hash={}", hash);
-
-        self.pool.add(tx.clone());
-
         Ok(())
     }
 
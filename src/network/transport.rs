@@ -1,4 +1,16 @@
 use std::{sync::{mpsc::Receiver, Mutex, Arc}, any::Any};
+#[cfg(feature = "native")]
+use std::{sync::mpsc::SyncSender, collections::HashMap, io::{self, BufReader, Write}, net::{TcpListener, TcpStream}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+#[cfg(feature = "native")]
+use log::warn;
+
+#[cfg(feature = "native")]
+use crate::core::block::Block;
+
+use super::message::Message;
+#[cfg(feature = "native")]
+use super::{channel::Channel, peer::PeerScore};
 
 pub type NetAddr = String;
 
@@ -15,4 +27,312 @@ pub trait Transport: Send + Sync {
     fn addr(&self) -> NetAddr;
     fn as_any(&self) -> &dyn Any;
     
-}
\ No newline at end of file
+}
+
+/// A decoded message and the address of the peer that sent it.
+#[derive(Debug, Clone)]
+pub struct Packet {
+    pub from: NetAddr,
+    pub message: Message,
+}
+
+/// Exchanges framed `Message`s with peers over TCP.
+///
+/// Each connection, accepted or dialled, gets a reader thread that decodes
+/// frames off a buffered stream, so frames split across TCP segments are
+/// reassembled before decoding. A peer that sends an undecodable frame is
+/// disconnected, since the stream can no longer be trusted to be in sync.
+///
+/// Undecodable frames and blocks whose signature does not verify count
+/// against the sending host in a `PeerScore`; such blocks are dropped
+/// rather than delivered. So do messages over the host's rate limit, which
+/// are dropped too. Once a host is banned it is disconnected and neither
+/// accepted nor dialled again.
+#[cfg(feature = "native")]
+pub struct TcpTransport {
+    addr: NetAddr,
+    peers: Arc<Mutex<HashMap<NetAddr, TcpStream>>>,
+    scores: Arc<Mutex<PeerScore>>,
+    chan: Channel<Packet>,
+}
+
+#[cfg(feature = "native")]
+impl TcpTransport {
+    /// Binds `addr` and accepts peers in the background. Port 0 picks a
+    /// free port; `addr()` returns the bound address.
+    pub fn listen(addr: &str) -> io::Result<TcpTransport> {
+        let listener = TcpListener::bind(addr)?;
+        let transport = TcpTransport {
+            addr: listener.local_addr()?.to_string(),
+            peers: Arc::new(Mutex::new(HashMap::new())),
+            scores: Arc::new(Mutex::new(PeerScore::default())),
+            chan: Channel::new(),
+        };
+
+        let peers = transport.peers.clone();
+        let scores = transport.scores.clone();
+        let sender = transport.chan.sender();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = Self::add_peer(&peers, &scores, &sender, stream) {
+                            warn!("could not accept peer: {}", e);
+                        }
+                    }
+                    Err(e) => warn!("could not accept peer: {}", e),
+                }
+            }
+        });
+        Ok(transport)
+    }
+
+    /// Dials a peer and starts reading its messages.
+    pub fn connect(&self, addr: &str) -> io::Result<()> {
+        let stream = TcpStream::connect(addr)?;
+        Self::add_peer(&self.peers, &self.scores, &self.chan.sender(), stream)
+    }
+
+    /// Sets how many invalid messages a host may send before it is banned.
+    pub fn set_ban_threshold(&self, ban_threshold: u32) {
+        self.scores.lock().unwrap().set_ban_threshold(ban_threshold);
+    }
+
+    /// Sets how many messages a host may send per second before further
+    /// ones are dropped and counted as invalid.
+    pub fn set_max_messages_per_sec(&self, max_messages_per_sec: u32) {
+        self.scores.lock().unwrap().set_max_messages_per_sec(max_messages_per_sec);
+    }
+
+    /// Whether the host `ip` has been banned.
+    pub fn is_banned(&self, ip: &str) -> bool {
+        self.scores.lock().unwrap().is_banned(&ip.to_owned())
+    }
+
+    /// Sends `message` to every connected peer, dropping peers whose
+    /// connection has failed.
+    pub fn broadcast(&self, message: &Message) -> io::Result<()> {
+        let frame = message.to_frame()?;
+        let mut peers = self.peers.lock().unwrap();
+        peers.retain(|addr, stream| match stream.write_all(&frame) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("dropping peer {}: {}", addr, e);
+                false
+            }
+        });
+        Ok(())
+    }
+
+    /// Sends `message` to a single connected peer.
+    pub fn send_to(&self, to: &NetAddr, message: &Message) -> io::Result<()> {
+        let frame = message.to_frame()?;
+        let mut peers = self.peers.lock().unwrap();
+        let stream = peers
+            .get_mut(to)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, format!("no peer {}", to)))?;
+        stream.write_all(&frame)
+    }
+
+    /// Blocks until a message arrives from any peer.
+    pub fn recv(&self) -> Option<Packet> {
+        self.chan.receiver().lock().unwrap().recv().ok()
+    }
+
+    /// Waits up to `timeout` for a message from any peer.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Packet> {
+        self.chan.receiver().lock().unwrap().recv_timeout(timeout).ok()
+    }
+
+    pub fn addr(&self) -> NetAddr {
+        self.addr.clone()
+    }
+
+    pub fn peers(&self) -> Vec<NetAddr> {
+        self.peers.lock().unwrap().keys().cloned().collect()
+    }
+
+    fn add_peer(
+        peers: &Arc<Mutex<HashMap<NetAddr, TcpStream>>>,
+        scores: &Arc<Mutex<PeerScore>>,
+        sender: &SyncSender<Packet>,
+        stream: TcpStream,
+    ) -> io::Result<()> {
+        let addr = stream.peer_addr()?;
+        let (from, host) = (addr.to_string(), addr.ip().to_string());
+        if scores.lock().unwrap().is_banned(&host) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is banned", host)));
+        }
+        let mut reader = BufReader::new(stream.try_clone()?);
+        peers.lock().unwrap().insert(from.clone(), stream);
+
+        let peers = peers.clone();
+        let scores = scores.clone();
+        let sender = sender.clone();
+        std::thread::spawn(move || {
+            loop {
+                match Message::read_frame(&mut reader) {
+                    Ok(_) if !scores.lock().unwrap().record_message(&host, unix_secs()) => {
+                        if scores.lock().unwrap().is_banned(&host) {
+                            warn!("banning peer {}: too many messages", from);
+                            break;
+                        }
+                    }
+                    Ok(message) if has_bad_signature(&message) => {
+                        if scores.lock().unwrap().record_invalid(&host) {
+                            warn!("banning peer {}: too many invalid blocks", from);
+                            break;
+                        }
+                    }
+                    Ok(message) => {
+                        if sender.send(Packet { from: from.clone(), message }).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        if e.kind() != io::ErrorKind::UnexpectedEof {
+                            warn!("dropping peer {}: {}", from, e);
+                            scores.lock().unwrap().record_invalid(&host);
+                        }
+                        break;
+                    }
+                }
+            }
+            if let Some(stream) = peers.lock().unwrap().remove(&from) {
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(feature = "native")]
+fn unix_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Whether `message` carries a block whose signature does not verify.
+/// Genesis is unsigned, so only blocks above it have to verify.
+#[cfg(feature = "native")]
+fn has_bad_signature(message: &Message) -> bool {
+    let bad = |b: &Block| b.header.height != 0 && b.verify().is_err();
+    match message {
+        Message::NewBlock(b) => bad(b),
+        Message::Blocks(blocks) => blocks.iter().any(bad),
+        _ => false,
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod test {
+    use std::{io::Write, net::TcpStream, thread, time::Duration};
+
+    use crate::{core::{block::Block, transaction::Transaction}, crypto::keypair::PrivateKey, network::message::Message};
+
+    use super::TcpTransport;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn signed_tx() -> Transaction {
+        let mut tx = Transaction::new(b"foo".to_vec());
+        assert!(tx.sign(PrivateKey::generate_key()).is_ok());
+        tx
+    }
+
+    #[test]
+    fn test_tcp_send_transaction() {
+        let a = TcpTransport::listen("127.0.0.1:0").unwrap();
+        let b = TcpTransport::listen("127.0.0.1:0").unwrap();
+        assert!(b.connect(&a.addr()).is_ok());
+
+        let tx = signed_tx();
+        assert!(b.broadcast(&Message::NewTransaction(tx.clone())).is_ok());
+
+        let packet = a.recv().unwrap();
+        assert_eq!(packet.message, Message::NewTransaction(tx));
+
+        // The accepted connection carries replies back.
+        let reply = Message::GetBlocks { from_height: 3 };
+        assert!(a.send_to(&packet.from, &reply).is_ok());
+        assert_eq!(b.recv_timeout(TIMEOUT).unwrap().message, reply);
+    }
+
+    #[test]
+    fn test_tcp_frame_split_across_writes() {
+        let a = TcpTransport::listen("127.0.0.1:0").unwrap();
+        let message = Message::NewTransaction(signed_tx());
+        let frame = message.to_frame().unwrap();
+
+        let mut stream = TcpStream::connect(a.addr()).unwrap();
+        stream.set_nodelay(true).unwrap();
+        for chunk in frame.chunks(7) {
+            stream.write_all(chunk).unwrap();
+            stream.flush().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(a.recv_timeout(TIMEOUT).unwrap().message, message);
+    }
+
+    #[test]
+    fn test_tcp_bans_peer_sending_invalid_blocks() {
+        let a = TcpTransport::listen("127.0.0.1:0").unwrap();
+        a.set_ban_threshold(3);
+
+        let mut forged = Block::random_block(1);
+        assert!(forged.sign(PrivateKey::generate_key()).is_ok());
+        forged.header.nonce += 1;
+        let frame = Message::NewBlock(forged).to_frame().unwrap();
+
+        let mut stream = TcpStream::connect(a.addr()).unwrap();
+        for _ in 0..4 {
+            stream.write_all(&frame).unwrap();
+        }
+
+        assert!(a.recv_timeout(Duration::from_millis(200)).is_none());
+        let deadline = std::time::Instant::now() + TIMEOUT;
+        while !(a.is_banned("127.0.0.1") && a.peers().is_empty()) && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(a.is_banned("127.0.0.1"));
+        assert!(a.peers().is_empty());
+
+        let b = TcpTransport::listen("127.0.0.1:0").unwrap();
+        assert!(a.connect(&b.addr()).is_err());
+    }
+
+    #[test]
+    fn test_tcp_bans_peer_over_rate_limit() {
+        let a = TcpTransport::listen("127.0.0.1:0").unwrap();
+        a.set_ban_threshold(0);
+        a.set_max_messages_per_sec(1);
+
+        let frame = Message::NewTransaction(signed_tx()).to_frame().unwrap();
+        let mut stream = TcpStream::connect(a.addr()).unwrap();
+        for _ in 0..3 {
+            stream.write_all(&frame).unwrap();
+        }
+
+        // Only the first message is within the limit.
+        assert!(a.recv_timeout(TIMEOUT).is_some());
+        let deadline = std::time::Instant::now() + TIMEOUT;
+        while !a.is_banned("127.0.0.1") && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(a.is_banned("127.0.0.1"));
+    }
+
+    #[test]
+    fn test_tcp_drops_peer_sending_garbage() {
+        let a = TcpTransport::listen("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(a.addr()).unwrap();
+        stream.write_all(b"not a frame").unwrap();
+
+        assert!(a.recv_timeout(Duration::from_millis(200)).is_none());
+        let deadline = std::time::Instant::now() + TIMEOUT;
+        while !a.peers().is_empty() && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(a.peers().is_empty());
+    }
+}
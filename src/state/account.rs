@@ -0,0 +1,574 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::core::{block::Block, encoding::{AccountMapDecoder, AccountMapEncoder, Decode, Encode}, transaction::Transaction};
+use crate::crypto::hasher::{Hasher, Sha256Hasher};
+use crate::types::{address::Address, hash::Hash};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum StateError {
+    /// The transaction is unsigned or its signature does not verify.
+    InvalidSignature(String),
+    /// The transaction data is not an encoded transfer, or the transaction's
+    /// `from`, `to` or `amount` disagree with it.
+    Malformed(String),
+    /// The transaction was signed by a key other than the sender's.
+    WrongSender(Address),
+    /// The sender cannot cover the amount plus fee.
+    InsufficientBalance { address: Address, balance: u64, amount: u64 },
+    /// The transfer nonce is not the sender's next one.
+    BadNonce { address: Address, expected: u64, got: u64 },
+    /// Crediting the recipient would overflow its balance.
+    BalanceOverflow(Address),
+    /// The transaction's `valid_until` is below the height of the block
+    /// applying it.
+    Expired { valid_until: u32, height: u32 },
+    /// The bytes are not a snapshot written by `AccountState::snapshot`.
+    InvalidSnapshot(String),
+    /// Applying the block would change the total supply by `actual` where
+    /// the `SupplyPolicy` allows exactly `expected`.
+    SupplyMismatch { expected: i128, actual: i128 },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::InvalidSignature(e) => write!(f, "invalid signature: {}", e),
+            StateError::Malformed(e) => write!(f, "malformed transfer: {}", e),
+            StateError::WrongSender(address) => write!(f, "transfer from {} is not signed by its sender", address),
+            StateError::InsufficientBalance { address, balance, amount } => {
+                write!(f, "account {} has balance {}, cannot send {}", address, balance, amount)
+            }
+            StateError::BadNonce { address, expected, got } => {
+                write!(f, "account {} expected nonce {}, got {}", address, expected, got)
+            }
+            StateError::BalanceOverflow(address) => write!(f, "balance of account {} would overflow", address),
+            StateError::Expired { valid_until, height } => {
+                write!(f, "transaction valid until height {} applied at height {}", valid_until, height)
+            }
+            StateError::InvalidSnapshot(e) => write!(f, "invalid state snapshot: {}", e),
+            StateError::SupplyMismatch { expected, actual } => {
+                write!(f, "block changes the supply by {}, expected {}", actual, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// What becomes of the fees paid by a block's transfers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeePolicy {
+    /// The fees leave circulation.
+    Burn,
+    /// The block's coinbase collects the fees on top of the reward. Fees
+    /// in a block without a coinbase are still burned.
+    Redistribute,
+}
+
+/// How much each block may change the total supply by: a block with a
+/// coinbase mints exactly `reward`, and every fee not redistributed per
+/// `fees` is taken out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SupplyPolicy {
+    pub reward: u64,
+    pub fees: FeePolicy,
+}
+
+impl SupplyPolicy {
+    /// Checks that applying `block` changes the total supply by what the
+    /// policy allows. A transfer moves its amount and debits its fee, so the
+    /// change is what the coinbase credits less the fees, without summing
+    /// any balances.
+    pub fn check(&self, block: &Block) -> Result<(), StateError> {
+        let coinbase = block.coinbase();
+        let minted = match coinbase {
+            Some(coinbase) => coinbase.decode_transfer().map_err(|e| StateError::Malformed(e.to_string()))?.amount as i128,
+            None => 0,
+        };
+        let fees: i128 = block.transactions[coinbase.is_some() as usize..].iter().map(|tx| tx.fee() as i128).sum();
+        let (reward, redistributed) = match (coinbase, self.fees) {
+            (None, _) => (0, 0),
+            (Some(_), FeePolicy::Burn) => (self.reward as i128, 0),
+            (Some(_), FeePolicy::Redistribute) => (self.reward as i128, fees),
+        };
+        let expected = reward - (fees - redistributed);
+        let actual = minted - fees;
+        if actual != expected {
+            return Err(StateError::SupplyMismatch { expected, actual });
+        }
+        Ok(())
+    }
+}
+
+/// Account balances and the next expected transfer nonce per account.
+/// Accounts that have never been touched have a zero balance and nonce.
+#[derive(Clone)]
+pub struct AccountState {
+    balances: HashMap<Address, u64>,
+    nonces: HashMap<Address, u64>,
+    policy: Option<SupplyPolicy>,
+}
+
+impl AccountState {
+    pub fn new() -> Self {
+        AccountState {
+            balances: HashMap::new(),
+            nonces: HashMap::new(),
+            policy: None,
+        }
+    }
+
+    /// Makes `apply_block` reject blocks that change the total supply by
+    /// anything other than what `policy` allows. Without a policy a
+    /// coinbase may mint any amount. The policy is not part of snapshots;
+    /// a `Blockchain` with `set_supply_policy` applies it to every state it
+    /// tracks or restores.
+    pub fn set_supply_policy(&mut self, policy: SupplyPolicy) {
+        self.policy = Some(policy);
+    }
+
+    pub fn balance(&self, address: &Address) -> u64 {
+        self.balances.get(address).copied().unwrap_or(0)
+    }
+
+    pub fn nonce(&self, address: &Address) -> u64 {
+        self.nonces.get(address).copied().unwrap_or(0)
+    }
+
+    /// Adds funds to an account outside of any transfer, e.g. to seed
+    /// genesis balances.
+    pub fn credit(&mut self, address: Address, amount: u64) -> Result<(), StateError> {
+        let balance = self.balance(&address)
+            .checked_add(amount)
+            .ok_or(StateError::BalanceOverflow(address))?;
+        self.balances.insert(address, balance);
+        Ok(())
+    }
+
+    /// Applies a signed transfer. Nothing is changed if it is rejected.
+    ///
+    /// The sender pays `amount + fee`; the fee leaves the sender's account
+    /// and crediting it to the block producer is up to the caller.
+    ///
+    /// A successful apply bumps the sender's nonce, so the same signed
+    /// transaction can never be applied twice.
+    pub fn apply(&mut self, tx: &Transaction) -> Result<(), StateError> {
+        let transfer = tx.decode_transfer()
+            .map_err(|e| StateError::Malformed(e.to_string()))?;
+
+        // Checked ahead of the signature, which also fails when `from` is
+        // not the signer's address.
+        let from = transfer.from;
+        if tx.key.is_some_and(|key| key.address() != from) {
+            return Err(StateError::WrongSender(from));
+        }
+        tx.verify().map_err(StateError::InvalidSignature)?;
+
+        let expected = self.nonce(&from);
+        if transfer.nonce != expected {
+            return Err(StateError::BadNonce { address: from, expected, got: transfer.nonce });
+        }
+
+        let balance = self.balance(&from);
+        let total = match transfer.amount.checked_add(transfer.fee) {
+            Some(total) if total <= balance => total,
+            total => {
+                let amount = total.unwrap_or(u64::MAX);
+                return Err(StateError::InsufficientBalance { address: from, balance, amount });
+            }
+        };
+
+        if from != transfer.to && self.balance(&transfer.to).checked_add(transfer.amount).is_none() {
+            return Err(StateError::BalanceOverflow(transfer.to));
+        }
+
+        self.balances.insert(from, balance - total);
+        self.credit(transfer.to, transfer.amount)?;
+        self.nonces.insert(from, expected + 1);
+        Ok(())
+    }
+
+    /// Credits the block's coinbase, if any, then applies the remaining
+    /// transactions in order. Either the whole block applies or nothing is
+    /// changed. Fees are debited from senders but, as with `apply`, not
+    /// credited to anyone; a coinbase collecting them has to include them
+    /// in its amount. With a supply policy set, the block must then have
+    /// changed the total supply by exactly what the policy allows.
+    pub fn apply_block(&mut self, block: &Block) -> Result<(), StateError> {
+        let mut state = self.clone();
+        let mut transactions = block.transactions.as_slice();
+        let coinbase = block.coinbase();
+        if let Some(coinbase) = coinbase {
+            let transfer = coinbase.decode_transfer()
+                .map_err(|e| StateError::Malformed(e.to_string()))?;
+            state.credit(transfer.to, transfer.amount)?;
+            transactions = &transactions[1..];
+        }
+
+        let height = block.header.height;
+        for tx in transactions {
+            if !tx.is_valid_at(height) {
+                let valid_until = tx.valid_until.expect("only a transaction with valid_until expires");
+                return Err(StateError::Expired { valid_until, height });
+            }
+            state.apply(tx)?;
+        }
+
+        if let Some(policy) = self.policy {
+            policy.check(block)?;
+        }
+        *self = state;
+        Ok(())
+    }
+
+    /// Encodes the balances and then the nonces with `AccountMapEncoder`.
+    /// Zero entries are left out, being indistinguishable from untouched
+    /// accounts, so equal states have equal snapshots.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let non_zero = |map: &HashMap<Address, u64>| -> HashMap<Address, u64> {
+            map.iter().filter(|(_, value)| **value != 0).map(|(address, value)| (*address, *value)).collect()
+        };
+        let mut bytes = vec![];
+        for map in [&self.balances, &self.nonces] {
+            non_zero(map).encode_binary(&mut bytes, AccountMapEncoder::new()).expect("writing to memory does not fail");
+        }
+        bytes
+    }
+
+    /// The hash of `snapshot`, which block headers commit to as their
+    /// `state_root`. Equal states have equal roots.
+    pub fn root(&self) -> Hash {
+        Sha256Hasher.hash(&self.snapshot())
+    }
+
+    /// Balance and nonce of every account with either set, by address.
+    fn accounts(&self) -> BTreeMap<Address, (u64, u64)> {
+        self.balances
+            .keys()
+            .chain(self.nonces.keys())
+            .map(|address| (*address, (self.balance(address), self.nonce(address))))
+            .filter(|(_, account)| *account != (0, 0))
+            .collect()
+    }
+
+    /// Rebuilds the state a `snapshot` was taken of.
+    pub fn restore(mut bytes: &[u8]) -> Result<AccountState, StateError> {
+        let invalid = |e: std::io::Error| StateError::InvalidSnapshot(e.to_string());
+        let reader = &mut bytes;
+
+        let balances = *HashMap::decode_binary(reader, AccountMapDecoder::new()).map_err(invalid)?;
+        let nonces = *HashMap::decode_binary(reader, AccountMapDecoder::new()).map_err(invalid)?;
+        if !reader.is_empty() {
+            return Err(StateError::InvalidSnapshot("trailing bytes after snapshot".to_owned()));
+        }
+        Ok(AccountState { balances, nonces, policy: None })
+    }
+}
+
+/// States are equal when every account has the same balance and nonce.
+impl PartialEq for AccountState {
+    fn eq(&self, other: &Self) -> bool {
+        self.accounts() == other.accounts()
+    }
+}
+
+impl fmt::Debug for AccountState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.accounts()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{core::{block::Block, transaction::Transaction, transfer::Transfer}, crypto::keypair::PrivateKey, types::address::Address};
+
+    use super::{AccountState, FeePolicy, StateError, SupplyPolicy};
+
+    fn transfer_tx(key: &PrivateKey, to: Address, amount: u64, nonce: u64) -> Transaction {
+        transfer_tx_with_fee(key, to, amount, nonce, 0)
+    }
+
+    fn transfer_tx_with_fee(key: &PrivateKey, to: Address, amount: u64, nonce: u64, fee: u64) -> Transaction {
+        let from = key.generate_public().address();
+        let transfer = Transfer { from, to, amount, nonce, fee };
+
+        let mut tx = Transaction::transfer(transfer);
+        assert!(tx.sign(key.clone()).is_ok());
+        tx
+    }
+
+    #[test]
+    fn test_apply_transfer() {
+        let key = PrivateKey::generate_key();
+        let from = key.generate_public().address();
+        let to = PrivateKey::generate_key().generate_public().address();
+
+        let mut state = AccountState::new();
+        assert!(state.credit(from, 100).is_ok());
+
+        assert!(state.apply(&transfer_tx(&key, to, 30, 0)).is_ok());
+        assert_eq!(state.balance(&from), 70);
+        assert_eq!(state.balance(&to), 30);
+        assert_eq!(state.nonce(&from), 1);
+        assert_eq!(state.nonce(&to), 0);
+    }
+
+    #[test]
+    fn test_apply_overdraft() {
+        let key = PrivateKey::generate_key();
+        let from = key.generate_public().address();
+        let to = PrivateKey::generate_key().generate_public().address();
+
+        let mut state = AccountState::new();
+        assert!(state.credit(from, 10).is_ok());
+
+        assert_eq!(
+            state.apply(&transfer_tx(&key, to, 11, 0)),
+            Err(StateError::InsufficientBalance { address: from, balance: 10, amount: 11 })
+        );
+        assert_eq!(state.balance(&from), 10);
+        assert_eq!(state.balance(&to), 0);
+        assert_eq!(state.nonce(&from), 0);
+    }
+
+    #[test]
+    fn test_apply_charges_fee() {
+        let key = PrivateKey::generate_key();
+        let from = key.generate_public().address();
+        let to = PrivateKey::generate_key().generate_public().address();
+
+        let mut state = AccountState::new();
+        assert!(state.credit(from, 100).is_ok());
+
+        assert!(state.apply(&transfer_tx_with_fee(&key, to, 30, 0, 5)).is_ok());
+        assert_eq!(state.balance(&from), 65);
+        assert_eq!(state.balance(&to), 30);
+
+        assert_eq!(
+            state.apply(&transfer_tx_with_fee(&key, to, 60, 1, 6)),
+            Err(StateError::InsufficientBalance { address: from, balance: 65, amount: 66 })
+        );
+        assert_eq!(
+            state.apply(&transfer_tx_with_fee(&key, to, 1, 1, u64::MAX)),
+            Err(StateError::InsufficientBalance { address: from, balance: 65, amount: u64::MAX })
+        );
+    }
+
+    #[test]
+    fn test_apply_replayed_nonce() {
+        let key = PrivateKey::generate_key();
+        let from = key.generate_public().address();
+        let to = PrivateKey::generate_key().generate_public().address();
+
+        let mut state = AccountState::new();
+        assert!(state.credit(from, 100).is_ok());
+        assert!(state.apply(&transfer_tx(&key, to, 10, 0)).is_ok());
+
+        assert_eq!(
+            state.apply(&transfer_tx(&key, to, 20, 0)),
+            Err(StateError::BadNonce { address: from, expected: 1, got: 0 })
+        );
+        assert_eq!(
+            state.apply(&transfer_tx(&key, to, 20, 2)),
+            Err(StateError::BadNonce { address: from, expected: 1, got: 2 })
+        );
+        assert_eq!(state.balance(&from), 90);
+    }
+
+    #[test]
+    fn test_apply_same_transaction_twice() {
+        let key = PrivateKey::generate_key();
+        let from = key.generate_public().address();
+        let to = PrivateKey::generate_key().generate_public().address();
+
+        let mut state = AccountState::new();
+        assert!(state.credit(from, 100).is_ok());
+
+        let tx = transfer_tx(&key, to, 10, 0);
+        assert!(state.apply(&tx).is_ok());
+        assert_eq!(
+            state.apply(&tx),
+            Err(StateError::BadNonce { address: from, expected: 1, got: 0 })
+        );
+        assert_eq!(state.balance(&from), 90);
+        assert_eq!(state.balance(&to), 10);
+    }
+
+    #[test]
+    fn test_apply_block_credits_coinbase() {
+        let key = PrivateKey::generate_key();
+        let validator = key.generate_public().address();
+        let to = PrivateKey::generate_key().generate_public().address();
+
+        let mut b = Block::new(Block::random_block(1).header, vec![transfer_tx(&key, to, 30, 0)]);
+        b.add_coinbase(validator, 50);
+
+        let mut state = AccountState::new();
+        assert!(state.apply_block(&b).is_ok());
+        assert_eq!(state.balance(&validator), 20);
+        assert_eq!(state.balance(&to), 30);
+
+        // The block's transfer now replays a used nonce, so nothing applies,
+        // not even the coinbase.
+        assert_eq!(
+            state.apply_block(&b),
+            Err(StateError::BadNonce { address: validator, expected: 1, got: 0 })
+        );
+        assert_eq!(state.balance(&validator), 20);
+    }
+
+    #[test]
+    fn test_apply_block_rejects_second_coinbase() {
+        let to = PrivateKey::generate_key().generate_public().address();
+        let mut b = Block::random_block(1);
+        b.add_coinbase(to, 50);
+        b.add_coinbase(to, 50);
+
+        let mut state = AccountState::new();
+        assert!(matches!(state.apply_block(&b), Err(StateError::InvalidSignature(_))));
+        assert_eq!(state.balance(&to), 0);
+    }
+
+    #[test]
+    fn test_apply_block_checks_supply() {
+        let key = PrivateKey::generate_key();
+        let from = key.generate_public().address();
+        let validator = PrivateKey::generate_key().generate_public().address();
+        let to = PrivateKey::generate_key().generate_public().address();
+
+        let block = |reward: u64| {
+            let mut b = Block::new(Block::random_block(1).header, vec![transfer_tx_with_fee(&key, to, 30, 0, 5)]);
+            b.add_coinbase(validator, reward);
+            b
+        };
+
+        let mut state = AccountState::new();
+        assert!(state.credit(from, 100).is_ok());
+        state.set_supply_policy(SupplyPolicy { reward: 50, fees: FeePolicy::Burn });
+
+        // The coinbase mints more than the reward, or also collects the
+        // fees that are meant to be burned.
+        assert_eq!(state.apply_block(&block(60)), Err(StateError::SupplyMismatch { expected: 45, actual: 55 }));
+        assert_eq!(state.apply_block(&block(55)), Err(StateError::SupplyMismatch { expected: 45, actual: 50 }));
+        assert_eq!(state.balance(&validator), 0);
+        assert_eq!(state.balance(&from), 100);
+
+        let mut redistributing = state.clone();
+        assert!(state.apply_block(&block(50)).is_ok());
+        assert_eq!(state.balance(&validator), 50);
+
+        redistributing.set_supply_policy(SupplyPolicy { reward: 50, fees: FeePolicy::Redistribute });
+        assert_eq!(redistributing.apply_block(&block(60)), Err(StateError::SupplyMismatch { expected: 50, actual: 55 }));
+        assert!(redistributing.apply_block(&block(55)).is_ok());
+        assert_eq!(redistributing.balance(&validator), 55);
+
+        // Without a coinbase nothing is minted and the fees are burned.
+        let b = Block::new(Block::random_block(2).header, vec![transfer_tx_with_fee(&key, to, 10, 1, 5)]);
+        assert!(redistributing.apply_block(&b).is_ok());
+        assert_eq!(redistributing.balance(&from), 50);
+    }
+
+    #[test]
+    fn test_apply_block_rejects_expired() {
+        let key = PrivateKey::generate_key();
+        let to = PrivateKey::generate_key().generate_public().address();
+        let mut tx = transfer_tx(&key, to, 30, 0);
+        tx.valid_until = Some(3);
+        assert!(tx.sign(key.clone()).is_ok());
+
+        let mut state = AccountState::new();
+        assert!(state.credit(key.generate_public().address(), 100).is_ok());
+
+        let late = Block::new(Block::random_block(5).header, vec![tx.clone()]);
+        assert_eq!(state.apply_block(&late), Err(StateError::Expired { valid_until: 3, height: 5 }));
+        assert_eq!(late.verify_transactions(), Err(0));
+        assert_eq!(state.balance(&to), 0);
+
+        let early = Block::new(Block::random_block(2).header, vec![tx]);
+        assert!(early.verify_transactions().is_ok());
+        assert!(state.apply_block(&early).is_ok());
+        assert_eq!(state.balance(&to), 30);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let keys: Vec<PrivateKey> = (0..3).map(|_| PrivateKey::generate_key()).collect();
+        let address = |i: usize| keys[i].generate_public().address();
+
+        let mut state = AccountState::new();
+        for height in 1..=4u64 {
+            let key = &keys[height as usize % 3];
+            let txs = match height {
+                1 => vec![],
+                _ => vec![transfer_tx(&keys[1], address(2), 10, height - 2)],
+            };
+            let mut b = Block::new(Block::random_block(height as u32).header, txs);
+            b.add_coinbase(key.generate_public().address(), 50);
+            assert!(state.apply_block(&b).is_ok());
+        }
+        assert!(state.apply(&transfer_tx(&keys[2], address(0), 30, 0)).is_ok());
+
+        let restored = AccountState::restore(&state.snapshot()).unwrap();
+        assert_eq!(restored, state);
+        for i in 0..3 {
+            assert_eq!(restored.balance(&address(i)), state.balance(&address(i)));
+            assert_eq!(restored.nonce(&address(i)), state.nonce(&address(i)));
+        }
+        assert_eq!(restored.snapshot(), state.snapshot());
+        assert_eq!(AccountState::restore(&AccountState::new().snapshot()), Ok(AccountState::new()));
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_snapshots() {
+        let mut state = AccountState::new();
+        assert!(state.credit(Address::from_bytes(&[1; 20]).unwrap(), 5).is_ok());
+        assert!(state.credit(Address::from_bytes(&[2; 20]).unwrap(), 7).is_ok());
+        let snapshot = state.snapshot();
+
+        let mut trailing = snapshot.clone();
+        trailing.push(0);
+        assert!(matches!(AccountState::restore(&trailing), Err(StateError::InvalidSnapshot(_))));
+        assert!(matches!(AccountState::restore(&snapshot[..snapshot.len() - 1]), Err(StateError::InvalidSnapshot(_))));
+
+        // Swap the two 28-byte balances after the count.
+        let mut unordered = snapshot[..1].to_vec();
+        unordered.extend(&snapshot[29..57]);
+        unordered.extend(&snapshot[1..29]);
+        unordered.extend(&snapshot[57..]);
+        assert!(matches!(AccountState::restore(&unordered), Err(StateError::InvalidSnapshot(_))));
+    }
+
+    #[test]
+    fn test_apply_rejects_wrong_sender() {
+        let owner = PrivateKey::generate_key().generate_public().address();
+        let thief = PrivateKey::generate_key();
+        let transfer = Transfer { from: owner, to: thief.generate_public().address(), amount: 5, nonce: 0, fee: 0 };
+
+        let mut tx = Transaction::transfer(transfer);
+        assert!(tx.sign(thief).is_ok());
+
+        let mut state = AccountState::new();
+        assert!(state.credit(owner, 100).is_ok());
+        assert_eq!(state.apply(&tx), Err(StateError::WrongSender(owner)));
+        assert_eq!(state.balance(&owner), 100);
+    }
+
+    #[test]
+    fn test_apply_rejects_fields_disagreeing_with_transfer() {
+        let key = PrivateKey::generate_key();
+        let from = key.generate_public().address();
+        let (a, b) = (Address::from_bytes(&[1; 20]).unwrap(), Address::from_bytes(&[2; 20]).unwrap());
+
+        let mut tx = Transaction::transfer(Transfer { from, to: b, amount: 5, nonce: 0, fee: 0 });
+        tx.to = Some(a);
+        tx.amount = 100;
+        assert!(tx.sign(key).is_ok());
+        assert!(tx.verify().is_ok());
+
+        let mut state = AccountState::new();
+        assert!(state.credit(from, 100).is_ok());
+        assert!(matches!(state.apply(&tx), Err(StateError::Malformed(_))));
+        assert_eq!((state.balance(&a), state.balance(&b), state.balance(&from)), (0, 0, 100));
+    }
+}
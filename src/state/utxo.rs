@@ -0,0 +1,313 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use encode_decode_derive::{Encode, Decode};
+
+use crate::core::{encoding::{Encoder, Decoder, Encode, Decode, write_varint, read_varint}, hasher::{Hasher, TxHasher}, transaction::Transaction};
+use crate::types::{address::Address, hash::Hash};
+
+/// Refers to output `index` of the transaction hashing to `prev_tx`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct TxInput {
+    pub prev_tx: Hash,
+    pub index: u32,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TxOutput {
+    pub amount: u64,
+    pub owner: Address,
+}
+
+/// Spends outputs and creates new ones, carried encoded in
+/// `Transaction.data` after the `TxKind::Utxo` tag. Whatever the inputs hold beyond the new outputs is
+/// the fee.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone)]
+pub struct UtxoTransaction {
+    pub inputs: Vec<TxInput>,
+    pub outputs: Vec<TxOutput>,
+}
+
+impl UtxoTransaction {
+    pub fn as_bytes(&self) -> Result<Vec<u8>, io::Error> {
+        let mut buf = vec![];
+        self.encode_binary(&mut buf, UtxoTransactionEncoder::new())?;
+        Ok(buf)
+    }
+
+    /// Decodes a UTXO transaction, rejecting trailing bytes.
+    pub fn from_bytes(mut b: &[u8]) -> Result<UtxoTransaction, io::Error> {
+        let tx = UtxoTransaction::decode_binary(&mut b, UtxoTransactionDecoder::new())?;
+        if !b.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "trailing bytes after UTXO transaction"));
+        }
+        Ok(*tx)
+    }
+}
+
+/// Varint input count, each input as `prev_tx` and a little-endian `u32`
+/// index, then a varint output count, each output as a little-endian `u64`
+/// amount and the owner address.
+pub struct UtxoTransactionEncoder {}
+
+impl UtxoTransactionEncoder {
+    pub fn new() -> Self {
+        UtxoTransactionEncoder {}
+    }
+}
+
+impl Encoder<UtxoTransaction> for UtxoTransactionEncoder {
+    fn encode<W: Write>(&self, writer: &mut W, t: &UtxoTransaction) -> io::Result<()> {
+        write_varint(writer, t.inputs.len() as u64)?;
+        for input in &t.inputs {
+            input.prev_tx.encode_binary(writer)?;
+            writer.write_u32::<LittleEndian>(input.index)?;
+        }
+
+        write_varint(writer, t.outputs.len() as u64)?;
+        for output in &t.outputs {
+            writer.write_u64::<LittleEndian>(output.amount)?;
+            output.owner.encode_binary(writer)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct UtxoTransactionDecoder {}
+
+impl UtxoTransactionDecoder {
+    pub fn new() -> Self {
+        UtxoTransactionDecoder {}
+    }
+}
+
+impl Decoder<UtxoTransaction> for UtxoTransactionDecoder {
+    fn decode<R: Read>(&self, reader: &mut R) -> io::Result<Box<UtxoTransaction>> {
+        let mut inputs = vec![];
+        for _ in 0..read_varint(reader)? {
+            inputs.push(TxInput {
+                prev_tx: Hash::decode_binary(reader)?,
+                index: reader.read_u32::<LittleEndian>()?,
+            });
+        }
+
+        let mut outputs = vec![];
+        for _ in 0..read_varint(reader)? {
+            outputs.push(TxOutput {
+                amount: reader.read_u64::<LittleEndian>()?,
+                owner: Address::decode_binary(reader)?,
+            });
+        }
+
+        Ok(Box::new(UtxoTransaction { inputs, outputs }))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum UtxoError {
+    /// The transaction is unsigned or its signature does not verify.
+    InvalidSignature(String),
+    /// The transaction data is not an encoded UTXO transaction.
+    Malformed(String),
+    /// The input refers to an output that does not exist or has already
+    /// been spent.
+    UnknownInput(TxInput),
+    /// The input appears more than once in the transaction.
+    DuplicateInput(TxInput),
+    /// The input's output is owned by someone other than the signer.
+    WrongOwner(TxInput),
+    /// The outputs are worth more than the inputs.
+    Overspend { inputs: u64, outputs: u64 },
+}
+
+impl fmt::Display for UtxoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UtxoError::InvalidSignature(e) => write!(f, "invalid signature: {}", e),
+            UtxoError::Malformed(e) => write!(f, "malformed UTXO transaction: {}", e),
+            UtxoError::UnknownInput(input) => write!(f, "output {}:{} is unknown or spent", input.prev_tx, input.index),
+            UtxoError::DuplicateInput(input) => write!(f, "output {}:{} is spent twice", input.prev_tx, input.index),
+            UtxoError::WrongOwner(input) => write!(f, "output {}:{} is not owned by the signer", input.prev_tx, input.index),
+            UtxoError::Overspend { inputs, outputs } => write!(f, "outputs worth {} exceed inputs worth {}", outputs, inputs),
+        }
+    }
+}
+
+impl std::error::Error for UtxoError {}
+
+/// Unspent outputs by the input that would spend them.
+pub struct UtxoSet {
+    outputs: HashMap<TxInput, TxOutput>,
+}
+
+impl UtxoSet {
+    pub fn new() -> Self {
+        UtxoSet {
+            outputs: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, input: &TxInput) -> Option<&TxOutput> {
+        self.outputs.get(input)
+    }
+
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    /// Adds an output outside of any transaction, e.g. to seed genesis
+    /// funds.
+    pub fn insert(&mut self, input: TxInput, output: TxOutput) {
+        self.outputs.insert(input, output);
+    }
+
+    /// Sum of the unspent outputs owned by `owner`.
+    pub fn balance(&self, owner: &Address) -> u64 {
+        self.outputs.values().filter(|o| o.owner == *owner).map(|o| o.amount).sum()
+    }
+
+    /// Applies a signed UTXO transaction. Nothing is changed if it is
+    /// rejected.
+    ///
+    /// Every input must be unspent and owned by the signer, and the outputs
+    /// may not be worth more than the inputs. The inputs are removed and
+    /// output `i` becomes spendable as `TxInput { prev_tx: <tx hash>, index: i }`.
+    pub fn apply(&mut self, tx: &Transaction) -> Result<(), UtxoError> {
+        tx.verify().map_err(UtxoError::InvalidSignature)?;
+        let utxo_tx = tx.decode_utxo()
+            .map_err(|e| UtxoError::Malformed(e.to_string()))?;
+        let signer = tx.key.map(|key| key.address());
+
+        let mut seen = HashSet::new();
+        let mut inputs: u64 = 0;
+        for input in &utxo_tx.inputs {
+            if !seen.insert(*input) {
+                return Err(UtxoError::DuplicateInput(*input));
+            }
+            let output = self.outputs.get(input).ok_or(UtxoError::UnknownInput(*input))?;
+            if Some(output.owner) != signer {
+                return Err(UtxoError::WrongOwner(*input));
+            }
+            inputs = inputs.saturating_add(output.amount);
+        }
+
+        let outputs = utxo_tx.outputs.iter().fold(0u64, |sum, o| sum.saturating_add(o.amount));
+        if outputs > inputs {
+            return Err(UtxoError::Overspend { inputs, outputs });
+        }
+
+        let prev_tx = TxHasher::new().hash(tx).map_err(UtxoError::Malformed)?;
+        for input in &utxo_tx.inputs {
+            self.outputs.remove(input);
+        }
+        for (index, output) in (0u32..).zip(utxo_tx.outputs) {
+            self.outputs.insert(TxInput { prev_tx, index }, output);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{core::{hasher::{Hasher, TxHasher}, transaction::Transaction}, crypto::keypair::PrivateKey, types::{address::Address, hash::Hash}};
+
+    use super::{TxInput, TxOutput, UtxoError, UtxoSet, UtxoTransaction};
+
+    fn utxo_tx(key: &PrivateKey, inputs: Vec<TxInput>, outputs: Vec<TxOutput>) -> Transaction {
+        let mut tx = Transaction::utxo(&UtxoTransaction { inputs, outputs });
+        assert!(tx.sign(key.clone()).is_ok());
+        tx
+    }
+
+    /// A set holding one output of `amount` owned by `key`.
+    fn funded(key: &PrivateKey, amount: u64) -> (UtxoSet, TxInput) {
+        let input = TxInput { prev_tx: Hash::random(), index: 0 };
+        let mut set = UtxoSet::new();
+        set.insert(input, TxOutput { amount, owner: key.generate_public().address() });
+        (set, input)
+    }
+
+    #[test]
+    fn test_utxo_transaction_encode_decode() {
+        let t = UtxoTransaction {
+            inputs: vec![TxInput { prev_tx: Hash::random(), index: 3 }],
+            outputs: vec![TxOutput { amount: 9, owner: Address::from_bytes(&[1u8; 20]).unwrap() }; 2],
+        };
+
+        let mut bytes = t.as_bytes().unwrap();
+        assert_eq!(UtxoTransaction::from_bytes(&bytes).unwrap(), t);
+
+        bytes.push(0);
+        assert!(UtxoTransaction::from_bytes(&bytes).is_err());
+        assert!(UtxoTransaction::from_bytes(&bytes[..10]).is_err());
+    }
+
+    #[test]
+    fn test_apply_spend() {
+        let key = PrivateKey::generate_key();
+        let to = PrivateKey::generate_key().generate_public().address();
+        let (mut set, input) = funded(&key, 100);
+
+        let change = TxOutput { amount: 58, owner: key.generate_public().address() };
+        let payment = TxOutput { amount: 40, owner: to };
+        let tx = utxo_tx(&key, vec![input], vec![payment, change]);
+        assert!(set.apply(&tx).is_ok());
+
+        let prev_tx = TxHasher::new().hash(&tx).unwrap();
+        assert_eq!(set.get(&input), None);
+        assert_eq!(set.get(&TxInput { prev_tx, index: 0 }), Some(&payment));
+        assert_eq!(set.get(&TxInput { prev_tx, index: 1 }), Some(&change));
+        assert_eq!(set.balance(&to), 40);
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_apply_double_spend() {
+        let key = PrivateKey::generate_key();
+        let owner = key.generate_public().address();
+        let (mut set, input) = funded(&key, 100);
+
+        let spend = |amount| utxo_tx(&key, vec![input], vec![TxOutput { amount, owner }]);
+        assert!(set.apply(&spend(100)).is_ok());
+        assert_eq!(set.apply(&spend(99)), Err(UtxoError::UnknownInput(input)));
+
+        let (mut set, input) = funded(&key, 100);
+        let twice = utxo_tx(&key, vec![input, input], vec![TxOutput { amount: 200, owner }]);
+        assert_eq!(set.apply(&twice), Err(UtxoError::DuplicateInput(input)));
+        assert_eq!(set.balance(&owner), 100);
+    }
+
+    #[test]
+    fn test_apply_overspend() {
+        let key = PrivateKey::generate_key();
+        let owner = key.generate_public().address();
+        let (mut set, input) = funded(&key, 100);
+
+        let tx = utxo_tx(&key, vec![input], vec![TxOutput { amount: 60, owner }, TxOutput { amount: 41, owner }]);
+        assert_eq!(set.apply(&tx), Err(UtxoError::Overspend { inputs: 100, outputs: 101 }));
+        assert_eq!(set.get(&input).map(|o| o.amount), Some(100));
+    }
+
+    #[test]
+    fn test_apply_rejects_wrong_owner() {
+        let (mut set, input) = funded(&PrivateKey::generate_key(), 100);
+        let thief = PrivateKey::generate_key();
+
+        let tx = utxo_tx(&thief, vec![input], vec![TxOutput { amount: 100, owner: thief.generate_public().address() }]);
+        assert_eq!(set.apply(&tx), Err(UtxoError::WrongOwner(input)));
+    }
+
+    #[test]
+    fn test_apply_rejects_untagged_payload() {
+        let key = PrivateKey::generate_key();
+        let (mut set, input) = funded(&key, 100);
+
+        let utxo = UtxoTransaction { inputs: vec![input], outputs: vec![] };
+        let mut tx = Transaction { data: utxo.as_bytes().unwrap(), ..Transaction::raw(&[]) };
+        assert!(tx.sign(key).is_ok());
+        assert!(matches!(set.apply(&tx), Err(UtxoError::Malformed(_))));
+        assert_eq!(set.get(&input).map(|o| o.amount), Some(100));
+    }
+}
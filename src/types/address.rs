@@ -1,17 +1,22 @@
+use std::{fmt, io::{self, Read, Write}, str::FromStr};
 
 
-#[derive(Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct Address([u8; 20]);
 
 impl Address {
-    pub fn to_string(&self) -> String {
-        hex::encode(self.0)
+    /// The all-zero address. No key hashes to it, so it is used as the
+    /// sender of coinbase transfers.
+    pub const fn zero() -> Self {
+        Address([0u8; 20])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0u8; 20]
     }
 
     pub fn from_bytes(b: &[u8]) -> Result<Self, String> {
-        println!("conv part");
         if b.len() != 20 {
-            println!("wrong len");
             return Err(format!("given bytes with length {} should be 20", b.len()));
         }
 
@@ -20,5 +25,95 @@ impl Address {
 
         Ok(Address(value))
     }
-    
-}
\ No newline at end of file
+
+    pub fn to_vec(self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+
+    pub fn encode_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.0)
+    }
+
+    pub fn decode_binary<R: Read>(reader: &mut R) -> io::Result<Address> {
+        let mut value = [0u8; 20];
+        reader.read_exact(&mut value)?;
+        Ok(Address(value))
+    }
+
+}
+
+/// Serialized as the lowercase hex string.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Address {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Address {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// Lowercase hex, 40 characters.
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum AddressParseError {
+    /// The input is not 40 characters long.
+    InvalidLength(usize),
+    InvalidHex(hex::FromHexError),
+}
+
+impl fmt::Display for AddressParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressParseError::InvalidLength(len) => write!(f, "expected 40 hex characters, got {}", len),
+            AddressParseError::InvalidHex(e) => write!(f, "invalid hex: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AddressParseError {}
+
+impl FromStr for Address {
+    type Err = AddressParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 40 {
+            return Err(AddressParseError::InvalidLength(s.len()));
+        }
+
+        let mut value = [0u8; 20];
+        hex::decode_to_slice(s, &mut value).map_err(AddressParseError::InvalidHex)?;
+        Ok(Address(value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Address, AddressParseError};
+
+    #[test]
+    fn test_address_parse() {
+        let a = Address::from_bytes(&[7u8; 20]).unwrap();
+        assert_eq!(a.to_string(), "07".repeat(20));
+        assert_eq!(a.to_string().parse(), Ok(a));
+
+        assert_eq!("07".parse::<Address>(), Err(AddressParseError::InvalidLength(2)));
+        assert!(matches!("zz".repeat(20).parse::<Address>(), Err(AddressParseError::InvalidHex(_))));
+    }
+}
@@ -1,47 +1,55 @@
-use byteorder::{WriteBytesExt, ReadBytesExt};
-use rand::{thread_rng, Rng};
-use std::{fmt, io};
+use rand::{rngs::OsRng, RngCore};
+use std::{fmt, io, str::FromStr};
 use std::io::{Write, Read};
-use std::iter::repeat;
 
-#[derive(Clone, Copy, Eq, PartialEq, Hash)]
+/// Ordered bytewise, i.e. as a big-endian number.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct Hash([u8; 32]);
 
 impl Hash {
-    pub fn default() -> Hash {
-        let mut hash = [0u8; 32];
-        for byte in &mut hash {
-            *byte = 0;
-        }
-        Hash(hash)
+    /// The all-zero hash, used as the parent of genesis and the Merkle root
+    /// of an empty block.
+    pub const fn zero() -> Hash {
+        Hash([0u8; 32])
     }
 
+    /// Writes the 32 bytes as they are, with no length prefix.
     pub fn encode_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
-        for byte in self.0 {
-            writer.write_u8(byte)?
-        }
-        Ok(())
+        writer.write_all(&self.0)
     }
 
+    /// Reads exactly 32 bytes, failing with `UnexpectedEof` on fewer.
     pub fn decode_binary<R: Read>(reader: &mut R) -> io::Result<Hash> {
         let mut hash = [0u8; 32];
-        for byte in &mut hash {
-            *byte = reader.read_u8()?;
-        }
+        reader.read_exact(&mut hash)?;
         Ok(Hash(hash))
     }
 
+    /// True only if all 32 bytes are zero.
     pub fn is_zero(&self) -> bool {
         self.0.iter().all(|&byte| byte == 0)
     }
 
-    pub fn to_vec(&self) -> Vec<u8> {
+    /// Number of leading zero bits, counting from the first byte.
+    pub fn leading_zero_bits(&self) -> u32 {
+        let mut bits = 0;
+        for byte in self.0 {
+            if byte != 0 {
+                return bits + byte.leading_zeros();
+            }
+            bits += 8;
+        }
+        bits
+    }
+
+    pub fn to_vec(self) -> Vec<u8> {
         self.0.to_vec()
     }
 
-    pub fn from_bytes(b: &[u8]) -> Result<Self, String> {
+    /// Copies exactly 32 bytes; any other length is an error.
+    pub fn from_bytes(b: &[u8]) -> Result<Self, HashError> {
         if b.len() != 32 {
-            return Err(format!("given bytes with length {} should be 32", b.len()));
+            return Err(HashError::InvalidLength(b.len()));
         }
 
         let mut value = [0u8; 32];
@@ -50,29 +58,164 @@ impl Hash {
         Ok(Hash(value))
     }
 
-    pub fn to_string(&self) -> String {
-        hex::encode(self.0)
+    /// 32 bytes from the operating system's CSPRNG. Meant for tests and
+    /// nonces; a random value is never the hash of anything.
+    pub fn random() -> Self {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        Hash(bytes)
     }
 
-    pub fn random() -> Self {
-        let mut rng = thread_rng();
-        let bytes = repeat(())
-            .map(|()| rng.gen::<u8>())
-            .take(32)
-            .collect::<Vec<u8>>();
-        Hash::from_bytes(&bytes).unwrap()
+}
+
+impl Default for Hash {
+    fn default() -> Self {
+        Hash::zero()
     }
+}
 
+/// Serialized as the lowercase hex string.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Hash {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Hash {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
 }
 
 impl fmt::Debug for Hash {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.to_string())
+        write!(f, "{}", self)
     }
 }
 
+/// Lowercase hex, 64 characters.
 impl fmt::Display for Hash {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.to_string())
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum HashError {
+    /// The input is not 32 bytes long.
+    InvalidLength(usize),
+}
+
+impl fmt::Display for HashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashError::InvalidLength(len) => write!(f, "expected 32 bytes, got {}", len),
+        }
+    }
+}
+
+impl std::error::Error for HashError {}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum HashParseError {
+    /// The input is not 64 characters long.
+    InvalidLength(usize),
+    InvalidHex(hex::FromHexError),
+}
+
+impl fmt::Display for HashParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashParseError::InvalidLength(len) => write!(f, "expected 64 hex characters, got {}", len),
+            HashParseError::InvalidHex(e) => write!(f, "invalid hex: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for HashParseError {}
+
+impl FromStr for Hash {
+    type Err = HashParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 64 {
+            return Err(HashParseError::InvalidLength(s.len()));
+        }
+
+        let mut value = [0u8; 32];
+        hex::decode_to_slice(s, &mut value).map_err(HashParseError::InvalidHex)?;
+        Ok(Hash(value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Hash, HashError, HashParseError};
+
+    #[test]
+    fn test_hash_string_round_trip() {
+        let h = Hash::random();
+        let s = h.to_string();
+
+        assert_eq!(s.len(), 64);
+        assert_eq!(s, s.to_lowercase());
+        assert_eq!(s.parse::<Hash>(), Ok(h));
+    }
+
+    #[test]
+    fn test_leading_zero_bits() {
+        assert_eq!(Hash::zero().leading_zero_bits(), 256);
+
+        let mut bytes = [0xffu8; 32];
+        assert_eq!(Hash(bytes).leading_zero_bits(), 0);
+
+        bytes[0] = 0;
+        bytes[1] = 0x10;
+        assert_eq!(Hash(bytes).leading_zero_bits(), 11);
+    }
+
+    #[test]
+    fn test_hash_from_bytes() {
+        let bytes: Vec<u8> = (0..32).collect();
+        assert_eq!(Hash::from_bytes(&bytes).unwrap().to_vec(), bytes);
+
+        assert_eq!(Hash::from_bytes(&bytes[..16]), Err(HashError::InvalidLength(16)));
+        assert_eq!(Hash::from_bytes(&[0u8; 40]), Err(HashError::InvalidLength(40)));
+        assert_eq!(Hash::from_bytes(&[]), Err(HashError::InvalidLength(0)));
+    }
+
+    #[test]
+    fn test_zero_hash() {
+        assert!(Hash::zero().is_zero());
+        assert_eq!(Hash::default(), Hash::zero());
+
+        let mut bytes = [0u8; 32];
+        bytes[31] = 1;
+        assert!(!Hash(bytes).is_zero());
+
+        assert!((0..1000).all(|_| !Hash::random().is_zero()));
+    }
+
+    #[test]
+    fn test_random_hash() {
+        assert_ne!(Hash::random(), Hash::random());
+
+        // Over 64 draws, a byte that stays fixed would show up as having a
+        // single value; a random one essentially never does.
+        let draws: Vec<Hash> = (0..64).map(|_| Hash::random()).collect();
+        for i in 0..32 {
+            assert!(draws.iter().any(|h| h.0[i] != draws[0].0[i]), "byte {} never varies", i);
+        }
+    }
+
+    #[test]
+    fn test_hash_parse_rejects_bad_input() {
+        assert_eq!("abcd".parse::<Hash>(), Err(HashParseError::InvalidLength(4)));
+
+        let not_hex = "z".repeat(64);
+        assert!(matches!(not_hex.parse::<Hash>(), Err(HashParseError::InvalidHex(_))));
     }
 }
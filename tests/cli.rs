@@ -0,0 +1,62 @@
+#![cfg(feature = "native")]
+
+use std::{fs, path::PathBuf, process::{Command, Output}};
+
+fn blockchain(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_blockchain")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+/// The value printed after `name:` in `out`.
+fn field<'a>(out: &'a str, name: &str) -> &'a str {
+    out.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+        .unwrap_or_else(|| panic!("no {} in {}", name, out))
+        .trim()
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("blockchain-cli-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_genkey_mkblock_inspect() {
+    let dir = temp_dir("round-trip");
+    let key = dir.join("key");
+    let block = dir.join("block");
+    let prev = "22".repeat(32);
+
+    let genkey = stdout(&blockchain(&["genkey", key.to_str().unwrap()]));
+    let address = field(&genkey, "address");
+
+    let args = ["mkblock", "--key", key.to_str().unwrap(), "--prev", &prev, "--height", "7", "--out", block.to_str().unwrap()];
+    let mkblock = stdout(&blockchain(&args));
+
+    let inspect = stdout(&blockchain(&["inspect", block.to_str().unwrap()]));
+    assert_eq!(field(&inspect, "hash"), field(&mkblock, "hash"));
+    assert_eq!(field(&inspect, "height"), "7");
+    assert_eq!(field(&inspect, "prev_block"), prev);
+    assert_eq!(field(&inspect, "validator"), address);
+    assert_eq!(field(&inspect, "signature"), "valid");
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_inspect_rejects_garbage() {
+    let dir = temp_dir("garbage");
+    let block = dir.join("block");
+    fs::write(&block, b"not a block").unwrap();
+
+    let output = blockchain(&["inspect", block.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("could not decode"));
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,27 @@
+//! The library core with no features enabled. `cargo test
+//! --no-default-features` builds and runs this against the minimal crate,
+//! the one a wasm embedder gets, so anything in the core that starts to
+//! need `native` fails here first.
+
+use blockchain::{core::{block::Block, blockchain::Blockchain, encoding::{BlockDecoder, BlockEncoder, Decoder, Encoder}, storage::MemoryStorage}, crypto::keypair::PrivateKey};
+
+#[test]
+fn test_chain_without_features() {
+    let mut bc = Blockchain::new(Block::genesis(), MemoryStorage::new()).unwrap();
+    for _ in 0..3 {
+        let tip = bc.get_header(bc.height()).unwrap();
+        let mut b = Block::random_block(bc.height() + 1);
+        b.header.prev_block = bc.last_hash();
+        b.header.timestamp = tip.timestamp + 1;
+        b.sign(PrivateKey::generate_key()).unwrap();
+        bc.add_block(b).unwrap();
+    }
+    assert_eq!(bc.height(), 3);
+    assert!(bc.validate_chain().is_ok());
+
+    let b = bc.get_block(3).unwrap().unwrap();
+    let mut buf = Vec::new();
+    BlockEncoder::new().encode(&mut buf, &b).unwrap();
+    let decoded = BlockDecoder::new().decode(&mut buf.as_slice()).unwrap();
+    assert_eq!(decoded.header.hash(), b.header.hash());
+}